@@ -0,0 +1,203 @@
+use std::io::{self, BufRead, Write};
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use tokio::io::{AsyncBufRead, AsyncRead, AsyncWrite, ReadBuf};
+#[cfg(feature = "tokio-util")]
+use tokio_util::codec::{Decoder, Encoder, Framed, FramedRead, FramedWrite};
+use {LocalPipeBufWriter, LocalPipeReader, LocalPipeStream, LocalPipeWriter};
+
+impl AsyncRead for LocalPipeReader {
+    fn poll_read(self: Pin<&mut Self>, cx: &mut Context, buf: &mut ReadBuf) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        match LocalPipeReader::poll_read(this, cx, buf.initialize_unfilled()) {
+            Poll::Ready(Ok(len)) => {
+                buf.advance(len);
+                Poll::Ready(Ok(()))
+            },
+            Poll::Ready(Err(e)) => Poll::Ready(Err(e)),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+/// Reads straight out of the pipe's chunks, so wrapping the reader in `FramedRead` or another
+/// buffered adapter doesn't copy the data twice.
+impl AsyncBufRead for LocalPipeReader {
+    fn poll_fill_buf(self: Pin<&mut Self>, cx: &mut Context) -> Poll<io::Result<&[u8]>> {
+        let this = self.get_mut();
+        match this.fill_buf() {
+            Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {
+                this.register(cx);
+                return Poll::Pending
+            },
+            Err(e) => return Poll::Ready(Err(e)),
+            Ok(..) => (),
+        }
+
+        Poll::Ready(this.fill_buf())
+    }
+
+    fn consume(self: Pin<&mut Self>, amt: usize) {
+        BufRead::consume(self.get_mut(), amt)
+    }
+}
+
+/// Dropping the writer is what signals EOF to the reader, so `poll_shutdown()` does nothing.
+impl AsyncWrite for LocalPipeWriter {
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context, buf: &[u8]) -> Poll<io::Result<usize>> {
+        LocalPipeWriter::poll_write(self.get_mut(), cx, buf)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context) -> Poll<io::Result<()>> {
+        Poll::Ready(self.get_mut().flush())
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, _cx: &mut Context) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+}
+
+/// Shutting down flushes the buffer, after which the writer should be dropped to signal EOF.
+impl AsyncWrite for LocalPipeBufWriter {
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context, buf: &[u8]) -> Poll<io::Result<usize>> {
+        LocalPipeBufWriter::poll_write(self.get_mut(), cx, buf)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context) -> Poll<io::Result<()>> {
+        LocalPipeBufWriter::poll_flush(self.get_mut(), cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context) -> Poll<io::Result<()>> {
+        LocalPipeBufWriter::poll_flush(self.get_mut(), cx)
+    }
+}
+
+impl AsyncRead for LocalPipeStream {
+    fn poll_read(self: Pin<&mut Self>, cx: &mut Context, buf: &mut ReadBuf) -> Poll<io::Result<()>> {
+        Pin::new(self.get_mut().reader_mut()).poll_read(cx, buf)
+    }
+}
+
+impl AsyncBufRead for LocalPipeStream {
+    fn poll_fill_buf(self: Pin<&mut Self>, cx: &mut Context) -> Poll<io::Result<&[u8]>> {
+        Pin::new(self.get_mut().reader_mut()).poll_fill_buf(cx)
+    }
+
+    fn consume(self: Pin<&mut Self>, amt: usize) {
+        Pin::new(self.get_mut().reader_mut()).consume(amt)
+    }
+}
+
+impl AsyncWrite for LocalPipeStream {
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context, buf: &[u8]) -> Poll<io::Result<usize>> {
+        Pin::new(self.get_mut().writer_mut()).poll_write(cx, buf)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context) -> Poll<io::Result<()>> {
+        Pin::new(self.get_mut().writer_mut()).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context) -> Poll<io::Result<()>> {
+        Pin::new(self.get_mut().writer_mut()).poll_shutdown(cx)
+    }
+}
+
+#[cfg(feature = "tokio-util")]
+impl LocalPipeReader {
+    /// Wraps the reader in a `FramedRead` that decodes frames with `codec`
+    #[cfg_attr(feature = "unstable-doc-cfg", doc(cfg(feature = "tokio-util")))]
+    pub fn into_framed_read<C: Decoder>(self, codec: C) -> FramedRead<Self, C> {
+        FramedRead::new(self, codec)
+    }
+}
+
+#[cfg(feature = "tokio-util")]
+impl LocalPipeWriter {
+    /// Wraps the writer in a `FramedWrite` that encodes frames with `codec`
+    #[cfg_attr(feature = "unstable-doc-cfg", doc(cfg(feature = "tokio-util")))]
+    pub fn into_framed_write<I, C: Encoder<I>>(self, codec: C) -> FramedWrite<Self, C> {
+        FramedWrite::new(self, codec)
+    }
+}
+
+#[cfg(feature = "tokio-util")]
+impl LocalPipeStream {
+    /// Wraps the stream in a `Framed` that both encodes and decodes frames with `codec`
+    ///
+    /// # Example
+    ///
+    /// A codec for frames prefixed by their length as a single byte:
+    ///
+    /// ```
+    /// # extern crate bytes;
+    /// # extern crate futures;
+    /// # extern crate pipe;
+    /// # extern crate tokio_util;
+    /// use bytes::{Buf, BufMut, BytesMut};
+    /// use futures::{executor::block_on, SinkExt, StreamExt};
+    /// use std::io;
+    /// use tokio_util::codec::{Decoder, Encoder};
+    ///
+    /// struct ShortFrames;
+    ///
+    /// impl Decoder for ShortFrames {
+    ///     type Item = Vec<u8>;
+    ///     type Error = io::Error;
+    ///
+    ///     fn decode(&mut self, src: &mut BytesMut) -> io::Result<Option<Vec<u8>>> {
+    ///         match src.first() {
+    ///             Some(&len) if src.len() > len as usize => {
+    ///                 src.advance(1);
+    ///                 Ok(Some(src.split_to(len as usize).to_vec()))
+    ///             },
+    ///             _ => Ok(None),
+    ///         }
+    ///     }
+    /// }
+    ///
+    /// impl<'a> Encoder<&'a [u8]> for ShortFrames {
+    ///     type Error = io::Error;
+    ///
+    ///     fn encode(&mut self, frame: &'a [u8], dst: &mut BytesMut) -> io::Result<()> {
+    ///         dst.put_u8(frame.len() as u8);
+    ///         dst.put_slice(frame);
+    ///         Ok(())
+    ///     }
+    /// }
+    ///
+    /// let (a, b) = pipe::pipe_local_duplex(64);
+    /// let (mut a, mut b) = (a.into_framed(ShortFrames), b.into_framed(ShortFrames));
+    /// block_on(a.send(&b"ping"[..])).unwrap();
+    /// assert_eq!(block_on(b.next()).unwrap().unwrap(), b"ping");
+    /// block_on(b.send(&b"pong"[..])).unwrap();
+    /// assert_eq!(block_on(a.next()).unwrap().unwrap(), b"pong");
+    /// ```
+    #[cfg_attr(feature = "unstable-doc-cfg", doc(cfg(feature = "tokio-util")))]
+    pub fn into_framed<C>(self, codec: C) -> Framed<Self, C> {
+        Framed::new(self, codec)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::executor::block_on;
+    use futures::future::poll_fn;
+    use tokio::io::{AsyncBufRead, AsyncRead, AsyncWrite, ReadBuf};
+    use std::pin::Pin;
+    use pipe_local;
+
+    #[test]
+    fn async_read_write() {
+        let (mut r, mut w) = pipe_local(4);
+        let len = block_on(poll_fn(|cx| Pin::new(&mut w).poll_write(cx, b"hello"))).unwrap();
+        assert_eq!(len, 4);
+        let data = block_on(poll_fn(|cx| Pin::new(&mut r).poll_fill_buf(cx).map_ok(|data| data.to_vec()))).unwrap();
+        assert_eq!(data, b"hell");
+        Pin::new(&mut r).consume(2);
+
+        let mut buf = [0; 8];
+        let mut buf = ReadBuf::new(&mut buf);
+        block_on(poll_fn(|cx| Pin::new(&mut r).poll_read(cx, &mut buf))).unwrap();
+        assert_eq!(buf.filled(), b"ll");
+    }
+}
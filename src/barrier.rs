@@ -0,0 +1,132 @@
+use std::sync::{Mutex, MutexGuard};
+use {Chunk, PipeError, PipeWriter};
+
+struct Inner {
+    writers: Vec<PipeWriter>,
+    epoch: u64,
+}
+
+/// Inserts epoch markers into several pipes at once, for cutting parallel streams at consistent
+/// points
+///
+/// Each call to `insert()` starts a new epoch and sends a marker for it into every attached pipe.
+/// The reader of each pipe passes over the marker like any other chunk, noting the epoch and
+/// where in the stream it was (see `PipeReader::barrier()` and `PipeReader::barrier_position()`).
+/// Data whose write completed before `insert()` was called comes before the marker in every
+/// pipe, and data written after it returned comes after.
+///
+/// The barrier holds a writer of each attached pipe, so the pipe's reader only sees EOF once the
+/// barrier has been dropped too. Since the markers are sent one pipe after another, the readers
+/// need to be reading concurrently for `insert()` to return.
+///
+/// # Example
+///
+/// ```
+/// use std::io::{Read, Write};
+/// use std::thread::spawn;
+///
+/// let (mut reader, mut writer) = pipe::pipe();
+/// let barrier = pipe::PipeBarrier::new();
+/// barrier.attach(&writer);
+/// let thread = spawn(move || {
+///     let mut data = Vec::new();
+///     reader.read_to_end(&mut data).map(|_| (data, reader.barrier_position()))
+/// });
+///
+/// writer.write_all(b"before").unwrap();
+/// assert_eq!(barrier.insert(), 1);
+/// writer.write_all(b"after").unwrap();
+/// drop((writer, barrier));
+/// assert_eq!(thread.join().unwrap().unwrap(), (b"beforeafter".to_vec(), 6));
+/// ```
+pub struct PipeBarrier {
+    inner: Mutex<Inner>,
+}
+
+impl PipeBarrier {
+    /// Creates a barrier without any pipes attached
+    pub fn new() -> Self {
+        PipeBarrier { inner: Mutex::new(Inner { writers: Vec::new(), epoch: 0 }) }
+    }
+
+    /// Attaches the pipe that `writer` writes to
+    pub fn attach(&self, writer: &PipeWriter) {
+        self.lock().writers.push(writer.clone());
+    }
+
+    /// Returns the number of pipes attached to the barrier
+    pub fn len(&self) -> usize {
+        self.lock().writers.len()
+    }
+
+    /// Returns `true` if no pipes are attached to the barrier
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns the epoch of the last marker inserted, or `0`
+    pub fn epoch(&self) -> u64 {
+        self.lock().epoch
+    }
+
+    /// Sends a marker for a new epoch into every attached pipe, blocking until each reader has
+    /// received it, and returns the epoch
+    ///
+    /// Concurrent calls insert their markers in the same order into every pipe. Pipes whose
+    /// reader has been dropped are detached rather than failing the barrier.
+    pub fn insert(&self) -> u64 {
+        let mut inner = self.lock();
+        inner.epoch += 1;
+        let epoch = inner.epoch;
+        inner.writers.retain(|writer| {
+            !matches!(writer.send_chunk(Chunk::new().with_barrier(epoch)), Err(PipeError::Disconnected))
+        });
+        epoch
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Inner> {
+        self.inner.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl Default for PipeBarrier {
+    fn default() -> Self {
+        PipeBarrier::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Read, Write};
+    use std::thread::spawn;
+    use super::*;
+    use pipe;
+
+    #[test]
+    fn barrier() {
+        let barrier = PipeBarrier::new();
+        let mut writers = Vec::new();
+        let mut threads = Vec::new();
+        for _ in 0..2 {
+            let (mut reader, writer) = pipe();
+            barrier.attach(&writer);
+            writers.push(writer);
+            threads.push(spawn(move || {
+                let mut data = Vec::new();
+                reader.read_to_end(&mut data).unwrap();
+                (data, reader.barrier(), reader.barrier_position())
+            }));
+        }
+
+        writers[0].write_all(b"a").unwrap();
+        writers[1].write_all(b"bb").unwrap();
+        assert_eq!(barrier.insert(), 1);
+        writers[0].write_all(b"c").unwrap();
+        assert_eq!(barrier.insert(), 2);
+        writers[1].write_all(b"d").unwrap();
+        drop((writers, barrier));
+
+        let results = threads.into_iter().map(|thread| thread.join().unwrap()).collect::<Vec<_>>();
+        assert_eq!(results, [(b"ac".to_vec(), 2, 2), (b"bbd".to_vec(), 2, 2)]);
+    }
+}
@@ -0,0 +1,108 @@
+//! A throughput harness for measuring pipe configurations against a workload
+//!
+//! This is the harness behind the crate's own benchmarks, so that the effect of capacities and
+//! buffering can be measured for a particular workload before settling on them.
+//!
+//! # Example
+//!
+//! ```
+//! use pipe::bench::{transfer, Workload};
+//!
+//! let workload = Workload { total: 64 * 1024, write_size: 512, read_size: 4096 };
+//! let unbuffered = transfer(pipe::pipe(), &workload).unwrap();
+//! let buffered = transfer(pipe::pipe_buffered(), &workload).unwrap();
+//! assert_eq!(unbuffered.bytes, buffered.bytes);
+//! println!("{} vs {} bytes/s", unbuffered.throughput(), buffered.throughput());
+//! ```
+
+use std::io::{self, Read, Write};
+use std::thread::spawn;
+use std::time::{Duration, Instant};
+
+/// How much data to send through a pipe, and in what sizes
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Workload {
+    /// Total number of bytes to send
+    pub total: usize,
+    /// Number of bytes passed to each write
+    pub write_size: usize,
+    /// Size of the buffer passed to each read
+    pub read_size: usize,
+}
+
+impl Default for Workload {
+    /// 1 MiB in writes and reads of 8 KiB
+    fn default() -> Self {
+        Workload { total: 1024 * 1024, write_size: 8 * 1024, read_size: 8 * 1024 }
+    }
+}
+
+/// The result of running a `Workload` (see `transfer()`)
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Measurement {
+    /// Number of bytes that were read
+    pub bytes: u64,
+    /// Time from the first write until the reader reached EOF
+    pub elapsed: Duration,
+}
+
+impl Measurement {
+    /// Returns the number of bytes transferred per second
+    pub fn throughput(&self) -> f64 {
+        self.bytes as f64 / self.elapsed.as_secs_f64()
+    }
+}
+
+/// Sends `workload` from `writer` to `reader`, reading on a thread of its own, and measures how
+/// long it takes
+///
+/// The writer is flushed and dropped once everything has been written, so any reader that sees
+/// EOF once its writers are gone will do, including ones that aren't pipes at all.
+pub fn transfer<R, W>((reader, writer): (R, W), workload: &Workload) -> io::Result<Measurement> where
+    R: Read + Send + 'static,
+    W: Write,
+{
+    let mut reader = reader;
+    let mut writer = writer;
+    let data: Vec<u8> = (0..workload.write_size).map(|i| i as u8).collect();
+    let read_size = workload.read_size;
+
+    let start = Instant::now();
+    let thread = spawn(move || -> io::Result<u64> {
+        let mut buf = vec![0; read_size];
+        let mut bytes = 0;
+        loop {
+            match reader.read(&mut buf) {
+                Ok(0) => return Ok(bytes),
+                Ok(len) => bytes += len as u64,
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => (),
+                Err(e) => return Err(e),
+            }
+        }
+    });
+
+    let mut remaining = workload.total;
+    while remaining > 0 {
+        let len = remaining.min(data.len());
+        writer.write_all(&data[..len])?;
+        remaining -= len;
+    }
+    writer.flush()?;
+    drop(writer);
+
+    let bytes = thread.join().unwrap_or_else(|e| ::std::panic::resume_unwind(e))?;
+    Ok(Measurement { bytes, elapsed: start.elapsed() })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pipe_buffered;
+
+    #[test]
+    fn transfer_all() {
+        let workload = Workload { total: 10_000, write_size: 300, read_size: 64 };
+        let measurement = transfer(pipe_buffered(), &workload).unwrap();
+        assert_eq!(measurement.bytes, 10_000);
+    }
+}
@@ -0,0 +1,178 @@
+use bytes::Bytes;
+use crossbeam_channel::{Sender, SendError, TryRecvError, TrySendError};
+use http_body::{Body, Frame};
+use std::fmt;
+use std::io;
+use std::pin::Pin;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::task::{Context, Poll, Waker};
+use {pipe, PipeReader, PipeWriter};
+
+/// Wakes an async reader of a pipe whenever a writer starts waiting for it
+///
+/// Writers count themselves before waking the task and blocking, and the reader checks the count
+/// after registering its waker, so a wakeup can't slip in between the two.
+pub(crate) struct Notify {
+    waker: Mutex<Option<Waker>>,
+    waiting: AtomicUsize,
+}
+
+impl Notify {
+    pub(crate) fn new() -> Self {
+        Notify { waker: Mutex::new(None), waiting: AtomicUsize::new(0) }
+    }
+
+    pub(crate) fn wake(&self) {
+        let waker = self.waker.lock().unwrap_or_else(|e| e.into_inner()).take();
+        if let Some(waker) = waker {
+            waker.wake();
+        }
+    }
+
+    fn register(&self, waker: &Waker) {
+        *self.waker.lock().unwrap_or_else(|e| e.into_inner()) = Some(waker.clone());
+    }
+
+    fn waiting(&self) -> bool {
+        self.waiting.load(Ordering::SeqCst) > 0
+    }
+
+    pub(crate) fn send<T>(&self, sender: &Sender<T>, data: T, send: impl FnOnce(T) -> Result<(), SendError<T>>) -> Result<(), SendError<T>> {
+        let data = match sender.try_send(data) {
+            Ok(()) => return Ok(()),
+            Err(TrySendError::Disconnected(data)) => return Err(SendError(data)),
+            Err(TrySendError::Full(data)) => data,
+        };
+
+        self.waiting.fetch_add(1, Ordering::SeqCst);
+        self.wake();
+        let res = send(data);
+        self.waiting.fetch_sub(1, Ordering::SeqCst);
+        res
+    }
+}
+
+/// A `http_body::Body` streaming the contents of a pipe (see `PipeReader::into_http_body()`)
+///
+/// Each chunk sent by a writer becomes a data frame of the body, and the body ends once all
+/// writers have been dropped. Polling the body never blocks, so the writers are free to run on
+/// ordinary threads while the body is served by an async runtime such as `hyper`.
+pub struct PipeBody {
+    reader: PipeReader,
+}
+
+/// Creates a pipe whose reading end is a `http_body::Body`
+#[cfg_attr(feature = "unstable-doc-cfg", doc(cfg(feature = "http-body")))]
+pub fn pipe_body() -> (PipeBody, PipeWriter) {
+    let (reader, writer) = pipe();
+    (reader.into_http_body(), writer)
+}
+
+impl PipeReader {
+    /// Converts the reader into a `http_body::Body`, for streaming a request or response body
+    /// produced by a blocking writer
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # extern crate pipe;
+    /// use std::io::Write;
+    /// use std::thread::spawn;
+    ///
+    /// let (reader, mut writer) = pipe::pipe();
+    /// let body = reader.into_http_body();
+    /// spawn(move || writer.write_all(b"hello, world"));
+    /// # drop(body);
+    /// // hyper::Response::new(body)
+    /// ```
+    #[cfg_attr(feature = "unstable-doc-cfg", doc(cfg(feature = "http-body")))]
+    pub fn into_http_body(self) -> PipeBody {
+        PipeBody { reader: self }
+    }
+}
+
+impl PipeBody {
+    /// Converts the body back into the reader it was created from
+    pub fn into_inner(self) -> PipeReader {
+        self.reader
+    }
+
+    fn poll_chunk(&mut self, cx: &Context) -> Poll<Option<io::Result<Bytes>>> {
+        let shared = self.reader.shared();
+        if shared.gate().is_paused() {
+            shared.notify().register(cx.waker());
+            if shared.gate().is_paused() {
+                return Poll::Pending
+            }
+        }
+
+        let mut chunk = self.reader.take_buffer();
+        if chunk.is_empty() {
+            chunk = match self.reader.receiver().try_recv() {
+                Ok(chunk) => chunk,
+                Err(TryRecvError::Disconnected) => return Poll::Ready(None),
+                Err(TryRecvError::Empty) => {
+                    let notify = self.reader.shared().notify();
+                    notify.register(cx.waker());
+                    // a writer may have counted itself before the waker was there to take, or
+                    // the last writer may be halfway through being dropped
+                    if notify.waiting() || self.reader.shared().writers() == 0 {
+                        cx.waker().wake_by_ref();
+                    }
+                    return Poll::Pending
+                },
+            };
+            if let Err(e) = self.reader.received(&mut chunk) {
+                return Poll::Ready(Some(Err(e)))
+            }
+        }
+
+        chunk.acknowledge();
+        Poll::Ready(Some(Ok(Bytes::from(chunk.into_vec()))))
+    }
+}
+
+impl Body for PipeBody {
+    type Data = Bytes;
+    type Error = io::Error;
+
+    fn poll_frame(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<io::Result<Frame<Bytes>>>> {
+        self.get_mut().poll_chunk(cx).map(|data| data.map(|data| data.map(Frame::data)))
+    }
+}
+
+impl fmt::Debug for PipeBody {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_tuple("PipeBody")
+            .field(&self.reader)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::executor::block_on;
+    use futures::future::poll_fn;
+    use http_body::Body;
+    use std::io::Write;
+    use std::pin::Pin;
+    use std::thread::spawn;
+    use super::*;
+
+    #[test]
+    fn body() {
+        let (mut body, mut writer) = pipe_body();
+        let thread = spawn(move || {
+            writer.write_all(b"hello").unwrap();
+            writer.write_all(b"world").unwrap();
+        });
+
+        let mut data = Vec::new();
+        while let Some(frame) = block_on(poll_fn(|cx| Pin::new(&mut body).poll_frame(cx))) {
+            data.extend_from_slice(&frame.unwrap().into_data().unwrap());
+        }
+        assert_eq!(data, b"helloworld");
+        thread.join().unwrap();
+    }
+}
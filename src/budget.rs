@@ -0,0 +1,91 @@
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+
+/// What a writer does when its `MemoryBudget` is exhausted
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum BudgetPolicy {
+    /// Block the writer until readers free up enough memory
+    Block,
+    /// Spool the data to disk instead of keeping it in memory
+    Spill,
+}
+
+struct Inner {
+    limit: usize,
+    policy: BudgetPolicy,
+    used: Mutex<usize>,
+    freed: Condvar,
+}
+
+/// A limit on the combined number of bytes queued in memory by a group of pipes.
+///
+/// Clones of a budget refer to the same pool, so one budget can be attached to any number of
+/// pipes (see `pipe_spooled_with_budget()`).
+#[derive(Clone)]
+pub struct MemoryBudget {
+    inner: Arc<Inner>,
+}
+
+impl MemoryBudget {
+    /// Creates a budget allowing up to `limit` bytes to be queued across all attached pipes
+    pub fn new(limit: usize, policy: BudgetPolicy) -> Self {
+        MemoryBudget {
+            inner: Arc::new(Inner {
+                limit,
+                policy,
+                used: Mutex::new(0),
+                freed: Condvar::new(),
+            }),
+        }
+    }
+
+    /// Returns the maximum number of bytes that may be queued
+    pub fn limit(&self) -> usize {
+        self.inner.limit
+    }
+
+    /// Returns the policy applied when the budget is exhausted
+    pub fn policy(&self) -> BudgetPolicy {
+        self.inner.policy
+    }
+
+    /// Returns the number of bytes currently queued across all attached pipes
+    pub fn used(&self) -> usize {
+        *self.lock()
+    }
+
+    /// Reserves `amt` bytes if they fit in the budget.
+    ///
+    /// A single reservation larger than the limit is only granted while nothing else is queued,
+    /// so that it can't stall forever.
+    pub(crate) fn try_acquire(&self, amt: usize) -> bool {
+        let mut used = self.lock();
+        self.fits(*used, amt) && {
+            *used += amt;
+            true
+        }
+    }
+
+    /// Blocks until `amt` bytes can be reserved
+    pub(crate) fn acquire(&self, amt: usize) {
+        let mut used = self.lock();
+        while !self.fits(*used, amt) {
+            used = self.inner.freed.wait(used).unwrap_or_else(|e| e.into_inner());
+        }
+        *used += amt;
+    }
+
+    pub(crate) fn release(&self, amt: usize) {
+        if amt > 0 {
+            *self.lock() -= amt;
+            self.inner.freed.notify_all();
+        }
+    }
+
+    fn fits(&self, used: usize, amt: usize) -> bool {
+        used == 0 || used + amt <= self.inner.limit
+    }
+
+    fn lock(&self) -> MutexGuard<'_, usize> {
+        self.inner.used.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
@@ -0,0 +1,3709 @@
+use crossbeam_channel::{self, Sender, Receiver, RecvTimeoutError, SendError, SendTimeoutError, TryRecvError, TrySendError};
+use std::borrow::Cow;
+use std::io::{self, BufRead, Read, Write};
+use std::cmp::min;
+use std::fmt;
+use std::mem::{replace, take};
+use std::hint::unreachable_unchecked;
+use std::panic::resume_unwind;
+use std::sync::{Arc, Mutex, Weak};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::thread::scope;
+use std::time::{Duration, Instant};
+#[cfg(all(feature = "readiness", unix))]
+use std::os::unix::io::RawFd;
+#[cfg(feature = "bidirectional")]
+use readwrite;
+use {check_consume, BufferPool, Chunk, LatencyStats, SendAllError, Clock, PipeControl, PipeError, PipeEvent, PipeEvents, Quota, SendHandle, WaitStrategy, DEFAULT_BUF_SIZE};
+use clock::ClockHandle;
+use coalesce::Coalescer;
+#[cfg(feature = "zstd")]
+use compress::Compression;
+use fair::Turnstile;
+use latency::LatencyWindow;
+use quota::QuotaState;
+use receipt::Receipt;
+use state::{PipeSnapshot, PipeState, Pressure, ReaderHandle, Shared, WriterHandle};
+
+/// The `Read` end of a pipe (see `pipe()`)
+///
+/// The reader sees EOF once every writer of the pipe has been dropped, clones included, and it
+/// has read everything they sent. A writer kept alive anywhere, even one that never writes,
+/// holds EOF back; `writer_count()` tells how many are left, and `disconnect_writers()` cuts them
+/// off without waiting for them.
+pub struct PipeReader {
+    receiver: Receiver<Chunk>,
+    buffer: Chunk,
+    position: usize,
+    block_size: Option<usize>,
+    min_read: Option<usize>,
+    min_read_timeout: Option<Duration>,
+    retain: bool,
+    retain_limit: Option<usize>,
+    pool: Option<BufferPool>,
+    wait: WaitStrategy,
+    sequence: AtomicU64,
+    sequenced: AtomicU64,
+    received: AtomicU64,
+    offset: u64,
+    barrier: AtomicU64,
+    barrier_position: AtomicU64,
+    expired: AtomicU64,
+    latency: Mutex<LatencyWindow>,
+    clock: ClockHandle,
+    handle: ReaderHandle,
+}
+
+/// The `Write` end of a pipe (see `pipe()`)
+///
+/// Chunks are normally handed over to a reader directly rather than queued, so once a write
+/// returns the data has been received by the reader, and every write is a synchronization point
+/// with the reader. That doesn't hold for a writer with a coalesce window, whose writes return
+/// once gathered into a batch (see `set_coalesce_window()`), nor for the writer feeding a
+/// `pipe_prefetch()` pipe, whose chunks are queued. `flush()` waits for a coalesced batch to be
+/// received, and is a no-op otherwise.
+///
+/// Every clone of a writer has an identity of its own (see `id()`), along with its own
+/// accounting of bytes written and its own `Quota`.
+pub struct PipeWriter {
+    /// Shared by clones, so that a `WeakPipeWriter` can upgrade for as long as any of them is alive
+    sender: Arc<Sender<Chunk>>,
+    turnstile: Option<Arc<Turnstile>>,
+    id: usize,
+    /// Shared with the writer sending coalesced batches on this one's behalf
+    written: Arc<AtomicU64>,
+    quota: Option<Arc<Mutex<QuotaState>>>,
+    checksums: bool,
+    sequenced: bool,
+    ttl: Option<Duration>,
+    timestamps: bool,
+    validator: Option<Validator>,
+    empty_writes: EmptyWrites,
+    #[cfg(feature = "zstd")]
+    compression: Option<Compression>,
+    coalescer: Option<Coalescer>,
+    clock: ClockHandle,
+    handle: WriterHandle,
+}
+
+/// A reference to the `Write` end of a pipe that doesn't keep the pipe open (see
+/// `PipeWriter::downgrade()`)
+#[derive(Clone)]
+pub struct WeakPipeWriter {
+    sender: Weak<Sender<Chunk>>,
+    turnstile: Option<Arc<Turnstile>>,
+    shared: Weak<Shared>,
+}
+
+/// The `Write` end of a pipe (see `pipe()`) that will buffer small writes before sending
+/// to the reader end.
+///
+/// `flush()` blocks until a reader has received all buffered data.
+///
+/// A write that doesn't fit into what is left of the buffer is normally split, so that the
+/// buffer is filled up before being sent. With cloned writers, another writer's data may then end
+/// up between the two halves; see `write_atomic()` and `set_atomic_writes()` to prevent that.
+///
+/// A write that leaves the buffer partially filled still sends it right away if the reader is
+/// already waiting for data. See `set_eager_sends()` to only ever send full buffers instead, or
+/// `set_nodelay()` to always send right away.
+pub struct PipeBufWriter {
+    sender: Option<Sender<Chunk>>,
+    buffer: Vec<u8>,
+    size: usize,
+    atomic: bool,
+    eager: bool,
+    eager_interval: Option<Duration>,
+    nodelay: bool,
+    checksums: bool,
+    sequenced: bool,
+    sequence: Option<u64>,
+    last_flush: Instant,
+    clock: ClockHandle,
+    pool: Option<BufferPool>,
+    drop_timeout: Option<Duration>,
+    abandon_hook: Option<AbandonHook>,
+    handle: WriterHandle,
+}
+
+/// Called with the data a `PipeBufWriter` gave up on when it was dropped (see
+/// `PipeBufWriter::set_abandon_hook()`)
+pub type AbandonHook = Arc<dyn Fn(&[u8]) + Send + Sync>;
+
+/// Checks the data of each chunk a `PipeWriter` sends (see `PipeWriter::set_validator()`)
+pub type Validator = Arc<dyn Fn(&[u8]) -> io::Result<()> + Send + Sync>;
+
+/// What a `PipeWriter` does with writes and chunks that carry no data (see
+/// `PipeWriter::set_empty_writes()`)
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, Default)]
+pub enum EmptyWrites {
+    /// Send them as empty chunks, which reads skip over but `PipeReader::recv_chunk()` returns, so
+    /// that they can serve as keepalive or flush markers
+    #[default]
+    Send,
+    /// Drop them without sending anything
+    Suppress,
+}
+
+/// Creates a synchronous memory pipe
+pub fn pipe() -> (PipeReader, PipeWriter) {
+    // A zero-capacity channel is already a single-slot handoff between the two ends, so there is
+    // no separate SPSC path for the common one-writer case. What such a path could save is small
+    // next to what it would take: `PipeSet` selects over the readers' channels, `WaitStrategy`
+    // polls them, and deadlines and timeouts use the channel's own, so every one of those would
+    // need a second implementation for the other kind of pipe.
+    unbuffered(Shared::new())
+}
+
+/// Creates a synchronous memory pipe identified by `name`
+///
+/// The name is included in the pipe's error messages and state, so that failures can be
+/// attributed to a particular pipe when there are many of them.
+pub fn pipe_named<N: Into<Cow<'static, str>>>(name: N) -> (PipeReader, PipeWriter) {
+    unbuffered(Shared::with_name(Some(name.into())))
+}
+
+fn unbuffered(shared: Arc<Shared>) -> (PipeReader, PipeWriter) {
+    queued(shared, 0)
+}
+
+/// Creates a pipe whose writers can send up to `depth` chunks ahead of the reader
+pub(crate) fn queued(shared: Arc<Shared>, depth: usize) -> (PipeReader, PipeWriter) {
+    let (sender, receiver) = crossbeam_channel::bounded(depth);
+
+    (
+        PipeReader::new(receiver, ReaderHandle::new(shared.clone())),
+        PipeWriter::new(Arc::new(sender), None, WriterHandle::new(shared)),
+    )
+}
+
+/// Creates a synchronous memory pipe that is fair to cloned writers
+///
+/// Writers sharing the pipe are admitted in the order they start sending, so a fast writer can't
+/// starve the others: while several writers are contending, their chunks are delivered
+/// round-robin.
+pub fn pipe_fair() -> (PipeReader, PipeWriter) {
+    let (sender, receiver) = crossbeam_channel::bounded(0);
+    let shared = Shared::new();
+
+    (
+        PipeReader::new(receiver, ReaderHandle::new(shared.clone())),
+        PipeWriter::new(Arc::new(sender), Some(Arc::new(Turnstile::new())), WriterHandle::new(shared)),
+    )
+}
+
+/// Creates a synchronous memory pipe with buffered writer
+pub fn pipe_buffered() -> (PipeReader, PipeBufWriter) {
+    buffered(Shared::new())
+}
+
+/// Creates a synchronous memory pipe with buffered writer, identified by `name` (see
+/// `pipe_named()`)
+pub fn pipe_buffered_named<N: Into<Cow<'static, str>>>(name: N) -> (PipeReader, PipeBufWriter) {
+    buffered(Shared::with_name(Some(name.into())))
+}
+
+fn buffered(shared: Arc<Shared>) -> (PipeReader, PipeBufWriter) {
+    let (tx, rx) = crossbeam_channel::bounded(0);
+
+    (PipeReader::new(rx, ReaderHandle::new(shared.clone())), PipeBufWriter { sender: Some(tx), buffer: Vec::with_capacity(DEFAULT_BUF_SIZE), size: DEFAULT_BUF_SIZE, atomic: false, eager: true, eager_interval: None, nodelay: false, checksums: false, sequenced: false, sequence: None, last_flush: Instant::now(), clock: ClockHandle::default(), pool: None, drop_timeout: None, abandon_hook: None, handle: WriterHandle::new(shared) } )
+}
+
+/// Creates a pair of pipes for bidirectional communication, a bit like UNIX's `socketpair(2)`.
+#[cfg(feature = "bidirectional")]
+#[cfg_attr(feature = "unstable-doc-cfg", doc(cfg(feature = "bidirectional")))]
+pub fn bipipe() -> (readwrite::ReadWrite<PipeReader, PipeWriter>, readwrite::ReadWrite<PipeReader, PipeWriter>) {
+    let (r1,w1) = pipe();
+    let (r2,w2) = pipe();
+    ((r1,w2).into(), (r2,w1).into())
+}
+
+/// Creates a pair of pipes for bidirectional communication using buffered writer, a bit like UNIX's `socketpair(2)`.
+#[cfg(feature = "bidirectional")]
+#[cfg_attr(feature = "unstable-doc-cfg", doc(cfg(feature = "bidirectional")))]
+pub fn bipipe_buffered() -> (readwrite::ReadWrite<PipeReader, PipeBufWriter>, readwrite::ReadWrite<PipeReader, PipeBufWriter>) {
+    let (r1,w1) = pipe_buffered();
+    let (r2,w2) = pipe_buffered();
+    ((r1,w2).into(), (r2,w1).into())
+}
+
+/// Creates a pair of pipes for bidirectional communication like `bipipe()`, with each end
+/// already holding data to be read
+///
+/// The first end starts out with `b_to_a` as though the second end had written it, and the
+/// second end with `a_to_b`, so a test can replay the first bytes of a captured conversation
+/// without a thread to write them.
+///
+/// # Example
+///
+/// ```
+/// use std::io::Read;
+///
+/// let (mut a, mut b) = pipe::bipipe_with_initial(b"hello".to_vec(), b"world".to_vec());
+/// let mut buf = [0; 5];
+/// a.read_exact(&mut buf).unwrap();
+/// assert_eq!(&buf, b"world");
+/// b.read_exact(&mut buf).unwrap();
+/// assert_eq!(&buf, b"hello");
+/// ```
+#[cfg(feature = "bidirectional")]
+#[cfg_attr(feature = "unstable-doc-cfg", doc(cfg(feature = "bidirectional")))]
+pub fn bipipe_with_initial(a_to_b: Vec<u8>, b_to_a: Vec<u8>) -> (readwrite::ReadWrite<PipeReader, PipeWriter>, readwrite::ReadWrite<PipeReader, PipeWriter>) {
+    let (mut r1,w1) = pipe();
+    let (mut r2,w2) = pipe();
+    r1.preload(b_to_a);
+    r2.preload(a_to_b);
+    ((r1,w2).into(), (r2,w1).into())
+}
+
+impl PipeWriter {
+    fn new(sender: Arc<Sender<Chunk>>, turnstile: Option<Arc<Turnstile>>, handle: WriterHandle) -> Self {
+        PipeWriter {
+            sender,
+            turnstile,
+            id: handle.shared().next_writer_id(),
+            written: Arc::new(AtomicU64::new(0)),
+            quota: None,
+            checksums: false,
+            sequenced: false,
+            ttl: None,
+            timestamps: false,
+            validator: None,
+            empty_writes: EmptyWrites::Send,
+            #[cfg(feature = "zstd")]
+            compression: None,
+            coalescer: None,
+            clock: ClockHandle::default(),
+            handle,
+        }
+    }
+
+    /// Extracts the inner `Sender` from the writer
+    ///
+    /// Since 0.5 the channel carries `Chunk`s rather than `Vec<u8>`s. A `Vec<u8>` converts into a
+    /// `Chunk` with `From`, and back with `Chunk::into_vec()`.
+    pub fn into_inner(self) -> Sender<Chunk> {
+        Arc::try_unwrap(self.sender).unwrap_or_else(|sender| (*sender).clone())
+    }
+
+    /// Creates a reference to the pipe that doesn't keep it open
+    ///
+    /// The reader sees EOF once every writer has been dropped, however many weak references there
+    /// are, so registries and caches can hold on to a pipe without holding it up. Like
+    /// `std::sync::Weak`, the reference can be upgraded again while any writer is alive.
+    pub fn downgrade(&self) -> WeakPipeWriter {
+        WeakPipeWriter {
+            sender: Arc::downgrade(&self.sender),
+            turnstile: self.turnstile.clone(),
+            shared: self.handle.downgrade(),
+        }
+    }
+
+    /// Gets a reference to the underlying `Sender`
+    pub fn sender(&self) -> &Sender<Chunk> {
+        &self.sender
+    }
+
+    /// Returns the name of the pipe, if it was given one
+    pub fn name(&self) -> Option<&str> {
+        self.handle.shared().name()
+    }
+
+    /// Returns a handle that can pause delivery to the pipe's reader
+    pub fn control(&self) -> PipeControl {
+        self.handle.control()
+    }
+
+    /// Returns `true` while the pipe has a reader that accepts writes
+    ///
+    /// Once this returns `false` every write fails with `BrokenPipe`, either because all readers
+    /// have been dropped or because one of them called `PipeReader::disconnect_writers()`.
+    pub fn reader_alive(&self) -> bool {
+        let shared = self.handle.shared();
+        shared.readers() > 0 && !shared.is_disconnected()
+    }
+
+    /// Subscribes to the pipe's events
+    pub fn events(&self) -> PipeEvents {
+        self.handle.shared().events()
+    }
+
+    /// Returns the identity of this writer, which is unique among the writers of its pipe
+    pub fn id(&self) -> usize {
+        self.id
+    }
+
+    /// Returns the number of bytes sent by this writer, not counting its clones
+    pub fn written(&self) -> u64 {
+        self.written.load(Ordering::Relaxed)
+    }
+
+    /// Limits how much this writer may send within a window of time
+    ///
+    /// The quota only applies to this writer. Clones made afterward get the same quota, but
+    /// account for it separately.
+    pub fn set_quota(&mut self, quota: Option<Quota>) {
+        self.quota = quota.map(|quota| Arc::new(Mutex::new(QuotaState::new(quota, &self.clock))));
+        self.restart_coalescing();
+    }
+
+    /// Returns the quota set by `set_quota()`
+    pub fn quota(&self) -> Option<Quota> {
+        self.quota.as_ref().map(|state| state.lock().unwrap_or_else(|e| e.into_inner()).quota())
+    }
+
+    /// Sends a CRC-32 checksum along with every chunk, which the reader verifies on receipt
+    ///
+    /// This is meant for validating producers: data that is modified while it passes through the
+    /// pipe, such as by an `unsafe` producer that keeps writing to a buffer it has already sent,
+    /// fails the read with `PipeError::Corrupted` rather than going unnoticed.
+    pub fn set_checksums(&mut self, checksums: bool) {
+        self.checksums = checksums;
+        self.restart_coalescing();
+    }
+
+    /// Returns whether chunks are checksummed (see `set_checksums()`)
+    pub fn checksums(&self) -> bool {
+        self.checksums
+    }
+
+    /// Numbers every chunk sent by this writer, so that the reader can tell whether any went
+    /// missing on the way (see `PipeReader::skipped()`)
+    ///
+    /// Numbers are shared by all writers of the pipe, starting at `1`. Chunks that were already
+    /// given a number with `Chunk::with_sequence()` keep it.
+    pub fn set_sequenced(&mut self, sequenced: bool) {
+        self.sequenced = sequenced;
+        self.restart_coalescing();
+    }
+
+    /// Returns whether chunks are numbered (see `set_sequenced()`)
+    pub fn sequenced(&self) -> bool {
+        self.sequenced
+    }
+
+    /// Makes every chunk sent expire `ttl` after it was sent, so that a reader that falls behind
+    /// drops stale data instead of receiving it late
+    ///
+    /// This suits data such as telemetry samples, where an old sample is worse than none. Chunks
+    /// that were already given an expiry with `Chunk::with_expiry()` keep it. Dropped chunks are
+    /// counted by `PipeReader::expired()`.
+    ///
+    /// Expiry is stamped by the writer's clock and judged by the reader's (see `set_clock()` and
+    /// `PipeReader::set_clock()`).
+    pub fn set_ttl(&mut self, ttl: Option<Duration>) {
+        self.ttl = ttl;
+        self.restart_coalescing();
+    }
+
+    /// Returns the time-to-live of chunks sent (see `set_ttl()`)
+    pub fn ttl(&self) -> Option<Duration> {
+        self.ttl
+    }
+
+    /// Compresses the chunks sent that are larger than the `threshold` of `compression`
+    ///
+    /// Compressed chunks are marked as such (see `Chunk::is_compressed()`), and the reader
+    /// decompresses them as it receives them, so this is transparent to it. `Quota`s and
+    /// `written()` count the data as it was written rather than as it was compressed.
+    #[cfg(feature = "zstd")]
+    #[cfg_attr(feature = "unstable-doc-cfg", doc(cfg(feature = "zstd")))]
+    pub fn set_compression(&mut self, compression: Option<Compression>) {
+        self.compression = compression;
+        self.restart_coalescing();
+    }
+
+    /// Returns how chunks are compressed (see `set_compression()`)
+    #[cfg(feature = "zstd")]
+    #[cfg_attr(feature = "unstable-doc-cfg", doc(cfg(feature = "zstd")))]
+    pub fn compression(&self) -> Option<Compression> {
+        self.compression
+    }
+
+    /// Stamps every chunk with the time it is sent, so that the reader can measure how long chunks
+    /// take to be handed over (see `PipeReader::latency_stats()`)
+    ///
+    /// Chunks that were already given a timestamp with `Chunk::with_timestamp()` keep it.
+    pub fn set_timestamps(&mut self, timestamps: bool) {
+        self.timestamps = timestamps;
+        self.restart_coalescing();
+    }
+
+    /// Returns whether chunks are stamped with the time they are sent (see `set_timestamps()`)
+    pub fn timestamps(&self) -> bool {
+        self.timestamps
+    }
+
+    /// Checks the data of every chunk with `validator` before sending it, such as to enforce frame
+    /// alignment or a maximum size
+    ///
+    /// A chunk that fails validation isn't sent, so malformed data is caught by the producer
+    /// rather than somewhere deep in the consumer. Writes through `io::Write` fail with the error
+    /// returned by `validator`, while methods such as `send_chunk()` fail with
+    /// `PipeError::Rejected`. With `set_coalesce_window()`, each write is validated on its own.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use std::io::{self, Write};
+    /// use std::sync::Arc;
+    ///
+    /// let (_reader, mut writer) = pipe::pipe();
+    /// writer.set_validator(Some(Arc::new(|data: &[u8]| match data.len() % 4 {
+    ///     0 => Ok(()),
+    ///     _ => Err(io::Error::new(io::ErrorKind::InvalidInput, "misaligned frame")),
+    /// })));
+    /// assert_eq!(writer.write(b"abc").unwrap_err().to_string(), "misaligned frame");
+    /// ```
+    pub fn set_validator(&mut self, validator: Option<Validator>) {
+        self.validator = validator;
+    }
+
+    /// Returns the validator chunks are checked with, if any (see `set_validator()`)
+    pub fn validator(&self) -> Option<&Validator> {
+        self.validator.as_ref()
+    }
+
+    /// Sets what is done with empty writes, and with chunks sent without any data
+    ///
+    /// By default they are sent like any other chunk, which costs the reader a wakeup. Chunks that
+    /// mark something despite being empty, such as those of a `PipeBarrier` or any with flags set,
+    /// are always sent.
+    pub fn set_empty_writes(&mut self, empty_writes: EmptyWrites) {
+        self.empty_writes = empty_writes;
+    }
+
+    /// Returns what is done with empty writes (see `set_empty_writes()`)
+    pub fn empty_writes(&self) -> EmptyWrites {
+        self.empty_writes
+    }
+
+    /// Returns `true` if `chunk` is to be dropped rather than sent (see `set_empty_writes()`)
+    fn suppressed(&self, chunk: &Chunk) -> bool {
+        self.empty_writes == EmptyWrites::Suppress && chunk.is_empty() && chunk.flags() == 0 && chunk.barrier().is_none()
+    }
+
+    fn validate(&self, data: &[u8]) -> io::Result<()> {
+        match &self.validator {
+            Some(validator) => validator(data),
+            None => Ok(()),
+        }
+    }
+
+    /// Gathers the writes made within `window` of each other into a single chunk
+    ///
+    /// This gets most of the benefit of a `PipeBufWriter` for code that can't easily be made to
+    /// flush at the right moments. The first write of a batch starts the window, and the batch is
+    /// sent by a thread of the writer's own once the window has passed, so it never waits for
+    /// another write. Writes return without waiting for the reader, unless the batch has grown
+    /// full; `flush()` sends the batch right away and waits for it to be received. Chunks sent
+    /// with `send_chunk()` and the like flush the batch first, so they stay in order, and the rest
+    /// of the batch is sent when the writer is dropped.
+    ///
+    /// A failure to send a batch is returned by the next write or flush. Batches count toward
+    /// this writer's `written()` and `Quota` like any other chunk, and changing how the writer
+    /// sends chunks, such as with `set_checksums()`, sends the current batch first.
+    pub fn set_coalesce_window(&mut self, window: Option<Duration>) {
+        // the old batch has to be sent before a new thread takes over
+        self.coalescer = None;
+        if let Some(window) = window {
+            self.coalescer = Some(Coalescer::new(self.batch_writer(), window, self.clock.clone()));
+        }
+    }
+
+    /// Starts coalescing over with the writer's current settings, if it coalesces at all
+    fn restart_coalescing(&mut self) {
+        if self.coalescer.is_some() {
+            let window = self.coalesce_window();
+            self.set_coalesce_window(window);
+        }
+    }
+
+    /// Creates the writer that sends coalesced batches on behalf of this one
+    ///
+    /// It shares this writer's identity, byte count and quota rather than being a writer of the
+    /// pipe in its own right. Batches aren't validated, since every write was as it was gathered.
+    fn batch_writer(&self) -> PipeWriter {
+        PipeWriter {
+            sender: self.sender.clone(),
+            turnstile: self.turnstile.clone(),
+            id: self.id,
+            written: self.written.clone(),
+            quota: self.quota.clone(),
+            checksums: self.checksums,
+            sequenced: self.sequenced,
+            ttl: self.ttl,
+            timestamps: self.timestamps,
+            validator: None,
+            empty_writes: self.empty_writes,
+            #[cfg(feature = "zstd")]
+            compression: self.compression,
+            coalescer: None,
+            clock: self.clock.clone(),
+            handle: self.handle.uncounted(),
+        }
+    }
+
+    /// Returns the window writes are coalesced within (see `set_coalesce_window()`)
+    pub fn coalesce_window(&self) -> Option<Duration> {
+        self.coalescer.as_ref().map(Coalescer::window)
+    }
+
+    /// Makes the writer's `Quota`, `set_ttl()` and `set_coalesce_window()` tell time and wait by
+    /// `clock`, such as a `ManualClock` in tests
+    ///
+    /// Clones made afterward use the same clock. The current quota window, if any, starts over,
+    /// and so does coalescing, after sending the current batch.
+    pub fn set_clock<C: Clock + 'static>(&mut self, clock: C) {
+        self.clock = ClockHandle::new(clock);
+        let quota = self.quota();
+        // restarts coalescing too
+        self.set_quota(quota);
+    }
+
+    /// Returns how hard the pipe is currently pushing back on its writers
+    ///
+    /// A producer can check this before writing, such as to lower its rate or quality instead of
+    /// blocking once the reader falls behind.
+    pub fn pressure(&self) -> Pressure {
+        self.handle.shared().pressure()
+    }
+
+    /// Captures the current state of the writer and its pipe
+    pub fn dump_state(&self) -> PipeState {
+        let shared = self.handle.shared();
+        PipeState {
+            name: shared.name().map(|name| Cow::Owned(name.into())),
+            buffered: 0,
+            queued: self.sender.len(),
+            capacity: None,
+            readers: shared.readers(),
+            writers: shared.writers(),
+        }
+    }
+
+    /// Captures the state of the writer and its pipe for a bug report (see `PipeSnapshot`)
+    pub fn snapshot(&self) -> PipeSnapshot {
+        self.handle.shared().snapshot(self.dump_state(), &[], 0)
+    }
+
+    /// Write data to the associated `PipeReader`
+    ///
+    /// The data is converted into a `Vec<u8>` first, so see `send_vec()` for when it is copied.
+    pub fn send<B: Into<Vec<u8>>>(&self, bytes: B) -> Result<(), PipeError> {
+        self.send_vec(bytes.into())
+    }
+
+    /// Hands an owned buffer to the associated `PipeReader`
+    ///
+    /// The allocation itself is passed along, so the data is never copied or reallocated on its way
+    /// to the reader. Readers that take whole chunks (such as `PipeReader::recv_many()`) receive
+    /// the very same `Vec`.
+    pub fn send_vec(&self, data: Vec<u8>) -> Result<(), PipeError> {
+        let ptr = data.as_ptr();
+        let chunk = Chunk::from(data);
+        debug_assert!(chunk.as_ptr() == ptr, "Chunk::from(Vec) must not copy");
+        self.send_chunk(chunk)
+    }
+
+    /// Hands static data to the associated `PipeReader` without copying or allocating, such as a
+    /// constant preamble or keepalive message
+    pub fn send_static(&self, data: &'static [u8]) -> Result<(), PipeError> {
+        self.send_chunk(Chunk::from_static(data))
+    }
+
+    /// Makes the pipe's broken pipe errors carry the platform's raw error code, as reported by
+    /// `io::Error::raw_os_error()`, instead of naming the pipe
+    ///
+    /// This applies to every writer of the pipe, so that code written against OS pipes that
+    /// matches on `EPIPE` behaves the same. It has no effect on platforms other than Unix and
+    /// Windows.
+    pub fn set_raw_os_errors(&self, raw: bool) {
+        self.handle.shared().set_raw_os_errors(raw);
+    }
+
+    /// Returns whether broken pipe errors carry a raw error code (see `set_raw_os_errors()`)
+    pub fn raw_os_errors(&self) -> bool {
+        self.handle.shared().raw_os_errors()
+    }
+
+    /// Write data to the associated `PipeReader`, tracking its delivery
+    ///
+    /// The returned handle resolves once a reader has consumed the whole chunk (see
+    /// `SendHandle`). Data moved into a block by `PipeReader::set_block_size()` counts as
+    /// consumed at that point.
+    pub fn send_tracked<B: Into<Vec<u8>>>(&self, bytes: B) -> Result<SendHandle, PipeError> {
+        let (receipt, handle) = Receipt::new();
+        self.send_chunk(Chunk::from(bytes.into()).with_receipt(receipt))
+            .map(|_| handle)
+    }
+
+    /// Writes all of `buf` so that it reaches the reader in one piece
+    ///
+    /// Every write to a `PipeWriter` is sent as a single chunk no matter its size, so this is the
+    /// same as `write_all()`: data from cloned writers is never interleaved within a write.
+    pub fn write_atomic(&self, buf: &[u8]) -> io::Result<()> {
+        self.send_chunk(buf.into())
+            .map_err(|e| self.io_error(e))
+    }
+
+    /// Hands a `Chunk` to the associated `PipeReader`, along with its metadata
+    ///
+    /// The metadata is only seen by readers that receive whole chunks, such as with
+    /// `PipeReader::recv_chunk()`.
+    pub fn send_chunk(&self, chunk: Chunk) -> Result<(), PipeError> {
+        self.send_chunk_until(chunk, None)
+    }
+
+    /// Sends every chunk produced by `chunks` in turn, returning how many were sent
+    ///
+    /// Should a send fail, such as because the reader is gone, the chunk that failed comes back
+    /// along with the rest of the iterator, so that a caller streaming from an expensive source
+    /// can carry on into another pipe without producing the data all over again. The failed chunk
+    /// keeps any checksum, sequence number or expiry this writer gave it.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// let (reader, writer) = pipe::pipe();
+    /// drop(reader);
+    /// let err = writer.send_all(vec![b"a".to_vec(), b"b".to_vec()]).unwrap_err();
+    /// assert_eq!(err.error, pipe::PipeError::Disconnected);
+    /// assert_eq!(&err.failed[..], b"a");
+    /// assert_eq!(err.remaining.collect::<Vec<_>>(), [b"b".to_vec()]);
+    /// ```
+    pub fn send_all<I>(&self, chunks: I) -> Result<usize, SendAllError<I::IntoIter>> where
+        I: IntoIterator,
+        I::Item: Into<Chunk>,
+    {
+        let mut chunks = chunks.into_iter();
+        let mut sent = 0;
+        while let Some(chunk) = chunks.next() {
+            if let Err((error, failed)) = self.send_chunk_returning(chunk.into(), None) {
+                // hand back the data as it was given
+                #[cfg(feature = "zstd")]
+                let failed = {
+                    let mut failed = failed;
+                    if failed.is_compressed() && failed.decompress().is_ok() && self.checksums {
+                        // decompressing drops the checksum of the compressed data
+                        failed = Box::new(failed.with_checksum());
+                    }
+                    failed
+                };
+                return Err(SendAllError { error, failed, remaining: chunks, sent })
+            }
+            sent += 1;
+        }
+        Ok(sent)
+    }
+
+    /// Sends a chunk, giving up with `PipeError::TimedOut` at `deadline` if there is one (see
+    /// `Shared::send_deadline()`)
+    pub(crate) fn send_chunk_until(&self, chunk: Chunk, deadline: Option<Instant>) -> Result<(), PipeError> {
+        self.send_chunk_returning(chunk, deadline).map_err(|(e, _)| e)
+    }
+
+    /// Sends a chunk like `send_chunk_until()`, handing it back if it couldn't be sent
+    fn send_chunk_returning(&self, chunk: Chunk, deadline: Option<Instant>) -> Result<(), (PipeError, Box<Chunk>)> {
+        if self.suppressed(&chunk) {
+            return Ok(())
+        }
+        if self.validate(&chunk).is_err() {
+            return Err((PipeError::Rejected, Box::new(chunk)))
+        }
+        self.send_validated(chunk, deadline)
+    }
+
+    /// Sends a chunk that has already passed validation
+    fn send_validated(&self, mut chunk: Chunk, deadline: Option<Instant>) -> Result<(), (PipeError, Box<Chunk>)> {
+        if let Some(coalescer) = &self.coalescer {
+            if let Err(e) = coalescer.flush() {
+                return Err((e, Box::new(chunk)))
+            }
+        }
+        let len = chunk.len() as u64;
+        #[cfg(feature = "zstd")]
+        if let Some(compression) = &self.compression {
+            compression.apply(&mut chunk);
+        }
+        if self.checksums {
+            chunk = chunk.with_checksum();
+        }
+        if self.sequenced && chunk.sequence().is_none() {
+            chunk = chunk.with_sequence(self.handle.shared().next_sequence());
+        }
+        if let (Some(ttl), None) = (self.ttl, chunk.expiry()) {
+            chunk = chunk.with_expiry(self.clock.now() + ttl);
+        }
+        if self.timestamps && chunk.timestamp().is_none() {
+            chunk = chunk.with_timestamp(Instant::now());
+        }
+
+        if let Some(quota) = &self.quota {
+            if let Err(e) = quota.lock().unwrap_or_else(|e| e.into_inner()).acquire(len, &self.clock) {
+                return Err((e, Box::new(chunk)))
+            }
+        }
+
+        let _turn = self.turnstile.as_ref().map(|turnstile| turnstile.enter());
+        let _pending = self.handle.shared().pending(len as usize);
+        match deadline {
+            Some(deadline) => self.handle.shared().send_deadline(self.sender(), chunk, deadline)
+                .map_err(|e| match e {
+                    SendTimeoutError::Timeout(chunk) => (PipeError::TimedOut, Box::new(chunk)),
+                    SendTimeoutError::Disconnected(chunk) => (PipeError::Disconnected, Box::new(chunk)),
+                })?,
+            None => self.handle.shared().send(self.sender(), chunk)
+                .map_err(|SendError(chunk)| (PipeError::Disconnected, Box::new(chunk)))?,
+        }
+        self.written.fetch_add(len, Ordering::Relaxed);
+        Ok(())
+    }
+
+    pub(crate) fn io_error(&self, err: PipeError) -> io::Error {
+        match err {
+            PipeError::Disconnected => self.handle.shared().epipe(),
+            err => err.into(),
+        }
+    }
+}
+
+impl WeakPipeWriter {
+    /// Returns a new writer for the pipe, or `None` if every writer has been dropped or the
+    /// reader is gone
+    ///
+    /// The writer has an identity of its own, like a clone (see `PipeWriter::id()`).
+    pub fn upgrade(&self) -> Option<PipeWriter> {
+        let sender = self.sender.upgrade()?;
+        let shared = self.shared.upgrade()?;
+        if shared.readers() == 0 || shared.is_disconnected() {
+            return None
+        }
+
+        Some(PipeWriter::new(sender, self.turnstile.clone(), WriterHandle::new(shared)))
+    }
+}
+
+impl fmt::Debug for WeakPipeWriter {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("WeakPipeWriter")
+            .field("writers", &self.sender.strong_count())
+            .finish()
+    }
+}
+
+/// Creates a new writer with an identity of its own (see `PipeWriter::id()`).
+impl Clone for PipeWriter {
+    fn clone(&self) -> Self {
+        let mut writer = PipeWriter::new(self.sender.clone(), self.turnstile.clone(), self.handle.clone());
+        writer.clock = self.clock.clone();
+        writer.set_quota(self.quota());
+        writer.checksums = self.checksums;
+        writer.sequenced = self.sequenced;
+        writer.ttl = self.ttl;
+        writer.timestamps = self.timestamps;
+        writer.validator = self.validator.clone();
+        writer.empty_writes = self.empty_writes;
+        #[cfg(feature = "zstd")]
+        {
+            writer.compression = self.compression;
+        }
+        writer.set_coalesce_window(self.coalesce_window());
+        writer
+    }
+}
+
+impl PipeBufWriter {
+    /// Extracts the inner `Sender` from the writer, and any pending buffered data
+    pub fn into_inner(mut self) -> (Sender<Chunk>, Vec<u8>) {
+        let sender = match self.sender.take() {
+            Some(sender) => sender,
+            None => unsafe {
+                // SAFETY: this is safe as long as `into_inner()` is the only method
+                // that clears the sender
+                unreachable_unchecked()
+            },
+        };
+        (sender, take(&mut self.buffer))
+    }
+
+    #[inline]
+    /// Gets a reference to the underlying `Sender`
+    pub fn sender(&self) -> &Sender<Chunk> {
+        match &self.sender {
+            Some(sender) => sender,
+            None => unsafe {
+                // SAFETY: this is safe as long as `into_inner()` is the only method
+                // that clears the sender, and this fn is never called afterward
+                unreachable_unchecked()
+            },
+        }
+    }
+
+    /// Returns a reference to the internally buffered data.
+    pub fn buffer(&self) -> &[u8] {
+        &self.buffer
+    }
+
+    /// Returns the number of bytes the internal buffer can hold without flushing.
+    pub fn capacity(&self) -> usize {
+        self.size
+    }
+
+    /// Returns the name of the pipe, if it was given one
+    pub fn name(&self) -> Option<&str> {
+        self.handle.shared().name()
+    }
+
+    /// Returns `true` while the pipe has a reader that accepts writes
+    ///
+    /// Once this returns `false` every write fails with `BrokenPipe`, either because all readers
+    /// have been dropped or because one of them called `PipeReader::disconnect_writers()`.
+    pub fn reader_alive(&self) -> bool {
+        let shared = self.handle.shared();
+        shared.readers() > 0 && !shared.is_disconnected()
+    }
+
+    /// Subscribes to the pipe's events
+    pub fn events(&self) -> PipeEvents {
+        self.handle.shared().events()
+    }
+
+    /// Returns how hard the pipe is currently pushing back on its writers (see
+    /// `PipeWriter::pressure()`)
+    ///
+    /// Data in this writer's own buffer isn't counted.
+    pub fn pressure(&self) -> Pressure {
+        self.handle.shared().pressure()
+    }
+
+    /// Captures the current state of the writer and its pipe
+    pub fn dump_state(&self) -> PipeState {
+        let shared = self.handle.shared();
+        PipeState {
+            name: shared.name().map(|name| Cow::Owned(name.into())),
+            buffered: self.buffer.len(),
+            queued: self.sender().len(),
+            capacity: Some(self.size),
+            readers: shared.readers(),
+            writers: shared.writers(),
+        }
+    }
+
+    /// Captures the state of the writer and its pipe for a bug report, including up to
+    /// `max_data` bytes of its buffer (see `PipeSnapshot`)
+    pub fn snapshot(&self, max_data: usize) -> PipeSnapshot {
+        self.handle.shared().snapshot(self.dump_state(), &self.buffer, max_data)
+    }
+
+    /// Takes buffers for new chunks from `pool` rather than allocating them
+    pub fn set_pool(&mut self, pool: Option<BufferPool>) {
+        self.pool = pool;
+    }
+
+    /// Releases excess capacity held by the internal buffer.
+    pub fn shrink_to_fit(&mut self) {
+        self.buffer.shrink_to_fit();
+    }
+
+    /// Writes all of `buf` so that it reaches the reader in one piece
+    ///
+    /// If `buf` doesn't fit into the rest of the buffer, the buffered data is flushed first rather
+    /// than topped up with the start of `buf`.
+    pub fn write_atomic(&mut self, buf: &[u8]) -> io::Result<()> {
+        let written = self.write_inner(buf, true)?;
+        debug_assert_eq!(written, buf.len());
+        Ok(())
+    }
+
+    /// Makes every `write()` behave like `write_atomic()`, so that neither `write()` nor
+    /// `write_all()` ever split their data across chunks
+    pub fn set_atomic_writes(&mut self, atomic: bool) {
+        self.atomic = atomic;
+    }
+
+    /// Returns whether writes are atomic (see `set_atomic_writes()`)
+    pub fn atomic_writes(&self) -> bool {
+        self.atomic
+    }
+
+    /// Controls whether a partially filled buffer is sent as soon as the reader is waiting
+    ///
+    /// This is enabled by default, which keeps latency low for a reader that keeps up, but may
+    /// hand it many small chunks. When disabled, data is only sent once the buffer is full or on
+    /// `flush()`.
+    pub fn set_eager_sends(&mut self, eager: bool) {
+        self.eager = eager;
+    }
+
+    /// Returns whether partially filled buffers are sent eagerly (see `set_eager_sends()`)
+    pub fn eager_sends(&self) -> bool {
+        self.eager
+    }
+
+    /// Limits eager sends to at most one per `interval` (see `set_eager_sends()`)
+    ///
+    /// A producer that emits many tiny writes to a reader that keeps up would otherwise wake the
+    /// reader for every one of them. With an interval, writes made within it of the last send are
+    /// only buffered, and sent along with the next write after it has passed, once the buffer is
+    /// full, or on `flush()`. The interval is measured by the writer's clock (see `set_clock()`).
+    pub fn set_eager_interval(&mut self, interval: Option<Duration>) {
+        self.eager_interval = interval;
+    }
+
+    /// Returns the minimum interval between eager sends (see `set_eager_interval()`)
+    pub fn eager_interval(&self) -> Option<Duration> {
+        self.eager_interval
+    }
+
+    /// Controls whether every write is flushed before it returns, however little it buffered
+    ///
+    /// This trades throughput for latency, and can be toggled at any time, such as to switch
+    /// between bulk transfers and interactive use of the same pipe. Enabling it doesn't send data
+    /// that is already buffered until the next write or `flush()`.
+    pub fn set_nodelay(&mut self, nodelay: bool) {
+        self.nodelay = nodelay;
+    }
+
+    /// Returns whether every write is flushed right away (see `set_nodelay()`)
+    pub fn nodelay(&self) -> bool {
+        self.nodelay
+    }
+
+    /// Sends a checksum along with every chunk (see `PipeWriter::set_checksums()`)
+    pub fn set_checksums(&mut self, checksums: bool) {
+        self.checksums = checksums;
+    }
+
+    /// Returns whether chunks are checksummed
+    pub fn checksums(&self) -> bool {
+        self.checksums
+    }
+
+    /// Numbers every chunk sent (see `PipeWriter::set_sequenced()`)
+    pub fn set_sequenced(&mut self, sequenced: bool) {
+        self.sequenced = sequenced;
+    }
+
+    /// Returns whether chunks are numbered
+    pub fn sequenced(&self) -> bool {
+        self.sequenced
+    }
+
+    /// Returns when the writer last handed its buffered data over to the reader, or when it was
+    /// created if it hasn't yet
+    ///
+    /// A writer whose buffer holds data that hasn't been flushed for a long time may have stalled.
+    /// Watchdogs that don't own the writer can subscribe to `PipeEvent::Flushed` instead.
+    pub fn last_flush(&self) -> Instant {
+        self.last_flush
+    }
+
+    /// Returns the time elapsed since `last_flush()`
+    pub fn time_since_flush(&self) -> Duration {
+        self.clock.now().saturating_duration_since(self.last_flush)
+    }
+
+    /// Makes `last_flush()` tell time by `clock`, such as a `ManualClock` in tests
+    ///
+    /// Clones made afterward use the same clock.
+    pub fn set_clock<C: Clock + 'static>(&mut self, clock: C) {
+        self.clock = ClockHandle::new(clock);
+        self.last_flush = self.clock.now();
+    }
+
+    /// Limits how long dropping the writer blocks on flushing its buffer, after which the data is
+    /// abandoned
+    ///
+    /// By default the flush on drop waits for as long as the reader takes, so a stuck reader can
+    /// hang a thread that is shutting down. Abandoned data can be reported with
+    /// `set_abandon_hook()`. Clones are made with the same timeout.
+    pub fn set_drop_timeout(&mut self, timeout: Option<Duration>) {
+        self.drop_timeout = timeout;
+    }
+
+    /// Returns how long dropping the writer may block (see `set_drop_timeout()`)
+    pub fn drop_timeout(&self) -> Option<Duration> {
+        self.drop_timeout
+    }
+
+    /// Sets a hook to call with any buffered data that couldn't be flushed when the writer was
+    /// dropped, because the drop timed out or the reader was gone
+    ///
+    /// Clones are made with the same hook.
+    pub fn set_abandon_hook(&mut self, hook: Option<AbandonHook>) {
+        self.abandon_hook = hook;
+    }
+
+    /// Called whenever buffered data has been handed over
+    fn flushed(&mut self) {
+        self.last_flush = self.clock.now();
+        self.sequence = None;
+        self.handle.shared().emit(PipeEvent::Flushed);
+        self.reserve();
+    }
+
+    /// Flushes several writers together, such as ones writing related streams to different pipes
+    ///
+    /// Each buffer is first handed to its reader if the reader is already waiting, then the
+    /// writers that are left flush at the same time, each on a thread of its own, so that no
+    /// stream has to wait for another's reader before it is flushed. Every writer is flushed even
+    /// if others fail, and the failures are returned along with the index of their writer.
+    pub fn flush_group(writers: &mut [&mut PipeBufWriter]) -> Result<(), Vec<(usize, io::Error)>> {
+        let mut errors = Vec::new();
+        let mut pending = Vec::new();
+        for (index, writer) in writers.iter_mut().enumerate() {
+            match writer.try_flush() {
+                Ok(true) => (),
+                Ok(false) => pending.push((index, &mut **writer)),
+                Err(e) => errors.push((index, e)),
+            }
+        }
+
+        if let [(index, writer)] = &mut pending[..] {
+            if let Err(e) = writer.flush() {
+                errors.push((*index, e));
+            }
+        } else if !pending.is_empty() {
+            scope(|scope| {
+                let flushes = pending.into_iter()
+                    .map(|(index, writer)| (index, scope.spawn(move || writer.flush())))
+                    .collect::<Vec<_>>();
+                for (index, flush) in flushes {
+                    if let Err(e) = flush.join().unwrap_or_else(|e| resume_unwind(e)) {
+                        errors.push((index, e));
+                    }
+                }
+            });
+        }
+
+        match errors.is_empty() {
+            true => Ok(()),
+            false => {
+                errors.sort_by_key(|&(index, _)| index);
+                Err(errors)
+            },
+        }
+    }
+
+    /// Sends the buffer only if a reader is already waiting for it, returning whether it did
+    fn try_flush(&mut self) -> io::Result<bool> {
+        if self.buffer.is_empty() {
+            return Ok(true)
+        }
+
+        let data = take(&mut self.buffer);
+        let chunk = self.chunk(data);
+        match self.sender().try_send(chunk) {
+            Ok(()) => {
+                self.flushed();
+                Ok(true)
+            },
+            Err(TrySendError::Full(data)) => {
+                self.buffer = data.into_vec();
+                Ok(false)
+            },
+            Err(TrySendError::Disconnected(data)) => {
+                self.buffer = data.into_vec();
+                Err(self.handle.shared().epipe())
+            },
+        }
+    }
+
+    /// Flushes the buffer, then sends `chunk` after it (see `ChunkSender`)
+    pub(crate) fn send_chunk_after(&mut self, mut chunk: Chunk) -> io::Result<()> {
+        self.flush()?;
+        if self.checksums {
+            chunk = chunk.with_checksum();
+        }
+        if self.sequenced && chunk.sequence().is_none() {
+            chunk = chunk.with_sequence(self.handle.shared().next_sequence());
+        }
+        self.handle.shared().send(self.sender(), chunk)
+            .map_err(|_| self.handle.shared().epipe())
+    }
+
+    fn chunk(&mut self, data: Vec<u8>) -> Chunk {
+        let mut chunk = Chunk::from(data);
+        if self.checksums {
+            chunk = chunk.with_checksum();
+        }
+        if self.sequenced {
+            // a buffer that couldn't be sent eagerly keeps its number for the next attempt
+            let shared = self.handle.shared();
+            chunk = chunk.with_sequence(*self.sequence.get_or_insert_with(|| shared.next_sequence()));
+        }
+        chunk
+    }
+
+    /// Makes the pipe's broken pipe errors carry the platform's raw error code (see
+    /// `PipeWriter::set_raw_os_errors()`)
+    pub fn set_raw_os_errors(&self, raw: bool) {
+        self.handle.shared().set_raw_os_errors(raw);
+    }
+
+    /// Returns whether broken pipe errors carry a raw error code
+    pub fn raw_os_errors(&self) -> bool {
+        self.handle.shared().raw_os_errors()
+    }
+
+    fn reserve(&mut self) {
+        match &self.pool {
+            Some(pool) if self.buffer.capacity() == 0 => self.buffer = pool.take(self.size),
+            _ => self.buffer.reserve(self.size),
+        }
+    }
+}
+
+/// Creates a new handle to the `PipeBufWriter` with a fresh new buffer. Any pending data is still
+/// owned by the existing writer and should be flushed if necessary.
+impl Clone for PipeBufWriter {
+    fn clone(&self) -> Self {
+        Self {
+            sender: self.sender.clone(),
+            buffer: Vec::with_capacity(self.size),
+            size: self.size,
+            atomic: self.atomic,
+            eager: self.eager,
+            eager_interval: self.eager_interval,
+            nodelay: self.nodelay,
+            checksums: self.checksums,
+            sequenced: self.sequenced,
+            sequence: None,
+            last_flush: self.clock.now(),
+            clock: self.clock.clone(),
+            pool: self.pool.clone(),
+            drop_timeout: self.drop_timeout,
+            abandon_hook: self.abandon_hook.clone(),
+            handle: self.handle.clone(),
+        }
+    }
+}
+
+impl PipeReader {
+    fn new(receiver: Receiver<Chunk>, handle: ReaderHandle) -> Self {
+        PipeReader {
+            receiver,
+            handle,
+            buffer: Chunk::new(),
+            position: 0,
+            block_size: None,
+            min_read: None,
+            min_read_timeout: None,
+            retain: false,
+            retain_limit: None,
+            pool: None,
+            wait: WaitStrategy::Park,
+            sequence: AtomicU64::new(0),
+            sequenced: AtomicU64::new(0),
+            received: AtomicU64::new(0),
+            offset: 0,
+            barrier: AtomicU64::new(0),
+            barrier_position: AtomicU64::new(0),
+            expired: AtomicU64::new(0),
+            latency: Mutex::new(LatencyWindow::default()),
+            clock: ClockHandle::default(),
+        }
+    }
+
+    /// Extracts the inner `Receiver` from the reader, and any pending buffered data
+    ///
+    /// The channel carries `Chunk`s (see `PipeWriter::into_inner()`).
+    pub fn into_inner(self) -> (Receiver<Chunk>, Vec<u8>) {
+        let mut buffer = self.buffer.into_vec();
+        buffer.drain(..self.position);
+        (self.receiver, buffer)
+    }
+
+    /// Returns a reference to the internally buffered data.
+    pub fn buffer(&self) -> &[u8] {
+        &self.buffer[self.position..]
+    }
+
+    /// Returns the name of the pipe, if it was given one
+    pub fn name(&self) -> Option<&str> {
+        self.handle.shared().name()
+    }
+
+    /// Returns a handle that can pause delivery to this reader
+    pub fn control(&self) -> PipeControl {
+        self.handle.control()
+    }
+
+    /// Subscribes to the pipe's events
+    pub fn events(&self) -> PipeEvents {
+        self.handle.shared().events()
+    }
+
+    /// Returns an iterator over the bytes of the pipe
+    ///
+    /// Unlike `Read::bytes()`, bytes are taken straight out of the internal buffer, and the pipe
+    /// is only read once the buffer runs out, so reading byte by byte costs little more than a
+    /// slice index.
+    pub fn bytes_iter(&mut self) -> BytesIter<'_> {
+        BytesIter { reader: self }
+    }
+
+    /// Views the reader as a receiver of whole chunks, such as for the bulk phase of a protocol
+    /// whose header was read as bytes
+    ///
+    /// Any data still buffered by the reader is received first, as a chunk of its own. Whatever
+    /// is left of the last chunk can be handed back with `Chunks::put_back()`, so that reading
+    /// bytes carries on where the chunks left off once the view is dropped.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use std::io::Read;
+    /// use std::thread::spawn;
+    ///
+    /// let (mut reader, writer) = pipe::pipe();
+    /// spawn(move || {
+    ///     writer.send(&b"\x02hiTRAILER"[..]).unwrap();
+    /// });
+    ///
+    /// let mut len = [0];
+    /// reader.read_exact(&mut len).unwrap();
+    /// let mut chunks = reader.as_chunks();
+    /// let mut chunk = chunks.recv().unwrap().unwrap();
+    /// let body = chunk.slice(0..len[0] as usize);
+    /// chunks.put_back(chunk.slice(len[0] as usize..chunk.len()));
+    /// assert_eq!(&body[..], b"hi");
+    ///
+    /// let mut trailer = String::new();
+    /// reader.read_to_string(&mut trailer).unwrap();
+    /// assert_eq!(trailer, "TRAILER");
+    /// ```
+    pub fn as_chunks(&mut self) -> Chunks<'_> {
+        Chunks { reader: self }
+    }
+
+    /// Blocks while the pipe is paused (see `PipeControl`)
+    pub(crate) fn wait_resumed(&self) {
+        self.handle.shared().gate().pass();
+    }
+
+    /// Returns the number of writers still attached to the pipe
+    ///
+    /// Every clone of a writer counts, and so does a writer that has been wrapped into something
+    /// else, such as a `PipeBufWriter`. The reader sees EOF once this is zero.
+    pub fn writer_count(&self) -> usize {
+        self.handle.shared().writers()
+    }
+
+    /// Cuts off all writers of the pipe, so that the reader sees EOF after the data it has
+    /// already buffered
+    ///
+    /// Every write from then on fails with `BrokenPipe`, as if the reader had been dropped.
+    /// Writers that are blocked handing over a chunk at the time are released too, and their data
+    /// is discarded. This affects every reader of the pipe, clones included.
+    pub fn disconnect_writers(&self) {
+        let shared = self.handle.shared();
+        shared.disconnect();
+        self.discard_pending();
+    }
+
+    /// Releases any writers waiting on the channel, dropping their chunks
+    fn discard_pending(&self) {
+        while self.receiver.try_recv().is_ok() { }
+    }
+
+    /// Waits for the next chunk, holding it back while the pipe is paused
+    ///
+    /// Returns `None` once all writers have been dropped, or once they were disconnected.
+    fn recv(&self) -> io::Result<Option<Chunk>> {
+        self.recv_until(None)
+    }
+
+    /// Waits for the next chunk like `recv()`, failing with `PipeError::TimedOut` if none arrives
+    /// by `deadline`
+    fn recv_until(&self, deadline: Option<Instant>) -> io::Result<Option<Chunk>> {
+        let shared = self.handle.shared();
+        if shared.is_disconnected() {
+            // a writer may have blocked just after the disconnect
+            self.discard_pending();
+            return Ok(None)
+        }
+
+        let waiting = shared.waiting();
+        let mut data = loop {
+            match self.wait.recv(&self.receiver, deadline) {
+                Ok(data) if self.drop_expired(&data) => (),
+                Ok(data) => break Some(data),
+                Err(RecvTimeoutError::Disconnected) => break None,
+                Err(RecvTimeoutError::Timeout) => return Err(PipeError::TimedOut.into()),
+            }
+        };
+        drop(waiting);
+        if let Some(data) = &mut data {
+            self.received(data)?;
+        }
+        self.wait_resumed();
+        Ok(data)
+    }
+
+    /// Waits for the next chunk until `deadline`, without holding it back while paused
+    fn recv_deadline(&self, deadline: Instant) -> Result<Chunk, RecvTimeoutError> {
+        let _waiting = self.handle.shared().waiting();
+        loop {
+            match self.receiver.recv_deadline(deadline) {
+                Ok(data) if self.drop_expired(&data) => (),
+                res => return res,
+            }
+        }
+    }
+
+    /// Counts `data` as expired if it is, in which case it is to be dropped instead of received
+    fn drop_expired(&self, data: &Chunk) -> bool {
+        let expired = data.expiry().is_some_and(|expiry| expiry <= self.clock.now());
+        if expired {
+            self.expired.fetch_add(1, Ordering::Relaxed);
+        }
+        expired
+    }
+
+    /// Returns the number of chunks that expired before this reader received them, and were
+    /// dropped (see `PipeWriter::set_ttl()`)
+    ///
+    /// Chunks received through a `PipeSet` or `PipeBody` are delivered whether expired or not.
+    pub fn expired(&self) -> u64 {
+        self.expired.load(Ordering::Relaxed)
+    }
+
+    /// Makes the reader judge whether chunks have expired by `clock`, such as a `ManualClock` in
+    /// tests (see `PipeWriter::set_ttl()`)
+    ///
+    /// Clones made afterward use the same clock.
+    pub fn set_clock<C: Clock + 'static>(&mut self, clock: C) {
+        self.clock = ClockHandle::new(clock);
+    }
+
+    /// Called for every chunk taken from the channel
+    ///
+    /// Compressed chunks are verified and decompressed first, so that they are accounted for by
+    /// the size of their original data.
+    pub(crate) fn received(&self, data: &mut Chunk) -> io::Result<()> {
+        #[cfg(feature = "zstd")]
+        data.decompress()?;
+        self.accept(data);
+        data.verify().map_err(From::from)
+    }
+
+    /// Accounts for a chunk taken from the channel, without verifying it
+    pub(crate) fn accept(&self, data: &Chunk) {
+        self.handle.shared().received();
+        let received = self.received.fetch_add(data.len() as u64, Ordering::Relaxed);
+        if let Some(epoch) = data.barrier() {
+            self.barrier_position.store(received.wrapping_add(self.offset), Ordering::Relaxed);
+            self.barrier.store(epoch, Ordering::Relaxed);
+        }
+        if let Some(sequence) = data.sequence() {
+            self.sequence.fetch_max(sequence, Ordering::Relaxed);
+            self.sequenced.fetch_add(1, Ordering::Relaxed);
+        }
+        if let Some(timestamp) = data.timestamp() {
+            self.latency.lock().unwrap_or_else(|e| e.into_inner()).record(timestamp.elapsed());
+        }
+    }
+
+    /// Returns how long the last timestamped chunk took from being sent to being received (see
+    /// `PipeWriter::set_timestamps()`)
+    pub fn last_chunk_latency(&self) -> Option<Duration> {
+        self.latency.lock().unwrap_or_else(|e| e.into_inner()).last()
+    }
+
+    /// Returns percentiles of how long timestamped chunks took from being sent to being received
+    /// (see `PipeWriter::set_timestamps()`)
+    ///
+    /// The percentiles are taken over the most recent chunks, so that they follow changes in
+    /// load. Each clone of the reader keeps its own measurements.
+    pub fn latency_stats(&self) -> LatencyStats {
+        self.latency.lock().unwrap_or_else(|e| e.into_inner()).stats()
+    }
+
+    /// Returns the number of bytes read from the pipe so far, in whatever way they were read
+    ///
+    /// This is the offset into the stream of the next byte to be read, such as for reporting
+    /// where a parser failed. Data that is still buffered by the reader doesn't count until it is
+    /// consumed. Each clone of the reader keeps its own count.
+    pub fn position(&self) -> u64 {
+        let unread = (self.buffer.len() - self.position) as u64;
+        self.received.load(Ordering::Relaxed).wrapping_sub(unread).wrapping_add(self.offset)
+    }
+
+    /// Makes `position()` count on from `position`, such as `0` at the start of a new message
+    pub fn set_position(&mut self, position: u64) {
+        self.offset = self.offset.wrapping_add(position.wrapping_sub(self.position()));
+    }
+
+    /// Returns the highest sequence number received so far, or `0` if no numbered chunk has been
+    /// received (see `PipeWriter::set_sequenced()`)
+    pub fn sequence(&self) -> u64 {
+        self.sequence.load(Ordering::Relaxed)
+    }
+
+    /// Returns how many numbered chunks never reached this reader, judging by the gaps between the
+    /// sequence numbers received so far
+    ///
+    /// Chunks sent by concurrent writers may arrive slightly out of order, so a gap may close
+    /// again once a late chunk arrives. Chunks taken by other clones of the reader count as
+    /// skipped too.
+    pub fn skipped(&self) -> u64 {
+        self.sequence().saturating_sub(self.sequenced.load(Ordering::Relaxed))
+    }
+
+    /// Returns the epoch of the last `PipeBarrier` marker this reader has come across, or `0` if
+    /// there hasn't been one
+    ///
+    /// Markers are passed over by reads, so this is checked along with `barrier_position()`.
+    pub fn barrier(&self) -> u64 {
+        self.barrier.load(Ordering::Relaxed)
+    }
+
+    /// Returns the `position()` in the stream at which the last `PipeBarrier` marker was inserted
+    ///
+    /// Everything before this position was written before the barrier, and everything after it
+    /// was written after.
+    pub fn barrier_position(&self) -> u64 {
+        self.barrier_position.load(Ordering::Relaxed)
+    }
+
+    /// Reads whatever data is available without blocking
+    ///
+    /// Fails with `io::ErrorKind::WouldBlock` if there is no data yet or the pipe is paused, and
+    /// returns `Ok(0)` once all writers have been dropped.
+    pub fn try_read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.handle.shared().gate().is_paused() {
+            return Err(PipeError::WouldBlock.into())
+        }
+
+        while self.position >= self.buffer.len() {
+            match self.receiver.try_recv() {
+                Ok(data) if self.drop_expired(&data) => (),
+                Ok(mut data) => {
+                    self.received(&mut data)?;
+                    self.set_buffer(data, 0);
+                },
+                Err(TryRecvError::Empty) => return Err(PipeError::WouldBlock.into()),
+                Err(TryRecvError::Disconnected) => return Ok(0),
+            }
+        }
+
+        let len = min(buf.len(), self.buffer.len() - self.position);
+        buf[..len].copy_from_slice(&self.buffer[self.position..self.position + len]);
+        self.consume(len);
+        Ok(len)
+    }
+
+    /// Returns a file descriptor that becomes readable whenever data can be read from the pipe
+    ///
+    /// This allows waiting on the pipe from an event loop built on `poll(2)`, `epoll(7)` or
+    /// `kqueue(2)`, reading from it with `try_read()` once the descriptor is readable. The
+    /// descriptor never needs to be read from, and stays owned by the pipe. Readiness may be
+    /// spurious, such as when another clone of the reader took the data first.
+    #[cfg(all(feature = "readiness", unix))]
+    #[cfg_attr(feature = "unstable-doc-cfg", doc(cfg(all(feature = "readiness", unix))))]
+    pub fn readiness_fd(&self) -> io::Result<RawFd> {
+        self.handle.shared().readiness().map(|readiness| readiness.fd())
+    }
+
+    /// Captures the current state of the reader and its pipe
+    pub fn dump_state(&self) -> PipeState {
+        let shared = self.handle.shared();
+        PipeState {
+            name: shared.name().map(|name| Cow::Owned(name.into())),
+            buffered: self.buffer.len() - self.position,
+            queued: self.receiver.len(),
+            capacity: None,
+            readers: shared.readers(),
+            writers: shared.writers(),
+        }
+    }
+
+    /// Captures the state of the reader and its pipe for a bug report, including up to
+    /// `max_data` bytes of the data it has buffered
+    ///
+    /// Chunks that writers are blocked on handing over can't be looked into, but show up as
+    /// pending bytes. Each part of the snapshot is captured separately, so while the pipe is in
+    /// use the parts may not quite agree with each other.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use std::io::BufRead;
+    /// use std::thread::spawn;
+    ///
+    /// let (mut reader, writer) = pipe::pipe_named("input");
+    /// spawn(move || writer.send(&b"hello"[..]));
+    /// reader.fill_buf().unwrap();
+    /// println!("{}", reader.snapshot(64));
+    /// // pipe 'input': 1 readers, 0 writers
+    /// // buffered: 5 bytes, queued: 0 chunks, pending: 0 bytes, reader waiting: false
+    /// // data: "hello"
+    /// ```
+    pub fn snapshot(&self, max_data: usize) -> PipeSnapshot {
+        self.handle.shared().snapshot(self.dump_state(), self.buffer(), max_data)
+    }
+
+    /// Re-chunks the stream into blocks of `size` bytes.
+    ///
+    /// When set, `fill_buf()` accumulates incoming data until exactly `size` bytes are available
+    /// (or the pipe is closed, in which case the final block may be shorter), and never returns
+    /// more than one block at a time.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `size` is zero.
+    pub fn set_block_size(&mut self, size: Option<usize>) {
+        assert!(size != Some(0), "block size must be non-zero");
+        self.block_size = size;
+    }
+
+    /// Makes `fill_buf()` and `read()` wait for at least `size` bytes before returning
+    ///
+    /// Small chunks are then gathered up until enough data has arrived, the pipe is closed, or the
+    /// timeout set by `set_min_read_timeout()` passes. Unlike `set_block_size()`, everything that
+    /// has been gathered is returned at once. This has no effect while a block size is set.
+    pub fn set_min_read_size(&mut self, size: Option<usize>) {
+        self.min_read = size;
+    }
+
+    /// Returns the minimum read size set by `set_min_read_size()`
+    pub fn min_read_size(&self) -> Option<usize> {
+        self.min_read
+    }
+
+    /// Limits how long a read waits for the minimum read size to be reached, counting from when
+    /// the first of its data arrived
+    pub fn set_min_read_timeout(&mut self, timeout: Option<Duration>) {
+        self.min_read_timeout = timeout;
+    }
+
+    /// Returns the timeout set by `set_min_read_timeout()`
+    pub fn min_read_timeout(&self) -> Option<Duration> {
+        self.min_read_timeout
+    }
+
+    /// Returns consumed chunks to `pool` so that they can be reused by a writer
+    pub fn set_pool(&mut self, pool: Option<BufferPool>) {
+        self.pool = pool;
+    }
+
+    /// Reserves capacity for at least `additional` more bytes in the internal buffer
+    ///
+    /// This is mostly useful along with `set_retain_buffer()`, to allocate the buffer up front.
+    pub fn reserve(&mut self, additional: usize) {
+        self.buffer.make_vec().reserve(additional);
+    }
+
+    /// Controls whether incoming chunks are copied into the internal buffer rather than replacing
+    /// it
+    ///
+    /// By default the reader adopts the allocation of each chunk it receives, which avoids a copy.
+    /// When retained, the reader instead keeps one long-lived buffer that it copies chunks into,
+    /// so that no allocation passes from writer to reader. A chunk counts as consumed (see
+    /// `PipeWriter::send_tracked()`) once it has been copied. The buffer grows to fit the largest
+    /// chunk received, unless bounded with `set_retain_limit()`.
+    pub fn set_retain_buffer(&mut self, retain: bool) {
+        self.retain = retain;
+    }
+
+    /// Returns whether the internal buffer is retained (see `set_retain_buffer()`)
+    pub fn retain_buffer(&self) -> bool {
+        self.retain
+    }
+
+    /// Caps how large a retained buffer may grow (see `set_retain_buffer()`)
+    ///
+    /// A chunk larger than `limit` is adopted as though the buffer weren't retained, rather than
+    /// growing the buffer to fit it. Whenever the buffer has more than `limit` bytes of capacity,
+    /// such as after adopting a chunk, it is shrunk back down before the next chunk is copied in.
+    pub fn set_retain_limit(&mut self, limit: Option<usize>) {
+        self.retain_limit = limit;
+    }
+
+    /// Returns the capacity a retained buffer is kept within (see `set_retain_limit()`)
+    pub fn retain_limit(&self) -> Option<usize> {
+        self.retain_limit
+    }
+
+    /// Releases the memory held by already consumed data.
+    pub fn shrink_to_fit(&mut self) {
+        let buffer = self.buffer.make_vec();
+        buffer.drain(..self.position);
+        buffer.shrink_to_fit();
+        self.position = 0;
+    }
+
+    /// Reads all bytes until EOF like `Read::read_to_end()`, but fails once more than `max` bytes
+    /// would be read
+    ///
+    /// On failure, the data read so far is left in `buf`, and the chunk that would have exceeded
+    /// the limit stays buffered in the reader. This guards against an unbounded producer on the
+    /// other end of the pipe exhausting memory.
+    pub fn read_to_end_limited(&mut self, buf: &mut Vec<u8>, max: usize) -> io::Result<usize> {
+        let mut read = 0;
+        loop {
+            let len = {
+                let data = self.fill_buf()?;
+                if data.len() > max - read {
+                    return Err(PipeError::TooLarge.into())
+                }
+                buf.extend_from_slice(data);
+                data.len()
+            };
+            if len == 0 {
+                return Ok(read)
+            }
+            self.consume(len);
+            read += len;
+        }
+    }
+
+    /// Consumes the pipe until EOF, passing each chunk to `on_chunk` and calling `on_idle` whenever
+    /// `idle_every` passes without any data arriving
+    ///
+    /// Any data still buffered by the reader is passed on first. Chunks are passed on as they were
+    /// written, regardless of `set_block_size()`. Returns once all writers have been dropped, or
+    /// with the first error returned by either callback.
+    pub fn drive<C, I>(&mut self, mut on_chunk: C, mut on_idle: I, idle_every: Duration) -> io::Result<()> where
+        C: FnMut(&[u8]) -> io::Result<()>,
+        I: FnMut() -> io::Result<()>,
+    {
+        self.wait_resumed();
+        if self.position < self.buffer.len() {
+            let len = self.buffer.len() - self.position;
+            on_chunk(&self.buffer[self.position..])?;
+            self.consume(len);
+        }
+
+        loop {
+            match self.recv_until(Some(Instant::now() + idle_every)) {
+                Ok(Some(data)) => {
+                    let result = on_chunk(&data);
+                    self.recycle(data);
+                    result?
+                },
+                Ok(None) => return Ok(()),
+                Err(ref e) if e.get_ref().and_then(|e| e.downcast_ref()) == Some(&PipeError::TimedOut) => on_idle()?,
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    /// Receives up to `max` chunks at once, appending them to `chunks`
+    ///
+    /// Any data still buffered by the reader is returned first as its own chunk. Otherwise this
+    /// waits for one chunk to arrive, then takes whatever other chunks are immediately available
+    /// without blocking again. Returns the number of chunks appended, which is only zero at EOF
+    /// (or when `max` is zero).
+    ///
+    /// A chunk that fails its checksum (see `PipeWriter::set_checksums()`) fails the call, though
+    /// any chunks received before it are still appended to `chunks`.
+    pub fn recv_many(&mut self, chunks: &mut Vec<Vec<u8>>, max: usize) -> io::Result<usize> {
+        if max == 0 {
+            return Ok(0)
+        }
+
+        let start = chunks.len();
+        match self.recv_chunk()?.map(Chunk::into_vec) {
+            Some(data) => chunks.push(data),
+            None => return Ok(0),
+        }
+
+        while chunks.len() - start < max {
+            let mut data = match self.receiver.try_recv() {
+                Ok(data) if self.drop_expired(&data) => continue,
+                Ok(data) => data,
+                Err(_) => break,
+            };
+            self.received(&mut data)?;
+            data.acknowledge();
+            chunks.push(data.into_vec());
+        }
+        Ok(chunks.len() - start)
+    }
+
+    /// Hands each chunk of the pipe over to `f`, until EOF
+    ///
+    /// Any data still buffered by the reader goes first. A chunk that was sent as a `Vec<u8>`
+    /// (see `PipeWriter::send_vec()`) arrives as that very allocation, so a sink that keeps chunks
+    /// around, such as a list of segments, never copies the data. Returns the number of bytes
+    /// handed over.
+    pub fn for_each_chunk<F: FnMut(Vec<u8>)>(&mut self, mut f: F) -> io::Result<u64> {
+        let mut total = 0;
+        while let Some(data) = self.recv_chunk()?.map(Chunk::into_vec) {
+            total += data.len() as u64;
+            f(data);
+        }
+        Ok(total)
+    }
+
+    /// Appends the rest of the pipe to `dest`, a chunk at a time, until EOF
+    ///
+    /// Returns the number of bytes appended.
+    pub fn drain_into<E: Extend<u8>>(&mut self, dest: &mut E) -> io::Result<u64> {
+        self.for_each_chunk(|data| dest.extend(data))
+    }
+
+    /// Receives the next chunk whole, along with any metadata it was sent with
+    ///
+    /// Any data still buffered by the reader is returned first, as a chunk of its own. Returns
+    /// `None` at EOF.
+    pub fn recv_chunk(&mut self) -> io::Result<Option<Chunk>> {
+        self.wait_resumed();
+        let chunk = match self.position < self.buffer.len() {
+            true => Some(self.take_buffer()),
+            false => self.recv()?,
+        };
+        Ok(chunk.map(|mut chunk| {
+            chunk.acknowledge();
+            chunk
+        }))
+    }
+
+    /// Reads exactly `len` bytes into a `Vec` of their own
+    ///
+    /// Whole chunks are taken over as they are rather than copied, so a payload that was sent as
+    /// a chunk of its own is returned without copying it, and one that spans several chunks is
+    /// built on top of the first. Fails with `io::ErrorKind::UnexpectedEof` if the pipe ends
+    /// first, like `read_exact()`.
+    pub fn read_exact_vec(&mut self, len: usize) -> io::Result<Vec<u8>> {
+        if self.block_size.is_some() {
+            // blocks need to be assembled by `fill_buf()`
+            let mut data = vec![0; len];
+            read_exact_fallback(self, &mut data)?;
+            return Ok(data)
+        }
+
+        self.wait_resumed();
+        let mut data = Vec::new();
+        while data.len() < len {
+            let mut chunk = match self.position < self.buffer.len() {
+                true => self.take_buffer(),
+                false => match self.recv()? {
+                    Some(chunk) => chunk,
+                    None => return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "failed to fill whole buffer")),
+                },
+            };
+
+            let needed = len - data.len();
+            if chunk.len() > needed {
+                // keep the remainder around for the next read
+                data.extend_from_slice(&chunk[..needed]);
+                self.set_buffer(chunk, needed);
+            } else {
+                chunk.acknowledge();
+                if data.is_empty() {
+                    data = chunk.into_vec();
+                    data.reserve_exact(len - data.len());
+                } else {
+                    data.extend_from_slice(&chunk);
+                    self.recycle(chunk);
+                }
+            }
+        }
+
+        Ok(data)
+    }
+
+    /// Fills `buf` completely unless the pipe ends first, returning how many bytes were read
+    ///
+    /// Unlike `read_exact()`, reaching EOF partway through isn't an error: the data read up to
+    /// that point is at the start of `buf` and its length is returned, so a final short block can
+    /// be told apart from a full one. Interrupted reads are retried.
+    pub fn read_exact_or_eof(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut read = 0;
+        while read < buf.len() {
+            match self.read(&mut buf[read..]) {
+                Ok(0) => break,
+                Ok(n) => read += n,
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => (),
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(read)
+    }
+
+    /// Reads a message whose end is located by `find_end`, such as one with a multi-byte
+    /// terminator, however it is split across chunks
+    ///
+    /// `find_end` is called with all the data of the message received so far, each time more of
+    /// it arrives, and returns the length of the message once it is complete. The message is then
+    /// returned, and anything read past it stays buffered for the next read. Returns `None` at EOF,
+    /// or fails with `io::ErrorKind::UnexpectedEof` if the pipe ends partway through a message,
+    /// leaving that data buffered. Fails with `io::ErrorKind::InvalidInput`, also leaving the data
+    /// buffered, if `find_end` returns a length past the end of the data it was given.
+    ///
+    /// This is named apart from `BufRead::read_until()`, which it generalizes to any kind of
+    /// delimiter.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use std::thread::spawn;
+    ///
+    /// let (mut reader, writer) = pipe::pipe();
+    /// spawn(move || {
+    ///     writer.send(&b"HELO\r"[..]).unwrap();
+    ///     writer.send(&b"\nQUIT\r\n"[..]).unwrap();
+    /// });
+    ///
+    /// let crlf = |data: &[u8]| data.windows(2).position(|w| w == b"\r\n").map(|pos| pos + 2);
+    /// assert_eq!(reader.read_until_with(crlf).unwrap().unwrap(), b"HELO\r\n");
+    /// assert_eq!(reader.read_until_with(crlf).unwrap().unwrap(), b"QUIT\r\n");
+    /// assert_eq!(reader.read_until_with(crlf).unwrap(), None);
+    /// ```
+    pub fn read_until_with<F: FnMut(&[u8]) -> Option<usize>>(&mut self, mut find_end: F) -> io::Result<Option<Vec<u8>>> {
+        let mut data = Vec::new();
+        loop {
+            let len = {
+                let buf = self.fill_buf()?;
+                data.extend_from_slice(buf);
+                buf.len()
+            };
+            if len == 0 {
+                if data.is_empty() {
+                    return Ok(None)
+                }
+                self.set_buffer(data.into(), 0);
+                return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "pipe ended partway through a message"))
+            }
+
+            let end = match find_end(&data) {
+                Some(end) => end,
+                None => {
+                    self.consume(len);
+                    continue
+                },
+            };
+            if end > data.len() {
+                let available = data.len();
+                self.consume(len);
+                self.set_buffer(data.into(), 0);
+                return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("message ends at {} bytes with only {} available", end, available)))
+            }
+            let rest = data.split_off(end);
+            if rest.len() <= len {
+                self.consume(len - rest.len());
+            } else {
+                // the message ended before this chunk, so give back what was read past it
+                self.consume(len);
+                self.set_buffer(rest.into(), 0);
+            }
+            return Ok(Some(data))
+        }
+    }
+
+    /// Marks `amt` bytes of the internal buffer as read, like `BufRead::consume()`, but fails with
+    /// `io::ErrorKind::InvalidInput` rather than panicking if fewer than `amt` bytes are buffered
+    pub fn try_consume(&mut self, amt: usize) -> io::Result<()> {
+        let available = self.buffer.len() - self.position;
+        if amt > available {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("consumed {} bytes with only {} available", amt, available)))
+        }
+
+        self.consume(amt);
+        Ok(())
+    }
+
+    /// Lends up to `max` bytes of the internal buffer to `f`, which returns how many of them it
+    /// consumed
+    ///
+    /// The buffer is filled first like `fill_buf()`, so `f` is given an empty slice only at EOF.
+    /// Returns the number of bytes consumed.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `f` consumes more bytes than it was given.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// let (mut reader, writer) = pipe::pipe();
+    /// std::thread::spawn(move || writer.send(&b"key=value"[..]).unwrap());
+    ///
+    /// let mut key = String::new();
+    /// reader.with_data(16, |data| {
+    ///     let len = data.iter().position(|&b| b == b'=').unwrap_or(data.len());
+    ///     key.push_str(std::str::from_utf8(&data[..len]).unwrap());
+    ///     len + 1
+    /// }).unwrap();
+    /// assert_eq!(key, "key");
+    /// assert_eq!(reader.buffer(), b"value");
+    /// ```
+    pub fn with_data<F: FnOnce(&[u8]) -> usize>(&mut self, max: usize, f: F) -> io::Result<usize> {
+        let consumed = {
+            let data = self.fill_buf()?;
+            let data = &data[..min(max, data.len())];
+            let consumed = f(data);
+            check_consume(consumed, data.len());
+            consumed
+        };
+        self.consume(consumed);
+        Ok(consumed)
+    }
+
+    #[cfg(feature = "http-body")]
+    pub(crate) fn shared(&self) -> &Shared {
+        self.handle.shared()
+    }
+
+    pub(crate) fn receiver(&self) -> &Receiver<Chunk> {
+        &self.receiver
+    }
+
+    /// Puts `data` ahead of anything still to be received, as though it had been received already
+    #[cfg(any(test, feature = "bidirectional"))]
+    pub(crate) fn preload(&mut self, data: Vec<u8>) {
+        let mut chunk = self.take_buffer();
+        self.received.fetch_add(data.len() as u64, Ordering::Relaxed);
+        if chunk.is_empty() {
+            chunk = data.into();
+        } else {
+            chunk.make_vec().splice(..0, data);
+        }
+        self.set_buffer(chunk, 0);
+    }
+
+    /// Takes the unread part of the internal buffer
+    pub(crate) fn take_buffer(&mut self) -> Chunk {
+        let mut chunk = take(&mut self.buffer);
+        if self.position > 0 {
+            chunk.make_vec().drain(..self.position);
+            self.position = 0;
+        }
+        chunk
+    }
+
+    /// Replaces the internal buffer with `chunk`, of which `position` bytes have been read
+    pub(crate) fn set_buffer(&mut self, chunk: Chunk, position: usize) {
+        let limit = self.retain_limit.unwrap_or(usize::MAX);
+        if self.retain && chunk.len() <= limit {
+            let buffer = self.buffer.make_vec();
+            buffer.clear();
+            buffer.shrink_to(limit);
+            buffer.extend_from_slice(&chunk);
+            self.recycle(chunk);
+        } else {
+            let data = replace(&mut self.buffer, chunk);
+            self.recycle(data);
+        }
+        self.position = 0;
+        self.consume(position);
+    }
+
+    /// Reads exactly enough to fill `buf` like `read_exact()`, but fails with
+    /// `PipeError::TimedOut` at `deadline`
+    pub(crate) fn read_exact_deadline(&mut self, buf: &mut [u8], deadline: Instant) -> io::Result<()> {
+        let mut filled = 0;
+        while filled < buf.len() {
+            if self.position >= self.buffer.len() {
+                match self.recv_deadline(deadline) {
+                    Ok(mut data) => {
+                        self.received(&mut data)?;
+                        self.set_buffer(data, 0);
+                    },
+                    Err(RecvTimeoutError::Timeout) => return Err(PipeError::TimedOut.into()),
+                    Err(RecvTimeoutError::Disconnected) => return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "failed to fill whole buffer")),
+                }
+            }
+
+            let len = min(buf.len() - filled, self.buffer.len() - self.position);
+            buf[filled..filled + len].copy_from_slice(&self.buffer[self.position..self.position + len]);
+            self.consume(len);
+            filled += len;
+        }
+        Ok(())
+    }
+
+    fn recycle(&mut self, mut data: Chunk) {
+        data.acknowledge();
+        match &self.pool {
+            Some(pool) if !data.is_inline() && !data.is_static() => pool.put(data.into_vec()),
+            _ => (),
+        }
+    }
+
+    /// Sets how the reader waits for incoming data
+    pub fn set_wait_strategy(&mut self, wait: WaitStrategy) {
+        self.wait = wait;
+    }
+
+    /// Returns the reader's `WaitStrategy`
+    pub fn wait_strategy(&self) -> WaitStrategy {
+        self.wait
+    }
+
+    /// Returns the block size set by `set_block_size()`
+    pub fn block_size(&self) -> Option<usize> {
+        self.block_size
+    }
+
+    fn fill_block(&mut self, size: usize) -> io::Result<()> {
+        if self.buffer.len() - self.position >= size {
+            return Ok(())
+        }
+
+        self.buffer.make_vec().drain(..self.position);
+        self.position = 0;
+        while self.buffer.len() < size {
+            match self.recv()? {
+                None => break,
+                Some(data) => {
+                    self.buffer.make_vec().extend_from_slice(&data);
+                    self.recycle(data);
+                },
+            }
+        }
+        Ok(())
+    }
+
+    fn fill_min(&mut self, size: usize) -> io::Result<()> {
+        if self.buffer.len() - self.position >= size {
+            return Ok(())
+        }
+
+        let deadline = self.min_read_timeout.map(|timeout| Instant::now() + timeout);
+        self.buffer.make_vec().drain(..self.position);
+        self.position = 0;
+        while self.buffer.len() < size {
+            let data = match deadline {
+                Some(deadline) => match self.recv_deadline(deadline) {
+                    Ok(mut data) => {
+                        self.received(&mut data)?;
+                        self.wait_resumed();
+                        data
+                    },
+                    Err(_) => break,
+                },
+                None => match self.recv()? {
+                    Some(data) => data,
+                    None => break,
+                },
+            };
+            self.buffer.make_vec().extend_from_slice(&data);
+            self.recycle(data);
+        }
+        Ok(())
+    }
+}
+
+/// Creates a new handle to the `PipeReader` with a fresh new buffer. Any pending data is still
+/// owned by the existing reader and will not be accessible from the new handle.
+impl Clone for PipeReader {
+    fn clone(&self) -> Self {
+        Self {
+            block_size: self.block_size,
+            min_read: self.min_read,
+            min_read_timeout: self.min_read_timeout,
+            retain: self.retain,
+            retain_limit: self.retain_limit,
+            pool: self.pool.clone(),
+            wait: self.wait,
+            clock: self.clock.clone(),
+            ..PipeReader::new(self.receiver.clone(), self.handle.clone())
+        }
+    }
+}
+
+impl fmt::Debug for PipeReader {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let state = self.dump_state();
+        f.debug_struct("PipeReader")
+            .field("name", &state.name)
+            .field("buffered", &state.buffered)
+            .field("queued", &state.queued)
+            .field("block_size", &self.block_size)
+            .field("wait", &self.wait)
+            .field("writers", &state.writers)
+            .finish()
+    }
+}
+
+impl BufRead for PipeReader {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        self.wait_resumed();
+        while self.position >= self.buffer.len() {
+            match self.recv()? {
+                None => break,
+                Some(data) => self.set_buffer(data, 0),
+            }
+        }
+
+        if let Some(size) = self.block_size {
+            self.fill_block(size)?;
+            let end = min(self.buffer.len(), self.position + size);
+            return Ok(&self.buffer[self.position..end])
+        }
+
+        if let Some(size) = self.min_read {
+            if self.position < self.buffer.len() {
+                self.fill_min(size)?;
+            }
+        }
+
+        Ok(&self.buffer[self.position..])
+    }
+
+    fn consume(&mut self, amt: usize) {
+        check_consume(amt, self.buffer.len() - self.position);
+        self.position += amt;
+        if self.position >= self.buffer.len() {
+            self.buffer.acknowledge();
+        }
+    }
+}
+
+impl Read for PipeReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+
+        let internal = self.fill_buf()?;
+
+        let len = min(buf.len(), internal.len());
+        if len > 0 {
+            buf[..len].copy_from_slice(&internal[..len]);
+            self.consume(len);
+        }
+        Ok(len)
+    }
+
+    /// Copies straight out of each incoming chunk, without going through `fill_buf()` for every
+    /// one of them.
+    fn read_exact(&mut self, buf: &mut [u8]) -> io::Result<()> {
+        if self.block_size.is_some() {
+            // blocks need to be assembled by `fill_buf()`
+            return read_exact_fallback(self, buf)
+        }
+
+        self.wait_resumed();
+        let len = min(buf.len(), self.buffer.len() - self.position);
+        buf[..len].copy_from_slice(&self.buffer[self.position..self.position + len]);
+        self.consume(len);
+
+        let mut buf = &mut buf[len..];
+        while !buf.is_empty() {
+            let data = match self.recv()? {
+                Some(data) => data,
+                None => return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "failed to fill whole buffer")),
+            };
+
+            let len = min(buf.len(), data.len());
+            buf[..len].copy_from_slice(&data[..len]);
+            buf = &mut buf[len..];
+            if len < data.len() {
+                // keep the remainder around for the next read
+                self.set_buffer(data, len);
+            } else {
+                self.recycle(data);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// An iterator over the bytes of a `PipeReader` (see `PipeReader::bytes_iter()`)
+pub struct BytesIter<'a> {
+    reader: &'a mut PipeReader,
+}
+
+impl Iterator for BytesIter<'_> {
+    type Item = io::Result<u8>;
+
+    fn next(&mut self) -> Option<io::Result<u8>> {
+        let reader = &mut *self.reader;
+        if reader.position >= reader.buffer.len() || reader.handle.shared().gate().is_paused() {
+            match reader.fill_buf() {
+                Ok([]) => return None,
+                Ok(..) => (),
+                Err(e) => return Some(Err(e)),
+            }
+        }
+
+        let byte = reader.buffer[reader.position];
+        reader.consume(1);
+        Some(Ok(byte))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.reader.buffer().len(), None)
+    }
+}
+
+/// A view of a `PipeReader` as a receiver of whole chunks (see `PipeReader::as_chunks()`)
+pub struct Chunks<'a> {
+    reader: &'a mut PipeReader,
+}
+
+impl Chunks<'_> {
+    /// Receives the next chunk, or `None` at EOF (see `PipeReader::recv_chunk()`)
+    pub fn recv(&mut self) -> io::Result<Option<Chunk>> {
+        self.reader.recv_chunk()
+    }
+
+    /// Hands data back to the reader, to be received or read again before anything else
+    pub fn put_back(&mut self, mut chunk: Chunk) {
+        let reader = &mut *self.reader;
+        if reader.position < reader.buffer.len() {
+            chunk.make_vec().extend_from_slice(&reader.buffer[reader.position..]);
+        }
+        let data = replace(&mut reader.buffer, chunk);
+        reader.position = 0;
+        reader.recycle(data);
+    }
+}
+
+impl Iterator for Chunks<'_> {
+    type Item = io::Result<Chunk>;
+
+    fn next(&mut self) -> Option<io::Result<Chunk>> {
+        self.recv().transpose()
+    }
+}
+
+fn read_exact_fallback<R: Read>(reader: &mut R, mut buf: &mut [u8]) -> io::Result<()> {
+    while !buf.is_empty() {
+        match reader.read(buf) {
+            Ok(0) => return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "failed to fill whole buffer")),
+            Ok(n) => buf = &mut buf[n..],
+            Err(ref e) if e.kind() == io::ErrorKind::Interrupted => (),
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(())
+}
+
+impl fmt::Debug for PipeWriter {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let state = self.dump_state();
+        f.debug_struct("PipeWriter")
+            .field("name", &state.name)
+            .field("id", &self.id)
+            .field("queued", &state.queued)
+            .field("fair", &self.turnstile.is_some())
+            .field("readers", &state.readers)
+            .field("writers", &state.writers)
+            .finish()
+    }
+}
+
+impl fmt::Debug for PipeBufWriter {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let state = self.dump_state();
+        f.debug_struct("PipeBufWriter")
+            .field("name", &state.name)
+            .field("buffered", &state.buffered)
+            .field("capacity", &self.size)
+            .field("queued", &state.queued)
+            .field("readers", &state.readers)
+            .field("writers", &state.writers)
+            .finish()
+    }
+}
+
+impl Write for &'_ PipeWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if buf.is_empty() && self.empty_writes == EmptyWrites::Suppress {
+            return Ok(0)
+        }
+        self.validate(buf)?;
+        if let Some(coalescer) = &self.coalescer {
+            return coalescer.write(buf)
+                .map(|_| buf.len())
+                .map_err(|e| self.io_error(e))
+        }
+
+        // small writes are stored inline without allocating
+        self.send_validated(buf.into(), None)
+            .map(|_| buf.len())
+            .map_err(|(e, _)| self.io_error(e))
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match &self.coalescer {
+            Some(coalescer) => coalescer.flush().map_err(|e| self.io_error(e)),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Write for PipeWriter {
+    #[inline]
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        Write::write(&mut &*self, buf)
+    }
+
+    #[inline]
+    fn flush(&mut self) -> io::Result<()> {
+        Write::flush(&mut &*self)
+    }
+}
+
+impl PipeBufWriter {
+    fn write_inner(&mut self, buf: &[u8], atomic: bool) -> io::Result<usize> {
+        if atomic && buf.len() <= self.size && buf.len() > self.size - self.buffer.len() {
+            // make room rather than splitting the write
+            self.flush()?;
+        }
+
+        if buf.len() > self.size {
+            // bypass buffering for big writes, sending them as a chunk of their own copied
+            // straight from `buf` rather than growing the buffer to fit them
+            self.flush()?;
+            let chunk = self.chunk(buf.to_vec());
+            let pending = self.handle.shared().pending(chunk.len());
+            let res = self.handle.shared().send(self.sender(), chunk);
+            drop(pending);
+            if res.is_err() {
+                return Err(self.handle.shared().epipe())
+            }
+            self.flushed();
+            return Ok(buf.len())
+        }
+
+        // avoid resizing of the buffer
+        let buffer_len = self.buffer.len();
+        let bytes_written = min(buf.len(), self.size - buffer_len);
+        self.buffer.extend_from_slice(&buf[..bytes_written]);
+
+        if self.buffer.len() >= self.size || self.nodelay {
+            self.flush()?;
+        } else if self.eager && self.eager_interval.is_none_or(|interval| self.time_since_flush() >= interval) {
+            // reserve capacity later to avoid needless allocations
+            let data = take(&mut self.buffer);
+            let chunk = self.chunk(data);
+
+            // buffer still has space but try to send it in case the other side already awaits
+            match self.sender().try_send(chunk) {
+                Ok(_) => self.flushed(),
+                Err(TrySendError::Full(data)) =>
+                    self.buffer = data.into_vec(),
+                Err(TrySendError::Disconnected(data)) => {
+                    self.buffer = data.into_vec();
+                    self.buffer.truncate(buffer_len);
+                    return Err(self.handle.shared().epipe())
+                },
+            }
+        }
+
+        Ok(bytes_written)
+    }
+}
+
+impl Write for PipeBufWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let atomic = self.atomic;
+        self.write_inner(buf, atomic)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if self.buffer.is_empty() {
+            self.last_flush = self.clock.now();
+            Ok(())
+        } else {
+            let data = take(&mut self.buffer);
+            let chunk = self.chunk(data);
+            let pending = self.handle.shared().pending(chunk.len());
+            let res = self.handle.shared().send(self.sender(), chunk);
+            drop(pending);
+            match res {
+                Ok(_) => {
+                    self.flushed();
+                    Ok(())
+                },
+                Err(SendError(data)) => {
+                    self.buffer = data.into_vec();
+                    Err(self.handle.shared().epipe())
+                },
+            }
+        }
+    }
+}
+
+/// Flushes the contents of the buffer before the writer is dropped. Errors are ignored, so it is
+/// recommended that `flush()` be used explicitly instead of relying on Drop.
+///
+/// This final flush can be avoided by using `drop(writer.into_inner())`, or limited with
+/// `set_drop_timeout()`.
+impl Drop for PipeBufWriter {
+    fn drop(&mut self) {
+        if !self.buffer.is_empty() {
+            let data = take(&mut self.buffer);
+            let chunk = self.chunk(data);
+            let shared = self.handle.shared();
+            let pending = shared.pending(chunk.len());
+            let abandoned = match self.drop_timeout {
+                Some(timeout) => shared.send_deadline(self.sender(), chunk, Instant::now() + timeout)
+                    .err().map(SendTimeoutError::into_inner),
+                None => shared.send(self.sender(), chunk).err().map(|SendError(chunk)| chunk),
+            };
+            drop(pending);
+            match (abandoned, &self.abandon_hook) {
+                // like `flushed()`, without setting up a buffer that is never going to be used
+                (None, _) => shared.emit(PipeEvent::Flushed),
+                (Some(chunk), Some(hook)) => hook(&chunk),
+                (Some(..), None) => (),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread::{sleep, spawn};
+    use std::time::{Duration, Instant};
+    use std::io::{self, Read, Write};
+    use {PipeEvent, Pressure, QuotaPolicy};
+    use super::*;
+
+    #[test]
+    fn pipe_reader() {
+        let i = b"hello there";
+        let mut o = Vec::with_capacity(i.len());
+        let (mut r, mut w) = pipe();
+        let guard = spawn(move || {
+            w.write_all(&i[..5]).unwrap();
+            w.write_all(&i[5..]).unwrap();
+            drop(w);
+        });
+
+        r.read_to_end(&mut o).unwrap();
+        assert_eq!(i, &o[..]);
+
+        guard.join().unwrap();
+    }
+
+    #[test]
+    fn pipe_writer_fail() {
+        let i = b"hi";
+        let (r, mut w) = pipe();
+        let guard = spawn(move || {
+            drop(r);
+        });
+
+        assert!(w.write_all(i).is_err());
+
+        guard.join().unwrap();
+    }
+
+    #[test]
+    fn small_reads() {
+        let block_cnt = 20;
+        const BLOCK: usize = 20;
+        let (mut r, mut w) = pipe();
+        let guard = spawn(move || {
+            for _ in 0..block_cnt {
+                let data = &[0; BLOCK];
+                w.write_all(data).unwrap();
+            }
+        });
+
+        let mut buff = [0; BLOCK / 2];
+        let mut read = 0;
+        while let Ok(size) = r.read(&mut buff) {
+            // 0 means EOF
+            if size == 0 {
+                break;
+            }
+            read += size;
+        }
+        assert_eq!(block_cnt * BLOCK, read);
+
+        guard.join().unwrap();
+    }
+
+    #[test]
+    fn pipe_reader_buffered() {
+        let i = b"hello there";
+        let mut o = Vec::with_capacity(i.len());
+        let (mut r, mut w) = pipe_buffered();
+        let guard = spawn(move || {
+            w.write_all(&i[..5]).unwrap();
+            w.write_all(&i[5..]).unwrap();
+            w.flush().unwrap();
+            drop(w);
+        });
+
+        r.read_to_end(&mut o).unwrap();
+        assert_eq!(i, &o[..]);
+
+        guard.join().unwrap();
+    }
+
+    #[test]
+    fn pipe_writer_fail_buffered() {
+        let i = &[0; DEFAULT_BUF_SIZE * 2];
+        let (r, mut w) = pipe_buffered();
+        let guard = spawn(move || {
+            drop(r);
+        });
+
+        assert!(w.write_all(i).is_err());
+
+        guard.join().unwrap();
+    }
+
+    #[test]
+    fn small_reads_buffered() {
+        let block_cnt = 20;
+        const BLOCK: usize = 20;
+        let (mut r, mut w) = pipe_buffered();
+        let guard = spawn(move || {
+            for _ in 0..block_cnt {
+                let data = &[0; BLOCK];
+                w.write_all(data).unwrap();
+            }
+            w.flush().unwrap();
+        });
+
+        let mut buff = [0; BLOCK / 2];
+        let mut read = 0;
+        while let Ok(size) = r.read(&mut buff) {
+            // 0 means EOF
+            if size == 0 {
+                break;
+            }
+            read += size;
+        }
+        assert_eq!(block_cnt * BLOCK, read);
+
+        guard.join().unwrap();
+    }
+
+    #[test]
+    fn buffer_pool() {
+        let pool = BufferPool::new(4);
+        let (mut r, mut w) = pipe_buffered();
+        r.set_pool(Some(pool.clone()));
+        w.set_pool(Some(pool.clone()));
+        let guard = spawn(move || {
+            for _ in 0..10 {
+                w.write_all(&[0; DEFAULT_BUF_SIZE]).unwrap();
+            }
+            w.flush().unwrap();
+        });
+
+        let mut o = Vec::new();
+        r.read_to_end(&mut o).unwrap();
+        assert_eq!(o.len(), DEFAULT_BUF_SIZE * 10);
+        guard.join().unwrap();
+
+        let stats = pool.stats();
+        assert!(stats.hits > 0);
+        assert_eq!(stats.hits + stats.misses, 10);
+        pool.shrink_to_fit();
+        assert_eq!(pool.stats().pooled, 0);
+    }
+
+    #[test]
+    fn spin_reader() {
+        for &wait in &[WaitStrategy::Spin, WaitStrategy::SpinThenPark(10)] {
+            let (mut r, mut w) = pipe();
+            r.set_wait_strategy(wait);
+            let guard = spawn(move || {
+                for _ in 0..10 {
+                    w.write_all(b"hello").unwrap();
+                }
+            });
+
+            let mut o = Vec::new();
+            r.read_to_end(&mut o).unwrap();
+            assert_eq!(o.len(), 50);
+
+            guard.join().unwrap();
+        }
+    }
+
+    #[test]
+    fn fair_writers() {
+        let (mut r, w) = pipe_fair();
+        let guards: Vec<_> = [b'a', b'b'].iter().map(|&c| {
+            let mut w = w.clone();
+            spawn(move || {
+                for _ in 0..20 {
+                    w.write_all(&[c]).unwrap();
+                }
+            })
+        }).collect();
+        drop(w);
+
+        // a slow reader gives both writers the chance to queue up
+        let mut o = Vec::new();
+        let mut buf = [0];
+        while r.read(&mut buf).unwrap() > 0 {
+            o.push(buf[0]);
+            sleep(Duration::from_millis(1));
+        }
+        assert_eq!(o.len(), 40);
+        assert!(o[2..38].windows(3).all(|w| w[0] != w[1] || w[1] != w[2]),
+            "unfair interleaving: {}", String::from_utf8_lossy(&o));
+
+        for guard in guards {
+            guard.join().unwrap();
+        }
+    }
+
+    #[test]
+    fn recv_many() {
+        let (mut r, w) = pipe();
+        let guards: Vec<_> = (0..4).map(|_| {
+            let w = w.clone();
+            spawn(move || w.send(vec![0; 10]).unwrap())
+        }).collect();
+        drop(w);
+        sleep(Duration::from_millis(10));
+
+        let mut chunks = Vec::new();
+        let mut total = 0;
+        loop {
+            match r.recv_many(&mut chunks, 3).unwrap() {
+                0 => break,
+                n => assert!(n <= 3),
+            }
+            total += 1;
+        }
+        assert_eq!(chunks.len(), 4);
+        assert!(total < 4);
+
+        for guard in guards {
+            guard.join().unwrap();
+        }
+    }
+
+    #[test]
+    fn send_vec_zero_copy() {
+        let (mut r, w) = pipe();
+        let data = vec![1; 1024];
+        let ptr = data.as_ptr() as usize;
+        let guard = spawn(move || w.send_vec(data).unwrap());
+
+        let mut chunks = Vec::new();
+        assert_eq!(r.recv_many(&mut chunks, 1).unwrap(), 1);
+        assert_eq!(chunks[0].as_ptr() as usize, ptr);
+
+        guard.join().unwrap();
+    }
+
+    #[test]
+    fn read_exact_chunks() {
+        let (mut r, mut w) = pipe();
+        let guard = spawn(move || {
+            for i in 0..10 {
+                w.write_all(&[i; 10]).unwrap();
+            }
+        });
+
+        let mut buf = [0; 16];
+        for i in 0..6 {
+            r.read_exact(&mut buf).unwrap();
+            assert!(buf.iter().enumerate().all(|(j, &b)| b as usize == (i * 16 + j) / 10));
+        }
+        assert_eq!(r.read_exact(&mut buf).unwrap_err().kind(), io::ErrorKind::UnexpectedEof);
+
+        guard.join().unwrap();
+    }
+
+    #[test]
+    fn dump_state() {
+        let (r, w) = pipe_buffered();
+        let mut w2 = w.clone();
+        w2.write_all(b"hi").unwrap();
+        assert_eq!(w2.dump_state(), PipeState {
+            name: None,
+            buffered: 2,
+            queued: 0,
+            capacity: Some(DEFAULT_BUF_SIZE),
+            readers: 1,
+            writers: 2,
+        });
+        drop(w);
+        drop(w2.into_inner());
+        assert_eq!(r.dump_state().writers, 0);
+        assert_eq!(format!("{:?}", r), "PipeReader { name: None, buffered: 0, queued: 0, block_size: None, wait: Park, writers: 0 }");
+    }
+
+    #[test]
+    fn snapshot() {
+        let (r, mut w) = pipe_buffered_named("log");
+        w.write_all(b"line\n").unwrap();
+        r.control().pause();
+        let snapshot = w.snapshot(3);
+        assert_eq!(snapshot.data, b"lin");
+        assert!(snapshot.paused);
+        assert_eq!(snapshot.to_string(), "pipe 'log': 1 readers, 1 writers, paused\n\
+            buffered: 5 bytes of 8192, queued: 0 chunks, pending: 0 bytes, reader waiting: false\n\
+            data: \"lin\" (2 more bytes)");
+        drop(w.into_inner());
+    }
+
+    #[test]
+    fn named() {
+        let (r, mut w) = pipe_named("decoder→encoder");
+        assert_eq!(r.name(), Some("decoder→encoder"));
+        assert_eq!(w.dump_state().name.as_ref().map(|name| &name[..]), Some("decoder→encoder"));
+        drop(r);
+        let err = w.write(&[0]).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::BrokenPipe);
+        assert_eq!(err.to_string(), "pipe 'decoder→encoder' reader has been dropped");
+
+        let (r, mut w) = pipe_buffered();
+        assert_eq!(w.name(), None);
+        drop(r);
+        assert_eq!(w.write(&[0]).unwrap_err().to_string(), "pipe reader has been dropped");
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn raw_os_errors() {
+        let (r, mut w) = pipe_named("raw");
+        w.set_raw_os_errors(true);
+        drop(r);
+        let err = w.write(&[0]).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::BrokenPipe);
+        assert_eq!(err.raw_os_error(), Some(32));
+    }
+
+    #[test]
+    fn send_tracked() {
+        let (mut r, w) = pipe();
+        let guard = spawn(move || {
+            let first = w.send_tracked(&b"abcd"[..]).unwrap();
+            let second = w.send_tracked(&b"ef"[..]).unwrap();
+            (first, second)
+        });
+
+        let mut buf = [0; 2];
+        r.read_exact(&mut buf).unwrap();
+        r.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"cd");
+        assert_eq!(r.fill_buf().unwrap(), b"ef");
+        let (first, second) = guard.join().unwrap();
+        assert!(first.is_consumed());
+        first.wait().unwrap();
+        assert!(!second.is_consumed());
+        assert_eq!(second.wait_timeout(Duration::from_millis(1)), Err(PipeError::TimedOut));
+
+        drop(r);
+        assert_eq!(second.wait(), Err(PipeError::Disconnected));
+    }
+
+    #[test]
+    fn flush_handoff() {
+        let (mut r, mut w) = pipe_buffered();
+        let guard = spawn(move || {
+            let start = Instant::now();
+            w.write_all(b"hi").unwrap();
+            w.flush().unwrap();
+            start.elapsed()
+        });
+
+        sleep(Duration::from_millis(50));
+        let mut buf = [0; 2];
+        r.read_exact(&mut buf).unwrap();
+        // the flush can't complete until the reader actually picks the data up
+        assert!(guard.join().unwrap() >= Duration::from_millis(50));
+    }
+
+    #[test]
+    fn read_to_end_limited() {
+        let (mut r, mut w) = pipe();
+        let guard = spawn(move || {
+            for _ in 0..10 {
+                if w.write_all(&[0; 10]).is_err() {
+                    break
+                }
+            }
+        });
+
+        let mut o = Vec::new();
+        let err = r.read_to_end_limited(&mut o, 25).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert_eq!(o.len(), 20);
+        drop(r);
+        guard.join().unwrap();
+
+        let (mut r, mut w) = pipe();
+        let guard = spawn(move || w.write_all(&[0; 10]).unwrap());
+        assert_eq!(r.read_to_end_limited(&mut o, 10).unwrap(), 10);
+        guard.join().unwrap();
+    }
+
+    #[test]
+    fn drive() {
+        let (mut r, w) = pipe();
+        let guard = spawn(move || {
+            w.send(&b"hello"[..]).unwrap();
+            sleep(Duration::from_millis(50));
+            w.send(&b"world"[..]).unwrap();
+        });
+
+        let mut o = Vec::new();
+        let mut idle = 0;
+        r.drive(|data| {
+            o.extend_from_slice(data);
+            Ok(())
+        }, || {
+            idle += 1;
+            Ok(())
+        }, Duration::from_millis(5)).unwrap();
+        guard.join().unwrap();
+        assert_eq!(o, b"helloworld");
+        assert!(idle > 0);
+
+        let (mut r, w) = pipe();
+        let guard = spawn(move || w.send(&b"stop"[..]));
+        let err = r.drive(|_| Err(io::Error::new(io::ErrorKind::InvalidData, "stop")), || Ok(()), Duration::from_secs(1)).unwrap_err();
+        assert_eq!(err.to_string(), "stop");
+        drop(r);
+        guard.join().unwrap().unwrap();
+    }
+
+    #[test]
+    fn drive_expired() {
+        let (mut r, mut w) = pipe();
+        let fresh = w.clone();
+        w.set_ttl(Some(Duration::from_millis(1)));
+        let guard = spawn(move || {
+            w.send(&b"stale"[..]).unwrap();
+            // a reader blocked in `drive()` counts as waiting
+            while !fresh.pressure().is_reader_waiting {
+                std::thread::yield_now();
+            }
+            fresh.send(&b"fresh"[..]).unwrap();
+        });
+        sleep(Duration::from_millis(20));
+
+        let mut o = Vec::new();
+        r.drive(|data| {
+            o.extend_from_slice(data);
+            Ok(())
+        }, || Ok(()), Duration::from_millis(5)).unwrap();
+        guard.join().unwrap();
+        assert_eq!(o, b"fresh");
+        assert_eq!(r.expired(), 1);
+    }
+
+    #[test]
+    fn write_atomic() {
+        let (mut r, mut w) = pipe_buffered();
+        let guard = spawn(move || {
+            w.write_all(&[1; 6000]).unwrap();
+            w.write_atomic(&[2; 6000]).unwrap();
+            w.set_atomic_writes(true);
+            w.write_all(&[3; 6000]).unwrap();
+        });
+
+        let mut chunks = Vec::new();
+        while r.recv_many(&mut chunks, 8).unwrap() > 0 { }
+        guard.join().unwrap();
+        let lens: Vec<_> = chunks.iter().map(Vec::len).collect();
+        assert_eq!(lens, [6000, 6000, 6000]);
+        assert!(chunks.iter().all(|chunk| chunk.iter().all(|&b| b == chunk[0])));
+    }
+
+    #[test]
+    fn eager_sends() {
+        let (mut r, mut w) = pipe_buffered();
+        w.set_eager_sends(false);
+        let guard = spawn(move || {
+            for b in 0..10 {
+                w.write_all(&[b]).unwrap();
+            }
+        });
+
+        let mut chunks = Vec::new();
+        while r.recv_many(&mut chunks, 8).unwrap() > 0 { }
+        guard.join().unwrap();
+        assert_eq!(chunks, [(0..10).collect::<Vec<u8>>()]);
+    }
+
+    #[test]
+    fn pressure() {
+        use std::thread::yield_now;
+
+        let (mut r, w) = pipe();
+        assert_eq!(w.pressure(), Pressure { queued_bytes: 0, is_reader_waiting: false });
+        let w2 = w.clone();
+        let guard = spawn(move || w2.send(vec![0; 5]).unwrap());
+        while w.pressure().queued_bytes != 5 {
+            yield_now();
+        }
+        let mut buf = [0; 8];
+        assert_eq!(r.read(&mut buf).unwrap(), 5);
+        guard.join().unwrap();
+        assert_eq!(w.pressure().queued_bytes, 0);
+
+        let guard = spawn(move || r.read(&mut buf).unwrap());
+        while !w.pressure().is_reader_waiting {
+            yield_now();
+        }
+        w.send(&b"x"[..]).unwrap();
+        assert_eq!(guard.join().unwrap(), 1);
+    }
+
+    #[test]
+    fn nodelay() {
+        let (mut r, mut w) = pipe_buffered();
+        w.set_eager_sends(false);
+        w.set_nodelay(true);
+        let guard = spawn(move || {
+            w.write_all(b"a").unwrap();
+            w.set_nodelay(false);
+            w.write_all(b"b").unwrap();
+            w.write_all(b"c").unwrap();
+        });
+
+        let mut chunks = Vec::new();
+        while r.recv_many(&mut chunks, 8).unwrap() > 0 { }
+        guard.join().unwrap();
+        assert_eq!(chunks, [b"a".to_vec(), b"bc".to_vec()]);
+    }
+
+    #[test]
+    fn eager_interval() {
+        use std::thread::yield_now;
+        use ManualClock;
+
+        let (mut r, mut w) = pipe_buffered();
+        w.set_clock(ManualClock::new());
+        w.set_eager_interval(Some(Duration::from_secs(1)));
+        let guard = spawn(move || {
+            let mut chunks = Vec::new();
+            while r.recv_many(&mut chunks, 8).unwrap() > 0 { }
+            chunks
+        });
+        while !w.pressure().is_reader_waiting {
+            yield_now();
+        }
+
+        // the reader is waiting, but no time has passed since the writer was created
+        w.write_all(b"a").unwrap();
+        w.write_all(b"b").unwrap();
+        assert_eq!(w.buffer(), b"ab");
+        w.flush().unwrap();
+        drop(w);
+        assert_eq!(guard.join().unwrap(), [b"ab".to_vec()]);
+    }
+
+    #[test]
+    fn large_write() {
+        let (mut r, mut w) = pipe_buffered();
+        w.set_eager_sends(false);
+        let size = w.capacity();
+        let guard = spawn(move || {
+            w.write_all(b"ab").unwrap();
+            w.write_all(&vec![1; size + 1]).unwrap();
+            assert!(w.buffer().is_empty());
+            assert!(w.buffer.capacity() <= size);
+        });
+
+        let mut chunks = Vec::new();
+        while r.recv_many(&mut chunks, 8).unwrap() > 0 { }
+        guard.join().unwrap();
+        assert_eq!(chunks.iter().map(Vec::len).collect::<Vec<_>>(), [2, size + 1]);
+    }
+
+    #[test]
+    fn ttl() {
+        let (mut r, mut w) = pipe();
+        let guard = spawn(move || {
+            w.send_chunk(Chunk::from(&b"stale"[..]).with_expiry(Instant::now())).unwrap();
+            w.set_ttl(Some(Duration::from_secs(60)));
+            w.send(&b"fresh"[..]).unwrap();
+        });
+
+        let mut data = Vec::new();
+        r.read_to_end(&mut data).unwrap();
+        guard.join().unwrap();
+        assert_eq!(data, b"fresh");
+        assert_eq!(r.expired(), 1);
+    }
+
+    #[test]
+    fn ttl_clock() {
+        use ManualClock;
+
+        let clock = ManualClock::new();
+        let (mut r, mut w) = pipe();
+        r.set_clock(clock.clone());
+        w.set_clock(clock.clone());
+        w.set_ttl(Some(Duration::from_secs(1)));
+        let probe = w.clone();
+        let guard = spawn(move || {
+            w.send(&b"stale"[..]).unwrap();
+            w.send(&b"fresh"[..]).unwrap();
+        });
+        // the first chunk is stamped before it is queued
+        while probe.pressure().queued_bytes == 0 {
+            std::thread::yield_now();
+        }
+        drop(probe);
+        clock.advance(Duration::from_secs(2));
+
+        let mut data = Vec::new();
+        r.read_to_end(&mut data).unwrap();
+        guard.join().unwrap();
+        assert_eq!(data, b"fresh");
+        assert_eq!(r.expired(), 1);
+    }
+
+    #[test]
+    fn flush_group() {
+        let (mut r1, mut w1) = pipe_buffered();
+        let (r2, mut w2) = pipe_buffered();
+        let (mut r3, mut w3) = pipe_buffered();
+        for w in [&mut w1, &mut w2, &mut w3] {
+            w.set_eager_sends(false);
+            w.write_all(b"data").unwrap();
+        }
+        drop(r2);
+        let readers = [spawn(move || r1.read_exact(&mut [0; 4])), spawn(move || r3.read_exact(&mut [0; 4]))];
+
+        let errors = PipeBufWriter::flush_group(&mut [&mut w1, &mut w2, &mut w3]).unwrap_err();
+        assert_eq!(errors.iter().map(|(index, e)| (*index, e.kind())).collect::<Vec<_>>(), [(1, io::ErrorKind::BrokenPipe)]);
+        assert!(w1.buffer().is_empty() && w3.buffer().is_empty());
+        assert_eq!(w2.buffer(), b"data");
+        for reader in readers {
+            reader.join().unwrap().unwrap();
+        }
+        drop(w2.into_inner());
+    }
+
+    #[test]
+    fn weak_writer() {
+        let (mut r, w) = pipe();
+        let weak = w.downgrade();
+        let guard = spawn(move || {
+            weak.upgrade().unwrap().send(&b"hello"[..]).unwrap();
+            drop(w);
+            assert!(weak.upgrade().is_none());
+        });
+
+        let mut data = Vec::new();
+        r.read_to_end(&mut data).unwrap();
+        assert_eq!(data, b"hello");
+        guard.join().unwrap();
+    }
+
+    #[test]
+    fn drop_timeout() {
+        let (r, mut w) = pipe_buffered();
+        let abandoned = Arc::new(Mutex::new(Vec::new()));
+        let hook = abandoned.clone();
+        w.set_eager_sends(false);
+        w.set_drop_timeout(Some(Duration::from_millis(10)));
+        w.set_abandon_hook(Some(Arc::new(move |data| hook.lock().unwrap().extend_from_slice(data))));
+        w.write_all(b"stuck").unwrap();
+
+        // the reader is alive but never reads
+        drop(w);
+        assert_eq!(*abandoned.lock().unwrap(), b"stuck");
+        drop(r);
+    }
+
+    #[test]
+    fn min_read_size() {
+        let (mut r, w) = pipe();
+        r.set_min_read_size(Some(4));
+        r.set_min_read_timeout(Some(Duration::from_secs(10)));
+        let guard = spawn(move || {
+            for &b in b"abcdefg" {
+                w.send(vec![b]).unwrap();
+            }
+        });
+
+        let mut buf = [0; 16];
+        assert_eq!(r.read(&mut buf).unwrap(), 4);
+        assert_eq!(&buf[..4], b"abcd");
+        // the rest falls short of the minimum once the writer is gone
+        assert_eq!(r.read(&mut buf).unwrap(), 3);
+        assert_eq!(&buf[..3], b"efg");
+        guard.join().unwrap();
+    }
+
+    #[test]
+    fn checksums() {
+        let (mut r, mut w) = pipe();
+        w.set_checksums(true);
+        let guard = spawn(move || {
+            w.write_all(b"intact").unwrap();
+            w.clone().send(vec![1; 100]).unwrap();
+        });
+
+        let mut chunks = Vec::new();
+        while r.recv_many(&mut chunks, 8).unwrap() > 0 { }
+        guard.join().unwrap();
+        assert_eq!(chunks, [b"intact".to_vec(), vec![1; 100]]);
+    }
+
+    #[test]
+    fn events() {
+        let (r, w) = pipe();
+        let events = w.events();
+        let w2 = w.clone();
+        drop(w);
+        assert_eq!(events.try_recv(), Some(PipeEvent::WriterDropped));
+        assert_eq!(events.try_recv(), None);
+        drop(w2);
+        drop(r);
+        assert_eq!(events.collect::<Vec<_>>(), [PipeEvent::WriterDropped, PipeEvent::Finished, PipeEvent::ReaderDropped]);
+    }
+
+    #[test]
+    fn last_flush() {
+        let (mut r, mut w) = pipe_buffered();
+        w.set_eager_sends(false);
+        let events = w.events();
+        let created = w.last_flush();
+        w.write_all(b"hello").unwrap();
+        assert_eq!(w.last_flush(), created);
+
+        let guard = spawn(move || {
+            w.flush().unwrap();
+            assert!(w.last_flush() > created);
+        });
+        let mut buf = [0; 5];
+        r.read_exact(&mut buf).unwrap();
+        guard.join().unwrap();
+        assert_eq!(events.try_recv(), Some(PipeEvent::Flushed));
+    }
+
+    #[test]
+    fn drop_flush_chunk() {
+        let (mut r, mut w) = pipe_buffered();
+        w.set_eager_sends(false);
+        w.set_sequenced(true);
+        let events = w.events();
+        w.write_all(b"tail").unwrap();
+        let guard = spawn(move || drop(w));
+
+        // the final flush numbers the chunk like any other
+        let chunk = r.recv_chunk().unwrap().unwrap();
+        assert_eq!(&chunk[..], b"tail");
+        assert!(chunk.sequence().is_some());
+        guard.join().unwrap();
+        assert_eq!(events.try_recv(), Some(PipeEvent::Flushed));
+    }
+
+    #[test]
+    fn retain_limit() {
+        let (mut r, w) = pipe();
+        r.set_retain_buffer(true);
+        r.set_retain_limit(Some(64));
+        let guard = spawn(move || {
+            w.send(vec![1; 32]).unwrap();
+            w.send(vec![2; 4096]).unwrap();
+            w.send(vec![3; 48]).unwrap();
+        });
+
+        let mut buf = vec![0; 4096];
+        r.read_exact(&mut buf[..32]).unwrap();
+        r.read_exact(&mut buf).unwrap();
+        assert!(buf.iter().all(|&b| b == 2));
+        assert_eq!(r.fill_buf().unwrap(), &[3; 48][..]);
+        assert!(r.buffer.make_vec().capacity() <= 64);
+        guard.join().unwrap();
+    }
+
+    #[test]
+    fn retain_buffer() {
+        let (mut r, w) = pipe();
+        r.set_retain_buffer(true);
+        r.reserve(64);
+        let ptr = r.buffer.as_ptr() as usize;
+        let guard = spawn(move || {
+            w.send(vec![1; 32]).unwrap();
+            w.send(vec![2; 48]).unwrap();
+        });
+
+        let mut buf = [0; 32];
+        r.read_exact(&mut buf[..16]).unwrap();
+        assert_eq!(r.buffer.as_ptr() as usize, ptr);
+        let mut data = Vec::new();
+        r.read_to_end(&mut data).unwrap();
+        assert_eq!(data.len(), 16 + 48);
+        assert_eq!(r.buffer.as_ptr() as usize, ptr);
+        guard.join().unwrap();
+    }
+
+    #[test]
+    fn cloned_writer_eof() {
+        let (mut r, w) = pipe();
+        let w2 = w.clone();
+        assert_eq!(r.writer_count(), 2);
+        drop(w);
+        assert_eq!(r.writer_count(), 1);
+        let guard = spawn(move || w2.send(b"late").unwrap());
+
+        let mut data = Vec::new();
+        r.read_to_end(&mut data).unwrap();
+        assert_eq!(data, b"late");
+        assert_eq!(r.writer_count(), 0);
+        guard.join().unwrap();
+    }
+
+    #[test]
+    fn disconnect_writers() {
+        let (mut r, mut w) = pipe();
+        let w2 = w.clone();
+        assert!(w.reader_alive());
+        r.disconnect_writers();
+        assert!(!w2.reader_alive());
+        assert_eq!(w.write(b"hi").unwrap_err().kind(), io::ErrorKind::BrokenPipe);
+
+        let mut data = Vec::new();
+        r.read_to_end(&mut data).unwrap();
+        assert!(data.is_empty());
+        assert_eq!(r.writer_count(), 2);
+    }
+
+    #[test]
+    fn disconnect_writers_blocked() {
+        for _ in 0..200 {
+            let (mut r, w) = pipe();
+            let guards: Vec<_> = (0..4).map(|_| {
+                let w = w.clone();
+                spawn(move || while w.send(b"data").is_ok() { })
+            }).collect();
+            drop(w);
+            for _ in 0..16 {
+                r.recv_chunk().unwrap().unwrap();
+            }
+            r.disconnect_writers();
+            // the reader never reads again, so the writers must be woken up by the disconnect
+            for guard in guards {
+                guard.join().unwrap();
+            }
+        }
+    }
+
+    #[test]
+    fn send_static() {
+        static KEEPALIVE: &[u8] = b"ping";
+        let (mut r, w) = pipe();
+        let guard = spawn(move || w.send_static(KEEPALIVE).unwrap());
+        let chunk = r.recv_chunk().unwrap().unwrap();
+        assert!(chunk.is_static());
+        assert_eq!(chunk.as_ptr(), KEEPALIVE.as_ptr());
+        guard.join().unwrap();
+    }
+
+    #[test]
+    fn position() {
+        let (mut r, w) = pipe();
+        let guard = spawn(move || {
+            w.send(b"abcd").unwrap();
+            w.send(b"efgh").unwrap();
+            w.send(b"ijkl").unwrap();
+        });
+
+        let mut buf = [0; 3];
+        r.read_exact(&mut buf).unwrap();
+        assert_eq!(r.position(), 3);
+        r.set_position(0);
+        assert_eq!(r.read_exact_vec(5).unwrap(), b"defgh");
+        assert_eq!(r.position(), 5);
+        r.recv_chunk().unwrap();
+        assert_eq!(r.position(), 9);
+        guard.join().unwrap();
+    }
+
+    #[test]
+    fn read_until_with() {
+        let (mut r, w) = pipe();
+        let guard = spawn(move || {
+            w.send(b"one;;tw").unwrap();
+            w.send(b"o;").unwrap();
+            w.send(b";thr").unwrap();
+        });
+
+        let find = |data: &[u8]| data.windows(2).position(|w| w == b";;").map(|pos| pos + 2);
+        assert_eq!(r.read_until_with(find).unwrap().unwrap(), b"one;;");
+        assert_eq!(r.read_until_with(find).unwrap().unwrap(), b"two;;");
+        assert_eq!(r.read_until_with(find).unwrap_err().kind(), io::ErrorKind::UnexpectedEof);
+        assert_eq!(r.buffer(), b"thr");
+        assert_eq!(r.position(), 10);
+        guard.join().unwrap();
+    }
+
+    #[test]
+    fn read_until_with_past_end() {
+        let (mut r, w) = pipe();
+        let guard = spawn(move || {
+            w.send(b"ab").unwrap();
+            w.send(b"cd").unwrap();
+        });
+
+        let mut calls = 0;
+        let err = r.read_until_with(|data: &[u8]| {
+            calls += 1;
+            if calls == 2 { Some(data.len() + 1) } else { None }
+        }).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        assert_eq!(r.buffer(), b"abcd");
+        let mut o = Vec::new();
+        r.read_to_end(&mut o).unwrap();
+        assert_eq!(o, b"abcd");
+        guard.join().unwrap();
+    }
+
+    #[test]
+    fn as_chunks() {
+        let (mut r, w) = pipe();
+        let guard = spawn(move || {
+            w.send(b"head").unwrap();
+            w.send_vec(vec![1; 256]).unwrap();
+            w.send(b"tail").unwrap();
+        });
+
+        let mut head = [0; 2];
+        r.read_exact(&mut head).unwrap();
+        {
+            let mut chunks = r.as_chunks();
+            assert_eq!(&chunks.next().unwrap().unwrap()[..], b"ad");
+            assert_eq!(chunks.next().unwrap().unwrap().len(), 256);
+            let mut tail = chunks.next().unwrap().unwrap();
+            chunks.put_back(tail.slice(2..4));
+            chunks.put_back(tail.slice(0..2));
+        }
+
+        let mut o = String::new();
+        r.read_to_string(&mut o).unwrap();
+        assert_eq!(o, "tail");
+        assert_eq!(r.position(), 2 + 2 + 256 + 4);
+        guard.join().unwrap();
+    }
+
+    #[test]
+    fn read_exact_vec() {
+        let (mut r, w) = pipe();
+        let payload = vec![1; 256];
+        let ptr = payload.as_ptr() as usize;
+        let guard = spawn(move || {
+            w.send(b"abcd").unwrap();
+            w.send_vec(payload).unwrap();
+            w.send(b"ef").unwrap();
+        });
+
+        assert_eq!(r.read_exact_vec(2).unwrap(), b"ab");
+        assert_eq!(r.read_exact_vec(2).unwrap(), b"cd");
+        let data = r.read_exact_vec(256).unwrap();
+        assert_eq!(data.as_ptr() as usize, ptr);
+        assert_eq!(r.read_exact_vec(1).unwrap(), b"e");
+        assert_eq!(r.read_exact_vec(2).unwrap_err().kind(), io::ErrorKind::UnexpectedEof);
+        guard.join().unwrap();
+    }
+
+    #[test]
+    fn read_exact_or_eof() {
+        let (mut r, w) = pipe();
+        let guard = spawn(move || {
+            w.send(b"abc").unwrap();
+            w.send(b"defg").unwrap();
+        });
+
+        let mut buf = [0; 4];
+        assert_eq!(r.read_exact_or_eof(&mut buf).unwrap(), 4);
+        assert_eq!(&buf, b"abcd");
+        assert_eq!(r.read_exact_or_eof(&mut buf).unwrap(), 3);
+        assert_eq!(&buf[..3], b"efg");
+        assert_eq!(r.read_exact_or_eof(&mut buf).unwrap(), 0);
+        guard.join().unwrap();
+    }
+
+    #[test]
+    fn try_consume() {
+        let (mut r, w) = pipe();
+        let guard = spawn(move || w.send(b"abc").unwrap());
+        assert_eq!(r.fill_buf().unwrap(), b"abc");
+        assert_eq!(r.try_consume(4).unwrap_err().kind(), io::ErrorKind::InvalidInput);
+        r.try_consume(1).unwrap();
+        assert_eq!(r.buffer(), b"bc");
+        guard.join().unwrap();
+    }
+
+    #[test]
+    fn with_data() {
+        let (mut r, w) = pipe();
+        let guard = spawn(move || w.send(b"abcd").unwrap());
+        assert_eq!(r.with_data(3, |data| {
+            assert_eq!(data, b"abc");
+            2
+        }).unwrap(), 2);
+        assert_eq!(r.buffer(), b"cd");
+        guard.join().unwrap();
+        assert_eq!(r.with_data(8, |data| data.len()).unwrap(), 2);
+        assert_eq!(r.with_data(8, |data| data.len()).unwrap(), 0);
+    }
+
+    #[test]
+    #[should_panic(expected = "consumed 4 bytes with only 3 available")]
+    fn consume_overrun() {
+        let (mut r, w) = pipe();
+        spawn(move || w.send(b"abc").unwrap());
+        r.fill_buf().unwrap();
+        r.consume(4);
+    }
+
+    #[test]
+    fn preload() {
+        let (mut r, w) = pipe();
+        r.preload(b"hello ".to_vec());
+        assert_eq!(r.position(), 0);
+        let guard = spawn(move || w.send(&b"world"[..]).unwrap());
+        let mut o = String::new();
+        r.read_to_string(&mut o).unwrap();
+        assert_eq!(o, "hello world");
+        assert_eq!(r.position(), 11);
+        guard.join().unwrap();
+    }
+
+    #[test]
+    fn empty_writes() {
+        let (mut r, mut w) = pipe();
+        let mut quiet = w.clone();
+        quiet.set_empty_writes(EmptyWrites::Suppress);
+        let guard = spawn(move || {
+            assert_eq!(w.write(b"").unwrap(), 0);
+            assert_eq!(quiet.write(b"").unwrap(), 0);
+            quiet.send_vec(Vec::new()).unwrap();
+            quiet.send_chunk(Chunk::new().with_flags(1)).unwrap();
+            quiet.write_all(b"a").unwrap();
+        });
+
+        // only the chunks that weren't suppressed are received
+        assert!(r.recv_chunk().unwrap().unwrap().is_empty());
+        assert_eq!(r.recv_chunk().unwrap().unwrap().flags(), 1);
+        assert_eq!(&r.recv_chunk().unwrap().unwrap()[..], b"a");
+        guard.join().unwrap();
+    }
+
+    #[test]
+    fn validator() {
+        let (mut r, mut w) = pipe();
+        w.set_validator(Some(Arc::new(|data: &[u8]| match data.len() <= 4 {
+            true => Ok(()),
+            false => Err(io::Error::new(io::ErrorKind::InvalidData, "frame too large")),
+        })));
+        assert_eq!(w.send(&b"too long"[..]), Err(PipeError::Rejected));
+        assert_eq!(w.write(b"too long").unwrap_err().kind(), io::ErrorKind::InvalidData);
+
+        // writes are validated before they are coalesced
+        w.set_coalesce_window(Some(Duration::from_secs(10)));
+        let guard = spawn(move || {
+            w.write_all(b"abcd").unwrap();
+            w.write_all(b"efgh").unwrap();
+        });
+        let mut o = Vec::new();
+        r.read_to_end(&mut o).unwrap();
+        assert_eq!(o, b"abcdefgh");
+        guard.join().unwrap();
+    }
+
+    #[test]
+    fn sequence_gaps() {
+        let (mut r, mut w) = pipe();
+        w.set_sequenced(true);
+        let guard = spawn(move || {
+            w.send(b"a").unwrap();
+            w.clone().send(b"b").unwrap();
+            // as if chunks 3 and 4 had been dropped on the way
+            w.send_chunk(Chunk::from(&b"e"[..]).with_sequence(5)).unwrap();
+        });
+
+        let mut data = String::new();
+        r.read_to_string(&mut data).unwrap();
+        guard.join().unwrap();
+        assert_eq!(data, "abe");
+        assert_eq!((r.sequence(), r.skipped()), (5, 2));
+    }
+
+    #[test]
+    fn for_each_chunk() {
+        let (mut r, w) = pipe();
+        let data = vec![1; 256];
+        let ptr = data.as_ptr() as usize;
+        let guard = spawn(move || {
+            w.send(b"abc").unwrap();
+            w.send_vec(data).unwrap();
+        });
+
+        let mut chunks = Vec::new();
+        let len = r.for_each_chunk(|chunk| chunks.push(chunk)).unwrap();
+        assert_eq!(len, 3 + 256);
+        assert_eq!(chunks[0], b"abc");
+        assert_eq!(chunks[1].as_ptr() as usize, ptr);
+        guard.join().unwrap();
+    }
+
+    #[test]
+    fn chunk_metadata() {
+        let (mut r, w) = pipe();
+        let guard = spawn(move || {
+            w.send_chunk(Chunk::from(&b"hello"[..]).with_flags(1).with_sequence(5)).unwrap();
+        });
+
+        let mut buf = [0; 2];
+        r.read_exact(&mut buf).unwrap();
+        let chunk = r.recv_chunk().unwrap().unwrap();
+        assert_eq!(&chunk[..], b"llo");
+        assert_eq!((chunk.flags(), chunk.sequence()), (1, Some(5)));
+        assert_eq!(r.recv_chunk().unwrap(), None);
+        guard.join().unwrap();
+    }
+
+    #[test]
+    fn bytes_iter() {
+        let (mut r, w) = pipe();
+        let guard = spawn(move || {
+            w.send(b"hello").unwrap();
+            w.send(b" world").unwrap();
+        });
+
+        let data: io::Result<Vec<u8>> = r.bytes_iter().take(7).collect();
+        assert_eq!(data.unwrap(), b"hello w");
+        assert_eq!(r.buffer(), b"orld");
+        let data: io::Result<Vec<u8>> = r.bytes_iter().collect();
+        assert_eq!(data.unwrap(), b"orld");
+        guard.join().unwrap();
+    }
+
+    #[test]
+    fn writer_quota() {
+        let (mut r, mut w) = pipe();
+        w.set_quota(Some(Quota::new(10, Duration::from_secs(60), QuotaPolicy::Error)));
+        let w2 = w.clone();
+        assert_ne!(w.id(), w2.id());
+        let guard = spawn(move || {
+            w.write_all(&[0; 8]).unwrap();
+            let err = w.write_all(&[0; 8]).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::WouldBlock);
+            w2.send(vec![1; 8]).unwrap();
+            assert_eq!(w2.send(vec![1; 8]), Err(PipeError::QuotaExceeded));
+            (w.written(), w2.written())
+        });
+
+        let mut o = Vec::new();
+        r.read_to_end(&mut o).unwrap();
+        assert_eq!(guard.join().unwrap(), (8, 8));
+        assert_eq!(o.len(), 16);
+
+        let (mut r, mut w) = pipe();
+        w.set_quota(Some(Quota::new(10, Duration::from_millis(20), QuotaPolicy::Block)));
+        let guard = spawn(move || {
+            let start = Instant::now();
+            for _ in 0..3 {
+                w.write_all(&[0; 10]).unwrap();
+            }
+            start.elapsed()
+        });
+        r.read_to_end(&mut o).unwrap();
+        assert!(guard.join().unwrap() >= Duration::from_millis(40));
+    }
+
+    #[test]
+    fn pause() {
+        let (mut r, mut w) = pipe();
+        let control = w.control();
+        control.pause();
+        assert!(r.control().is_paused());
+
+        let (tx, rx) = crossbeam_channel::unbounded();
+        let reader = spawn(move || {
+            let mut buf = [0; 2];
+            r.read_exact(&mut buf).unwrap();
+            tx.send(buf).unwrap();
+        });
+        let writer = spawn(move || w.write_all(b"hi").unwrap());
+
+        sleep(Duration::from_millis(30));
+        assert!(rx.try_recv().is_err());
+        control.resume();
+        assert_eq!(&rx.recv().unwrap(), b"hi");
+        reader.join().unwrap();
+        writer.join().unwrap();
+    }
+
+    #[test]
+    fn try_read() {
+        let (mut r, w) = pipe();
+        let mut buf = [0; 4];
+        assert_eq!(r.try_read(&mut buf).unwrap_err().kind(), io::ErrorKind::WouldBlock);
+
+        let guard = spawn(move || w.send(&b"hello"[..]).unwrap());
+        let len = loop {
+            match r.try_read(&mut buf) {
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => sleep(Duration::from_millis(1)),
+                res => break res.unwrap(),
+            }
+        };
+        assert_eq!(&buf[..len], b"hell");
+        guard.join().unwrap();
+        assert_eq!(r.try_read(&mut buf).unwrap(), 1);
+        assert_eq!(r.try_read(&mut buf).unwrap(), 0);
+    }
+
+    #[cfg(all(feature = "readiness", unix))]
+    #[test]
+    fn readiness_fd() {
+        use libc;
+
+        let (mut r, mut w) = pipe();
+        let fd = r.readiness_fd().unwrap();
+        let guard = spawn(move || {
+            for _ in 0..3 {
+                w.write_all(b"hi").unwrap();
+            }
+        });
+
+        let mut o = Vec::new();
+        loop {
+            let mut poll = libc::pollfd { fd, events: libc::POLLIN, revents: 0 };
+            assert_eq!(unsafe { libc::poll(&mut poll, 1, 5000) }, 1);
+            let mut buf = [0; 16];
+            match r.try_read(&mut buf) {
+                Ok(0) => break,
+                Ok(len) => o.extend_from_slice(&buf[..len]),
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => (),
+                Err(e) => panic!("{}", e),
+            }
+        }
+        assert_eq!(o, b"hihihi");
+        guard.join().unwrap();
+    }
+
+    #[test]
+    fn block_size() {
+        let (mut r, mut w) = pipe();
+        let guard = spawn(move || {
+            for i in 0..10 {
+                w.write_all(&[i; 7]).unwrap();
+            }
+        });
+
+        r.set_block_size(Some(16));
+        let mut sizes = Vec::new();
+        let mut o = Vec::new();
+        loop {
+            let len = {
+                let block = r.fill_buf().unwrap();
+                o.extend_from_slice(block);
+                block.len()
+            };
+            if len == 0 {
+                break
+            }
+            sizes.push(len);
+            r.consume(len);
+        }
+        assert_eq!(sizes, [16, 16, 16, 16, 6]);
+        assert_eq!(o.len(), 70);
+        assert!(o.chunks(7).enumerate().all(|(i, c)| c.iter().all(|&b| b == i as u8)));
+
+        guard.join().unwrap();
+    }
+}
@@ -0,0 +1,32 @@
+use std::io;
+
+/// A point in the stream of a spooled or history pipe that a reader can resume from (see
+/// `SpooledPipeReader::checkpoint()` and `HistoryPipeReader::checkpoint()`)
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Checkpoint {
+    pipe: usize,
+    offset: u64,
+}
+
+impl Checkpoint {
+    pub(crate) fn new(pipe: usize, offset: u64) -> Self {
+        Checkpoint { pipe, offset }
+    }
+
+    /// Returns the offset in the stream, counting every byte ever read from the pipe
+    pub fn offset(&self) -> u64 {
+        self.offset
+    }
+
+    /// Checks that the checkpoint was taken on the pipe identified by `pipe`
+    pub(crate) fn check(&self, pipe: usize) -> io::Result<u64> {
+        match self.pipe == pipe {
+            true => Ok(self.offset),
+            false => Err(invalid("checkpoint belongs to another pipe")),
+        }
+    }
+}
+
+pub(crate) fn invalid(msg: &'static str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, msg)
+}
@@ -0,0 +1,392 @@
+use std::fmt;
+use std::mem::{replace, take};
+use std::ops::{Deref, Range};
+use std::sync::Arc;
+use std::time::Instant;
+use checksum::crc32;
+use receipt::Receipt;
+use PipeError;
+
+const INLINE_CAPACITY: usize = 64;
+
+#[derive(Clone)]
+enum Data {
+    Inline(u8, [u8; INLINE_CAPACITY]),
+    Heap(Vec<u8>),
+    /// A range of an allocation shared with other chunks
+    Shared(Arc<Vec<u8>>, usize, usize),
+    Static(&'static [u8]),
+}
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+struct Metadata {
+    flags: u32,
+    timestamp: Option<Instant>,
+    sequence: Option<u64>,
+    barrier: Option<u64>,
+    expiry: Option<Instant>,
+    compressed: bool,
+}
+
+/// A chunk of data as passed from a writer to a reader.
+///
+/// Chunks of up to `Chunk::INLINE_CAPACITY` bytes copied from a slice are stored inline, so that
+/// small writes don't need a heap allocation. Chunks created from a `Vec<u8>` take ownership of
+/// it without copying, and static data is only ever borrowed (see `from_static()`).
+///
+/// A chunk can also carry metadata from the writer to the reader, such as a timestamp, and can be
+/// split into slices that share its data (see `slice()`). Send one with `PipeWriter::send_chunk()`
+/// and receive it whole with `PipeReader::recv_chunk()`.
+pub struct Chunk {
+    data: Data,
+    receipt: Option<Receipt>,
+    checksum: Option<u32>,
+    meta: Metadata,
+}
+
+impl Chunk {
+    /// The largest slice that is stored without a heap allocation
+    pub const INLINE_CAPACITY: usize = INLINE_CAPACITY;
+
+    /// Creates an empty chunk
+    pub fn new() -> Self {
+        Chunk::with_data(Data::Inline(0, [0; INLINE_CAPACITY]))
+    }
+
+    /// Creates a chunk that borrows `data` rather than copying it, however large it is
+    pub fn from_static(data: &'static [u8]) -> Self {
+        Chunk::with_data(Data::Static(data))
+    }
+
+    fn with_data(data: Data) -> Self {
+        Chunk { data, receipt: None, checksum: None, meta: Metadata::default() }
+    }
+
+    /// Attaches application-defined flags to the chunk
+    pub fn with_flags(mut self, flags: u32) -> Self {
+        self.meta.flags = flags;
+        self
+    }
+
+    /// Returns the flags attached with `with_flags()`, or `0`
+    pub fn flags(&self) -> u32 {
+        self.meta.flags
+    }
+
+    /// Attaches a timestamp to the chunk, such as when its data was produced
+    pub fn with_timestamp(mut self, timestamp: Instant) -> Self {
+        self.meta.timestamp = Some(timestamp);
+        self
+    }
+
+    /// Returns the timestamp attached with `with_timestamp()`, if any
+    pub fn timestamp(&self) -> Option<Instant> {
+        self.meta.timestamp
+    }
+
+    /// Attaches a sequence number to the chunk
+    pub fn with_sequence(mut self, sequence: u64) -> Self {
+        self.meta.sequence = Some(sequence);
+        self
+    }
+
+    /// Returns the sequence number attached with `with_sequence()`, if any
+    pub fn sequence(&self) -> Option<u64> {
+        self.meta.sequence
+    }
+
+    /// Makes the chunk expire at `expiry`, after which a reader drops it rather than delivering it
+    /// late (see `PipeReader::expired()`)
+    pub fn with_expiry(mut self, expiry: Instant) -> Self {
+        self.meta.expiry = Some(expiry);
+        self
+    }
+
+    /// Returns when the chunk expires, if it was given an expiry with `with_expiry()`
+    pub fn expiry(&self) -> Option<Instant> {
+        self.meta.expiry
+    }
+
+    /// Returns `true` if the chunk has expired
+    pub fn is_expired(&self) -> bool {
+        self.meta.expiry.is_some_and(|expiry| expiry <= Instant::now())
+    }
+
+    /// Turns the chunk into a marker for the given epoch of a `PipeBarrier`
+    pub(crate) fn with_barrier(mut self, epoch: u64) -> Self {
+        self.meta.barrier = Some(epoch);
+        self
+    }
+
+    /// Returns the epoch if the chunk is a marker inserted by `PipeBarrier::insert()`
+    ///
+    /// Markers carry no data of their own, and only show up as chunks to readers that receive
+    /// whole chunks (see `PipeReader::recv_chunk()`).
+    pub fn barrier(&self) -> Option<u64> {
+        self.meta.barrier
+    }
+
+    /// Returns `true` if the chunk's data is still compressed by the writer that sent it (see
+    /// `PipeWriter::set_compression()`)
+    pub fn is_compressed(&self) -> bool {
+        self.meta.compressed
+    }
+
+    /// Replaces the chunk's data with a compressed or decompressed copy, keeping its metadata
+    #[cfg(feature = "zstd")]
+    pub(crate) fn replace_data(&mut self, data: Vec<u8>, compressed: bool) {
+        self.data = Data::Heap(data);
+        self.checksum = None;
+        self.meta.compressed = compressed;
+    }
+
+    pub(crate) fn with_receipt(mut self, receipt: Receipt) -> Self {
+        self.receipt = Some(receipt);
+        self
+    }
+
+    /// Attaches a checksum of the chunk's current data, to be verified by the reader
+    pub(crate) fn with_checksum(mut self) -> Self {
+        self.checksum = Some(crc32(self.as_slice()));
+        self
+    }
+
+    /// Checks the chunk's data against the checksum it was sent with, if it has one (see
+    /// `PipeWriter::set_checksums()`)
+    pub fn verify(&self) -> Result<(), PipeError> {
+        match self.checksum {
+            Some(checksum) if checksum != crc32(self.as_slice()) => Err(PipeError::Corrupted),
+            _ => Ok(()),
+        }
+    }
+
+    /// Resolves the chunk's `SendHandle`, if any, as consumed
+    pub(crate) fn acknowledge(&mut self) {
+        if let Some(receipt) = self.receipt.take() {
+            receipt.acknowledge();
+        }
+    }
+
+    /// Returns the contents of the chunk
+    pub fn as_slice(&self) -> &[u8] {
+        match &self.data {
+            Data::Inline(len, data) => &data[..*len as usize],
+            Data::Heap(data) => data,
+            Data::Shared(data, start, end) => &data[*start..*end],
+            Data::Static(data) => data,
+        }
+    }
+
+    /// Returns a chunk holding `range` of this chunk's data, along with its metadata
+    ///
+    /// The first slice moves the data into an allocation shared by reference counting, so slicing
+    /// never copies heap data, and neither does cloning this chunk or its slices from then on.
+    /// Inline data is small enough to be copied instead.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `range` is out of bounds.
+    pub fn slice(&mut self, range: Range<usize>) -> Chunk {
+        assert!(range.start <= range.end && range.end <= self.len(), "chunk slice {:?} out of bounds for length {}", range, self.len());
+        if let Data::Heap(data) = &mut self.data {
+            let data = take(data);
+            let len = data.len();
+            self.data = Data::Shared(Arc::new(data), 0, len);
+        }
+
+        let mut chunk = match &self.data {
+            Data::Shared(data, start, _) => Chunk::with_data(Data::Shared(data.clone(), start + range.start, start + range.end)),
+            Data::Static(data) => Chunk::from_static(&data[range]),
+            _ => Chunk::from(&self[range]),
+        };
+        chunk.meta = self.meta;
+        chunk
+    }
+
+    /// Returns `true` if the chunk's data is stored inline rather than in a heap allocation
+    pub fn is_inline(&self) -> bool {
+        matches!(self.data, Data::Inline(..))
+    }
+
+    /// Returns `true` if the chunk borrows static data (see `from_static()`)
+    pub fn is_static(&self) -> bool {
+        matches!(self.data, Data::Static(..))
+    }
+
+    /// Converts the chunk into a `Vec<u8>`
+    ///
+    /// Inline and static data is copied, as is shared data while other chunks still share it (see
+    /// `slice()`): a chunk created from a `Vec<u8>` returns that same allocation.
+    pub fn into_vec(self) -> Vec<u8> {
+        match self.data {
+            Data::Inline(len, data) => data[..len as usize].to_vec(),
+            Data::Heap(data) => data,
+            Data::Static(data) => data.to_vec(),
+            Data::Shared(data, start, end) => match Arc::try_unwrap(data) {
+                Ok(mut data) => {
+                    data.truncate(end);
+                    data.drain(..start);
+                    data
+                },
+                Err(data) => data[start..end].to_vec(),
+            },
+        }
+    }
+
+    /// Gets mutable access to the chunk as a `Vec<u8>`, moving any inline or shared data to a
+    /// heap allocation of its own
+    pub(crate) fn make_vec(&mut self) -> &mut Vec<u8> {
+        // the data is about to change
+        self.checksum = None;
+        if !matches!(self.data, Data::Heap(..)) {
+            let data = replace(&mut self.data, Data::Heap(Vec::new()));
+            self.data = Data::Heap(Chunk::with_data(data).into_vec());
+        }
+
+        match &mut self.data {
+            Data::Heap(data) => data,
+            _ => unreachable!(),
+        }
+    }
+}
+
+/// Clones the data and metadata, but the copy isn't tracked by any `SendHandle`. Shared data is
+/// only referenced again rather than copied (see `slice()`).
+impl Clone for Chunk {
+    fn clone(&self) -> Self {
+        Chunk { data: self.data.clone(), receipt: None, checksum: self.checksum, meta: self.meta }
+    }
+}
+
+impl Default for Chunk {
+    fn default() -> Self {
+        Chunk::new()
+    }
+}
+
+impl Deref for Chunk {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        self.as_slice()
+    }
+}
+
+impl AsRef<[u8]> for Chunk {
+    fn as_ref(&self) -> &[u8] {
+        self.as_slice()
+    }
+}
+
+impl fmt::Debug for Chunk {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_tuple("Chunk")
+            .field(&self.as_slice())
+            .finish()
+    }
+}
+
+impl PartialEq for Chunk {
+    fn eq(&self, other: &Self) -> bool {
+        self.as_slice() == other.as_slice()
+    }
+}
+
+impl Eq for Chunk { }
+
+impl From<Vec<u8>> for Chunk {
+    fn from(data: Vec<u8>) -> Self {
+        Chunk::with_data(Data::Heap(data))
+    }
+}
+
+impl<'a> From<&'a [u8]> for Chunk {
+    fn from(data: &'a [u8]) -> Self {
+        if data.len() <= INLINE_CAPACITY {
+            let mut inline = [0; INLINE_CAPACITY];
+            inline[..data.len()].copy_from_slice(data);
+            Chunk::with_data(Data::Inline(data.len() as u8, inline))
+        } else {
+            data.to_vec().into()
+        }
+    }
+}
+
+impl From<Chunk> for Vec<u8> {
+    fn from(chunk: Chunk) -> Self {
+        chunk.into_vec()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn chunk_inline() {
+        let small = Chunk::from(&b"hello"[..]);
+        assert!(small.is_inline());
+        assert_eq!(&small[..], b"hello");
+
+        let large = Chunk::from(&[1; INLINE_CAPACITY + 1][..]);
+        assert!(!large.is_inline());
+        assert_eq!(large.len(), INLINE_CAPACITY + 1);
+
+        let mut chunk = small.clone();
+        chunk.make_vec().extend_from_slice(b" there");
+        assert!(!chunk.is_inline());
+        assert_eq!(chunk.into_vec(), b"hello there");
+    }
+
+    #[test]
+    fn slice() {
+        let mut chunk = Chunk::from(vec![1, 2, 3, 4, 5]).with_flags(7);
+        let ptr = chunk.as_ptr() as usize;
+        let slice = chunk.slice(1..3);
+        assert_eq!(&slice[..], [2, 3]);
+        assert_eq!(slice.as_ptr() as usize, ptr + 1);
+        assert_eq!(slice.flags(), 7);
+        assert_eq!(chunk.clone().as_ptr() as usize, ptr);
+
+        drop(slice);
+        let data = chunk.into_vec();
+        assert_eq!(data, [1, 2, 3, 4, 5]);
+        assert_eq!(data.as_ptr() as usize, ptr);
+    }
+
+    #[test]
+    fn from_static() {
+        static DATA: [u8; 256] = [1; 256];
+        let mut chunk = Chunk::from_static(&DATA);
+        assert!(chunk.is_static());
+        assert_eq!(chunk.as_ptr(), DATA.as_ptr());
+        assert_eq!(chunk.slice(16..32).as_ptr(), DATA[16..].as_ptr());
+        assert_eq!(chunk.into_vec(), &DATA[..]);
+    }
+
+    #[test]
+    fn checksum() {
+        let mut chunk = Chunk::from(vec![1; 100]).with_checksum();
+        assert_eq!(chunk.verify(), Ok(()));
+        if let Data::Heap(data) = &mut chunk.data {
+            data[0] = 2;
+        }
+        assert_eq!(chunk.verify(), Err(PipeError::Corrupted));
+        chunk.make_vec()[0] = 1;
+        assert_eq!(chunk.verify(), Ok(()));
+    }
+
+    #[test]
+    #[cfg(feature = "zstd")]
+    fn send_all_checksum() {
+        use {pipe, Compression};
+
+        let (r, mut w) = pipe();
+        w.set_compression(Some(Compression::default()));
+        w.set_checksums(true);
+        drop(r);
+        let failed = w.send_all(vec![vec![0; 1024]]).unwrap_err().failed;
+        assert!(!failed.is_compressed());
+        assert_eq!(failed.checksum, Some(crc32(&failed)));
+    }
+}
@@ -0,0 +1,163 @@
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// A source of time for the features that pace or time writers, such as `RateLimiter`, `Quota`,
+/// `PipeBufWriter::last_flush()`, chunk expiry (see `PipeWriter::set_ttl()`) and the stall
+/// window of a `Watchdog`
+///
+/// The default is `SystemClock`. Tests can use a `ManualClock` instead, so that a rate limit,
+/// quota window or time-to-live passes instantly rather than actually sleeping through it. Waits
+/// that block on the pipe itself, such as `PipeReader::set_min_read_timeout()`, always use real
+/// time.
+pub trait Clock: Send + Sync {
+    /// Returns the current time
+    fn now(&self) -> Instant;
+
+    /// Blocks the current thread for `duration`, as measured by this clock
+    fn sleep(&self, duration: Duration);
+}
+
+/// The real time, as told by `Instant::now()` and `thread::sleep()`
+#[derive(Copy, Clone, Debug, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+
+    fn sleep(&self, duration: Duration) {
+        thread::sleep(duration)
+    }
+}
+
+/// A clock that only moves when told to, for deterministic tests
+///
+/// Sleeping on the clock advances it instead of blocking. Clones share the same time.
+///
+/// # Example
+///
+/// ```
+/// use std::time::Duration;
+/// use pipe::{ManualClock, RateLimiter};
+///
+/// let clock = ManualClock::new();
+/// let start = clock.now();
+/// let limiter = RateLimiter::with_clock(100, 100, clock.clone());
+/// limiter.acquire(100);
+/// limiter.acquire(50);
+/// assert_eq!(clock.now() - start, Duration::from_millis(500));
+/// ```
+#[derive(Clone, Debug)]
+pub struct ManualClock {
+    now: Arc<Mutex<Instant>>,
+}
+
+impl ManualClock {
+    /// Creates a clock starting at the current time
+    pub fn new() -> Self {
+        ManualClock { now: Arc::new(Mutex::new(Instant::now())) }
+    }
+
+    /// Returns the clock's current time
+    pub fn now(&self) -> Instant {
+        *self.now.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Moves the clock forward by `duration`
+    pub fn advance(&self, duration: Duration) {
+        *self.now.lock().unwrap_or_else(|e| e.into_inner()) += duration;
+    }
+}
+
+impl Default for ManualClock {
+    fn default() -> Self {
+        ManualClock::new()
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> Instant {
+        ManualClock::now(self)
+    }
+
+    fn sleep(&self, duration: Duration) {
+        self.advance(duration)
+    }
+}
+
+/// The clock used by a pipe handle, which is `SystemClock` unless another one was set
+#[derive(Clone, Default)]
+pub(crate) struct ClockHandle(Option<Arc<dyn Clock>>);
+
+impl ClockHandle {
+    pub(crate) fn new<C: Clock + 'static>(clock: C) -> Self {
+        ClockHandle(Some(Arc::new(clock)))
+    }
+
+    pub(crate) fn now(&self) -> Instant {
+        match &self.0 {
+            Some(clock) => clock.now(),
+            None => Instant::now(),
+        }
+    }
+
+    /// Returns how long to block for in real time while waiting for this clock to reach
+    /// `deadline`, such as with `Condvar::wait_timeout()`
+    ///
+    /// Any clock but the system's may be moved forward at any moment, so it is checked again
+    /// every millisecond.
+    pub(crate) fn timeout(&self, deadline: Instant) -> Duration {
+        let remaining = deadline.saturating_duration_since(self.now());
+        match &self.0 {
+            Some(..) => remaining.min(Duration::from_millis(1)),
+            None => remaining,
+        }
+    }
+
+    pub(crate) fn sleep(&self, duration: Duration) {
+        match &self.0 {
+            Some(clock) => clock.sleep(duration),
+            None => thread::sleep(duration),
+        }
+    }
+}
+
+impl fmt::Debug for ClockHandle {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self.0 {
+            Some(..) => "Clock",
+            None => "SystemClock",
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Read, Write};
+    use std::thread::spawn;
+    use {pipe, Quota, QuotaPolicy};
+    use super::*;
+
+    #[test]
+    fn manual_quota() {
+        let (mut r, mut w) = pipe();
+        let clock = ManualClock::new();
+        let start = clock.now();
+        w.set_clock(clock.clone());
+        w.set_quota(Some(Quota::new(10, Duration::from_secs(3600), QuotaPolicy::Block)));
+        let guard = spawn(move || {
+            w.write_all(&[0; 10]).unwrap();
+            w.write_all(&[0; 5]).unwrap();
+        });
+
+        let mut o = Vec::new();
+        r.read_to_end(&mut o).unwrap();
+        guard.join().unwrap();
+        assert_eq!(o.len(), 15);
+        // the writer waited out the hour without actually sleeping
+        assert_eq!(clock.now() - start, Duration::from_secs(3600));
+    }
+}
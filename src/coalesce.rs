@@ -0,0 +1,231 @@
+use std::mem::take;
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::thread::{spawn, JoinHandle};
+use std::time::{Duration, Instant};
+use clock::ClockHandle;
+use {PipeError, PipeWriter, DEFAULT_BUF_SIZE};
+
+struct Batch {
+    data: Vec<u8>,
+    /// When the first write of the batch happened
+    started: Option<Instant>,
+    /// Set to send the batch without waiting for the window to pass
+    urgent: bool,
+    sending: bool,
+    closed: bool,
+    error: Option<PipeError>,
+}
+
+struct Shared {
+    batch: Mutex<Batch>,
+    changed: Condvar,
+}
+
+impl Shared {
+    fn lock(&self) -> MutexGuard<'_, Batch> {
+        self.batch.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn wait<'a>(&self, batch: MutexGuard<'a, Batch>) -> MutexGuard<'a, Batch> {
+        self.changed.wait(batch).unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// Gathers the writes made to a `PipeWriter` within a short window into a single chunk (see
+/// `PipeWriter::set_coalesce_window()`)
+///
+/// The batch is sent by a thread of its own once the window has passed since its first write, so
+/// data never waits on a later write or an explicit flush to be sent. The window is measured by
+/// the writer's clock.
+pub(crate) struct Coalescer {
+    window: Duration,
+    clock: ClockHandle,
+    shared: Arc<Shared>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Coalescer {
+    /// Starts coalescing writes into batches sent with `writer`
+    pub(crate) fn new(writer: PipeWriter, window: Duration, clock: ClockHandle) -> Self {
+        let shared = Arc::new(Shared {
+            batch: Mutex::new(Batch { data: Vec::new(), started: None, urgent: false, sending: false, closed: false, error: None }),
+            changed: Condvar::new(),
+        });
+        let thread = {
+            let shared = shared.clone();
+            let clock = clock.clone();
+            spawn(move || run(&shared, &writer, window, &clock))
+        };
+
+        Coalescer { window, clock, shared, thread: Some(thread) }
+    }
+
+    pub(crate) fn window(&self) -> Duration {
+        self.window
+    }
+
+    /// Adds `buf` to the current batch, waiting for the batch to be sent first if it is full
+    pub(crate) fn write(&self, buf: &[u8]) -> Result<(), PipeError> {
+        let mut batch = self.shared.lock();
+        while batch.data.len() >= DEFAULT_BUF_SIZE && batch.error.is_none() {
+            batch.urgent = true;
+            self.shared.changed.notify_all();
+            batch = self.shared.wait(batch);
+        }
+        if let Some(error) = batch.error {
+            return Err(error)
+        }
+
+        if batch.started.is_none() {
+            batch.started = Some(self.clock.now());
+            self.shared.changed.notify_all();
+        }
+        batch.data.extend_from_slice(buf);
+        Ok(())
+    }
+
+    /// Sends the current batch right away, and waits until it has been received
+    pub(crate) fn flush(&self) -> Result<(), PipeError> {
+        let mut batch = self.shared.lock();
+        while (!batch.data.is_empty() || batch.sending) && batch.error.is_none() {
+            batch.urgent = true;
+            self.shared.changed.notify_all();
+            batch = self.shared.wait(batch);
+        }
+        match batch.error {
+            Some(error) => Err(error),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Sends each batch once its window has passed, until the coalescer is dropped
+fn run(shared: &Shared, writer: &PipeWriter, window: Duration, clock: &ClockHandle) {
+    let mut batch = shared.lock();
+    loop {
+        let due = match batch.started {
+            None if batch.closed => return,
+            None => {
+                batch = shared.wait(batch);
+                continue
+            },
+            Some(started) => started + window,
+        };
+        if clock.now() < due && !batch.urgent && !batch.closed {
+            batch = shared.changed.wait_timeout(batch, clock.timeout(due)).unwrap_or_else(|e| e.into_inner()).0;
+            continue
+        }
+
+        let data = take(&mut batch.data);
+        batch.started = None;
+        batch.urgent = false;
+        batch.sending = true;
+        drop(batch);
+        let res = writer.send_vec(data);
+        batch = shared.lock();
+        batch.sending = false;
+        if let Err(error) = res {
+            // nothing more can be sent, so writes fail from now on
+            batch.error = Some(error);
+            batch.data.clear();
+            batch.started = None;
+        }
+        shared.changed.notify_all();
+    }
+}
+
+/// Sends whatever is left of the current batch, and stops the thread sending batches.
+impl Drop for Coalescer {
+    fn drop(&mut self) {
+        self.shared.lock().closed = true;
+        self.shared.changed.notify_all();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Read, Write};
+    use std::thread::spawn;
+    use super::*;
+    use pipe;
+
+    #[test]
+    fn coalesce() {
+        let (mut r, mut w) = pipe();
+        w.set_coalesce_window(Some(Duration::from_secs(10)));
+        let guard = spawn(move || {
+            for b in b"hello" {
+                w.write_all(&[*b]).unwrap();
+            }
+            w.flush().unwrap();
+            w.write_all(b" world").unwrap();
+        });
+
+        assert_eq!(&r.recv_chunk().unwrap().unwrap()[..], b"hello");
+        // the last batch is sent as the writer is dropped
+        let mut rest = String::new();
+        r.read_to_string(&mut rest).unwrap();
+        assert_eq!(rest, " world");
+        guard.join().unwrap();
+    }
+
+    #[test]
+    fn coalesce_clock() {
+        use ManualClock;
+
+        let clock = ManualClock::new();
+        let (mut r, mut w) = pipe();
+        w.set_clock(clock.clone());
+        w.set_coalesce_window(Some(Duration::from_secs(60)));
+        w.write_all(b"ab").unwrap();
+        clock.advance(Duration::from_secs(60));
+        // sent as soon as the clock says the window has passed
+        assert_eq!(&r.recv_chunk().unwrap().unwrap()[..], b"ab");
+        drop(w);
+    }
+
+    #[test]
+    fn coalesce_accounting() {
+        use {Quota, QuotaPolicy};
+
+        let (mut r, mut w) = pipe();
+        let id = w.id();
+        w.set_quota(Some(Quota::new(4, Duration::from_secs(60), QuotaPolicy::Error)));
+        w.set_coalesce_window(Some(Duration::from_secs(60)));
+        w.set_checksums(true);
+        assert_eq!(w.id(), id);
+        assert_eq!(r.writer_count(), 1);
+
+        let guard = spawn(move || {
+            w.write_all(b"abc").unwrap();
+            w.flush().unwrap();
+            assert_eq!(w.written(), 3);
+            // the batch is held to this writer's quota
+            w.write_all(b"de").unwrap();
+            assert_eq!(w.flush().unwrap_err().kind(), ::std::io::ErrorKind::WouldBlock);
+            w
+        });
+        assert_eq!(&r.recv_chunk().unwrap().unwrap()[..], b"abc");
+        let w = guard.join().unwrap();
+        assert_eq!(w.written(), 3);
+        drop(w);
+        assert_eq!(r.writer_count(), 0);
+    }
+
+    #[test]
+    fn coalesce_window() {
+        let (mut r, mut w) = pipe();
+        w.set_coalesce_window(Some(Duration::from_millis(100)));
+        w.write_all(b"ab").unwrap();
+        w.write_all(b"c").unwrap();
+        // sent without any further write or flush
+        assert_eq!(&r.recv_chunk().unwrap().unwrap()[..], b"abc");
+
+        drop(r);
+        w.write_all(b"d").unwrap();
+        assert_eq!(w.flush().unwrap_err().kind(), ::std::io::ErrorKind::BrokenPipe);
+    }
+}
@@ -0,0 +1,145 @@
+use std::io::{BufRead, Write};
+use tokio_util::bytes::BytesMut;
+use tokio_util::codec::{Decoder, Encoder};
+use {PipeReader, PipeWriter};
+
+/// A blocking iterator over the frames decoded from a `PipeReader` (see `PipeReader::frames()`)
+///
+/// Like `FramedRead`, the iterator ends after the first error.
+pub struct Frames<'a, C> {
+    reader: &'a mut PipeReader,
+    codec: C,
+    buffer: BytesMut,
+    eof: bool,
+    errored: bool,
+}
+
+impl PipeReader {
+    /// Decodes frames from the pipe with a `tokio_util` codec, blocking for more data as needed
+    ///
+    /// The same codec can decode a `LocalPipeReader` asynchronously through `into_framed_read()`,
+    /// so framing code can be shared between threads and async tasks.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # extern crate pipe;
+    /// # extern crate tokio_util;
+    /// use std::thread::spawn;
+    /// use tokio_util::codec::LinesCodec;
+    ///
+    /// let (mut reader, writer) = pipe::pipe();
+    /// spawn(move || writer.send_frame(&mut LinesCodec::new(), "hello"));
+    /// let lines: Result<Vec<_>, _> = reader.frames(LinesCodec::new()).collect();
+    /// assert_eq!(lines.unwrap(), ["hello"]);
+    /// ```
+    #[cfg_attr(feature = "unstable-doc-cfg", doc(cfg(feature = "tokio-util")))]
+    pub fn frames<C: Decoder>(&mut self, codec: C) -> Frames<'_, C> {
+        Frames {
+            reader: self,
+            codec,
+            buffer: BytesMut::new(),
+            eof: false,
+            errored: false,
+        }
+    }
+}
+
+impl<C> Frames<'_, C> {
+    /// Returns the codec
+    pub fn codec(&self) -> &C {
+        &self.codec
+    }
+
+    /// Returns data that has been read but not yet decoded into a frame
+    pub fn buffer(&self) -> &[u8] {
+        &self.buffer
+    }
+}
+
+impl<C: Decoder> Iterator for Frames<'_, C> {
+    type Item = Result<C::Item, C::Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.errored {
+            return None
+        }
+
+        let res = self.decode();
+        self.errored = matches!(res, Some(Err(..)));
+        res
+    }
+}
+
+impl<C: Decoder> Frames<'_, C> {
+    /// Decodes the next frame, reading more of the pipe as needed
+    fn decode(&mut self) -> Option<Result<C::Item, C::Error>> {
+        loop {
+            if self.eof {
+                return self.codec.decode_eof(&mut self.buffer).transpose()
+            }
+
+            match self.codec.decode(&mut self.buffer) {
+                Ok(Some(frame)) => return Some(Ok(frame)),
+                Ok(None) => (),
+                Err(e) => return Some(Err(e)),
+            }
+
+            let len = match self.reader.fill_buf() {
+                Ok(buf) => {
+                    self.buffer.extend_from_slice(buf);
+                    buf.len()
+                },
+                Err(e) => return Some(Err(e.into())),
+            };
+            self.reader.consume(len);
+            self.eof = len == 0;
+        }
+    }
+}
+
+impl PipeWriter {
+    /// Encodes `item` with a `tokio_util` codec and sends it as a single chunk
+    ///
+    /// This is the blocking counterpart of sending through `LocalPipeWriter::into_framed_write()`.
+    #[cfg_attr(feature = "unstable-doc-cfg", doc(cfg(feature = "tokio-util")))]
+    pub fn send_frame<I, C: Encoder<I>>(&self, codec: &mut C, item: I) -> Result<(), C::Error> {
+        let mut buffer = BytesMut::new();
+        codec.encode(item, &mut buffer)?;
+        let mut writer = self;
+        writer.write_all(&buffer).map_err(C::Error::from)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+    use std::thread::spawn;
+    use tokio_util::codec::{LengthDelimitedCodec, LinesCodec};
+    use pipe;
+
+    #[test]
+    fn frames() {
+        let (mut r, mut w) = pipe();
+        let guard = spawn(move || {
+            w.send_frame(&mut LinesCodec::new(), "one").unwrap();
+            w.write_all(b"tw").unwrap();
+            w.write_all(b"o\nthree").unwrap();
+        });
+
+        let lines: Result<Vec<_>, _> = r.frames(LinesCodec::new()).collect();
+        assert_eq!(lines.unwrap(), ["one", "two", "three"]);
+        guard.join().unwrap();
+    }
+
+    #[test]
+    fn frames_truncated() {
+        let (mut r, mut w) = pipe();
+        let guard = spawn(move || w.write_all(b"\0\0\0\x05ab").unwrap());
+
+        let mut frames = r.frames(LengthDelimitedCodec::new());
+        assert!(frames.next().unwrap().is_err());
+        assert!(frames.next().is_none());
+        guard.join().unwrap();
+    }
+}
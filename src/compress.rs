@@ -0,0 +1,94 @@
+use std::io;
+use zstd;
+use Chunk;
+
+/// How a writer compresses the chunks it sends (see `PipeWriter::set_compression()`)
+///
+/// Compressing a small chunk costs more than it saves, so only chunks larger than `threshold`
+/// bytes are compressed, and only if that actually makes them smaller.
+#[cfg_attr(feature = "unstable-doc-cfg", doc(cfg(feature = "zstd")))]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Compression {
+    /// The zstd compression level
+    pub level: i32,
+    /// The size in bytes a chunk has to exceed to be compressed
+    pub threshold: usize,
+}
+
+impl Default for Compression {
+    fn default() -> Self {
+        Compression { level: zstd::DEFAULT_COMPRESSION_LEVEL, threshold: 512 }
+    }
+}
+
+impl Compression {
+    /// Compresses `chunk` in place if it is worth it, marking it as compressed
+    pub(crate) fn apply(&self, chunk: &mut Chunk) {
+        if chunk.len() <= self.threshold || chunk.is_compressed() {
+            return
+        }
+
+        if let Ok(data) = zstd::bulk::compress(chunk, self.level) {
+            if data.len() < chunk.len() {
+                chunk.replace_data(data, true);
+            }
+        }
+    }
+}
+
+impl Chunk {
+    /// Restores the original data of a chunk sent compressed (see `PipeWriter::set_compression()`)
+    ///
+    /// Readers do this by themselves, so this is only needed for chunks taken from a `PipeSet`.
+    /// Chunks that aren't compressed are left as they are.
+    #[cfg_attr(feature = "unstable-doc-cfg", doc(cfg(feature = "zstd")))]
+    pub fn decompress(&mut self) -> io::Result<()> {
+        if self.is_compressed() {
+            self.verify()?;
+            let data = zstd::stream::decode_all(self.as_slice())?;
+            self.replace_data(data, false);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+    use std::thread::spawn;
+    use super::*;
+    use pipe;
+
+    #[test]
+    fn compression_threshold() {
+        let (mut r, mut w) = pipe();
+        w.set_compression(Some(Compression { level: 3, threshold: 64 }));
+        w.set_checksums(true);
+        let guard = spawn(move || {
+            w.send(&[1; 32][..]).unwrap();
+            w.send(vec![2; 4096]).unwrap();
+        });
+
+        let small = r.recv_chunk().unwrap().unwrap();
+        assert!(!small.is_compressed());
+        assert_eq!(&small[..], &[1; 32][..]);
+        let mut rest = Vec::new();
+        r.read_to_end(&mut rest).unwrap();
+        assert_eq!(rest, vec![2; 4096]);
+        assert_eq!(r.position(), 32 + 4096);
+        guard.join().unwrap();
+    }
+
+    #[test]
+    fn compressed_chunk() {
+        let mut chunk = Chunk::from(vec![0; 1024]).with_flags(3);
+        Compression::default().apply(&mut chunk);
+        assert!(chunk.is_compressed());
+        assert!(chunk.len() < 1024);
+        assert_eq!(chunk.flags(), 3);
+
+        chunk.decompress().unwrap();
+        assert!(!chunk.is_compressed());
+        assert_eq!(&chunk[..], &[0; 1024][..]);
+    }
+}
@@ -0,0 +1,85 @@
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::sync::atomic::{AtomicBool, Ordering};
+use state::Shared;
+
+/// Holds back the reader of a pipe while it is paused
+pub(crate) struct Gate {
+    paused: AtomicBool,
+    state: Mutex<bool>,
+    resumed: Condvar,
+}
+
+impl Gate {
+    pub(crate) fn new() -> Self {
+        Gate {
+            paused: AtomicBool::new(false),
+            state: Mutex::new(false),
+            resumed: Condvar::new(),
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, bool> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn set(&self, paused: bool) {
+        let mut state = self.lock();
+        *state = paused;
+        self.paused.store(paused, Ordering::Release);
+        if !paused {
+            self.resumed.notify_all();
+        }
+    }
+
+    pub(crate) fn is_paused(&self) -> bool {
+        self.paused.load(Ordering::Acquire)
+    }
+
+    /// Blocks for as long as the pipe is paused
+    pub(crate) fn pass(&self) {
+        if !self.is_paused() {
+            return
+        }
+
+        let mut state = self.lock();
+        while *state {
+            state = self.resumed.wait(state).unwrap_or_else(|e| e.into_inner());
+        }
+    }
+}
+
+/// Pauses and resumes delivery of data to the reader of a pipe (see `PipeReader::control()`)
+///
+/// While paused, the reader blocks before returning any data, including data it had already
+/// buffered. A writer whose chunk the reader was already waiting on may still complete, but every
+/// other writer blocks until the pipe is resumed, just like when the reader is busy.
+#[derive(Clone)]
+pub struct PipeControl {
+    shared: Arc<Shared>,
+}
+
+impl PipeControl {
+    pub(crate) fn new(shared: Arc<Shared>) -> Self {
+        PipeControl { shared }
+    }
+
+    /// Stops delivering data to the reader until `resume()` is called
+    pub fn pause(&self) {
+        self.shared.gate().set(true);
+    }
+
+    /// Resumes delivering data to the reader
+    pub fn resume(&self) {
+        self.shared.gate().set(false);
+        self.shared.resumed();
+    }
+
+    /// Returns `true` if the pipe is paused
+    pub fn is_paused(&self) -> bool {
+        self.shared.gate().is_paused()
+    }
+
+    pub(crate) fn shared(&self) -> &Arc<Shared> {
+        &self.shared
+    }
+}
@@ -0,0 +1,158 @@
+use std::cmp::min;
+use std::io::{self, Read, Write};
+use std::time::{Duration, Instant};
+use DEFAULT_BUF_SIZE;
+
+/// Options for `copy()`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CopyOptions {
+    /// Size of the buffer used to relay data, and so the largest chunk written at once
+    pub buffer_size: usize,
+    /// Stops after this many bytes have been copied
+    pub limit: Option<u64>,
+}
+
+impl Default for CopyOptions {
+    fn default() -> Self {
+        CopyOptions {
+            buffer_size: DEFAULT_BUF_SIZE,
+            limit: None,
+        }
+    }
+}
+
+/// Why `copy()` stopped
+#[derive(Debug)]
+pub enum CopyEnd {
+    /// The reader reached EOF
+    Eof,
+    /// `CopyOptions::limit` bytes were copied
+    Limit,
+    /// Reading failed
+    ReadFailed(io::Error),
+    /// Writing failed, which for a pipe usually means its reader was dropped
+    WriteFailed(io::Error),
+    /// Copying was stopped early (see `PumpHandle::stop()`)
+    Stopped,
+}
+
+/// The outcome of `copy()`
+#[derive(Debug)]
+pub struct CopyReport {
+    /// Number of bytes written
+    pub bytes: u64,
+    /// Time spent waiting on the reader
+    pub read_time: Duration,
+    /// Time spent waiting on the writer, which includes waiting for backpressure to clear
+    pub write_time: Duration,
+    /// Why copying stopped
+    pub end: CopyEnd,
+}
+
+impl CopyReport {
+    /// Returns `true` if copying stopped because of an error
+    pub fn is_err(&self) -> bool {
+        matches!(self.end, CopyEnd::ReadFailed(..) | CopyEnd::WriteFailed(..))
+    }
+
+    /// Converts the report into the `io::copy()` style result
+    pub fn into_result(self) -> io::Result<u64> {
+        match self.end {
+            CopyEnd::ReadFailed(e) | CopyEnd::WriteFailed(e) => Err(e),
+            CopyEnd::Eof | CopyEnd::Limit | CopyEnd::Stopped => Ok(self.bytes),
+        }
+    }
+}
+
+/// Relays data from `reader` to `writer`, such as into or out of a pipe
+///
+/// Unlike `io::copy()`, this reports how long each side kept the copy waiting, so that it is
+/// visible whether the producer or the consumer is the bottleneck, and errors are reported as
+/// part of the `CopyReport` along with how much had been copied by then.
+///
+/// # Panics
+///
+/// Panics if `opts.buffer_size` is zero.
+pub fn copy<R: Read + ?Sized, W: Write + ?Sized>(reader: &mut R, writer: &mut W, opts: &CopyOptions) -> CopyReport {
+    assert!(opts.buffer_size > 0, "copy buffer size must be non-zero");
+
+    let mut buf = vec![0; opts.buffer_size];
+    let mut report = CopyReport {
+        bytes: 0,
+        read_time: Duration::from_secs(0),
+        write_time: Duration::from_secs(0),
+        end: CopyEnd::Eof,
+    };
+
+    loop {
+        let want = match opts.limit {
+            Some(limit) if report.bytes >= limit => {
+                report.end = CopyEnd::Limit;
+                break
+            },
+            Some(limit) => min(buf.len() as u64, limit - report.bytes) as usize,
+            None => buf.len(),
+        };
+
+        let start = Instant::now();
+        let read = reader.read(&mut buf[..want]);
+        report.read_time += start.elapsed();
+        let len = match read {
+            Ok(0) => break,
+            Ok(len) => len,
+            Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => {
+                report.end = CopyEnd::ReadFailed(e);
+                break
+            },
+        };
+
+        let start = Instant::now();
+        let written = writer.write_all(&buf[..len]);
+        report.write_time += start.elapsed();
+        if let Err(e) = written {
+            report.end = CopyEnd::WriteFailed(e);
+            break
+        }
+        report.bytes += len as u64;
+    }
+
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{self, Read};
+    use std::thread::spawn;
+    use pipe;
+    use super::*;
+
+    #[test]
+    fn copy_into_pipe() {
+        let (mut r, mut w) = pipe();
+        let guard = spawn(move || {
+            let mut o = Vec::new();
+            r.read_to_end(&mut o).unwrap();
+            o
+        });
+
+        let data = [7; 100];
+        let opts = CopyOptions { buffer_size: 16, limit: Some(90) };
+        let report = copy(&mut &data[..], &mut w, &opts);
+        assert!(matches!(report.end, CopyEnd::Limit));
+        assert_eq!(report.bytes, 90);
+        drop(w);
+        assert_eq!(guard.join().unwrap(), &data[..90]);
+    }
+
+    #[test]
+    fn copy_reader_dropped() {
+        let (r, mut w) = pipe();
+        drop(r);
+
+        let report = copy(&mut &b"hello"[..], &mut w, &CopyOptions::default());
+        assert!(report.is_err());
+        assert_eq!(report.bytes, 0);
+        assert_eq!(report.into_result().unwrap_err().kind(), io::ErrorKind::BrokenPipe);
+    }
+}
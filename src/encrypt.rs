@@ -0,0 +1,306 @@
+use chacha20poly1305::aead::{Aead, Payload};
+use chacha20poly1305::{ChaCha20Poly1305, Key, KeyInit, Nonce};
+use std::cmp::min;
+use std::collections::HashMap;
+use std::fmt;
+use std::io::{self, BufRead, Read, Write};
+use std::sync::atomic::{AtomicU64, Ordering};
+use {check_consume, pipe, PipeReader, PipeWriter};
+
+const NONCE_LEN: usize = 12;
+const STREAM_LEN: usize = 8;
+const TAG_LEN: usize = 16;
+
+/// Nonces are drawn from a single counter, so that no two chunks sealed by this process share one,
+/// whatever key they were sealed with
+static NONCES: AtomicU64 = AtomicU64::new(0);
+
+/// Identifies each writer, and each of its clones, to the readers
+static STREAMS: AtomicU64 = AtomicU64::new(0);
+
+/// The `Write` end of an encrypted pipe (see `pipe_encrypted()`)
+pub struct EncryptedPipeWriter {
+    writer: PipeWriter,
+    cipher: ChaCha20Poly1305,
+    stream: u64,
+    sequence: u64,
+}
+
+/// The `Read` end of an encrypted pipe (see `pipe_encrypted()`)
+pub struct EncryptedPipeReader {
+    reader: PipeReader,
+    cipher: ChaCha20Poly1305,
+    /// The sequence number expected next from each writer seen so far
+    streams: HashMap<u64, u64>,
+    buffer: Vec<u8>,
+    position: usize,
+}
+
+/// Creates a pipe whose data is encrypted with ChaCha20-Poly1305 under `key` while in transit
+///
+/// Every write is sealed into a chunk of its own, so data that passes through memory shared with
+/// other components, or that ends up in a dump, is neither readable nor silently modifiable. A
+/// chunk that fails authentication fails the read with `io::ErrorKind::InvalidData`. Existing
+/// pipe ends can be wrapped with `EncryptedPipeReader::new()` and `EncryptedPipeWriter::new()`.
+///
+/// Each chunk is also bound to its writer and its position among that writer's chunks, so a chunk
+/// that was dropped, reordered or replayed fails authentication as well. Chunks missing from the
+/// end of the pipe can't be told apart from a writer that stopped there, however, so a reader
+/// that needs to know it got everything must have the writer say so within the data.
+///
+/// Nonces are unique within the process, but not across processes, so a key must not be reused
+/// after a restart.
+///
+/// # Example
+///
+/// ```
+/// use std::io::{Read, Write};
+///
+/// let (mut reader, mut writer) = pipe::pipe_encrypted(&[7; 32]);
+/// let thread = std::thread::spawn(move || writer.write_all(b"secret"));
+/// let mut data = String::new();
+/// reader.read_to_string(&mut data).unwrap();
+/// assert_eq!(data, "secret");
+/// # thread.join().unwrap().unwrap();
+/// ```
+#[cfg_attr(feature = "unstable-doc-cfg", doc(cfg(feature = "encryption")))]
+pub fn pipe_encrypted(key: &[u8; 32]) -> (EncryptedPipeReader, EncryptedPipeWriter) {
+    let (reader, writer) = pipe();
+    (EncryptedPipeReader::new(reader, key), EncryptedPipeWriter::new(writer, key))
+}
+
+fn cipher(key: &[u8; 32]) -> ChaCha20Poly1305 {
+    ChaCha20Poly1305::new(Key::from_slice(key))
+}
+
+impl EncryptedPipeWriter {
+    /// Encrypts everything written to `writer` under `key`
+    pub fn new(writer: PipeWriter, key: &[u8; 32]) -> Self {
+        EncryptedPipeWriter { writer, cipher: cipher(key), stream: STREAMS.fetch_add(1, Ordering::Relaxed), sequence: 0 }
+    }
+
+    /// Gets a reference to the underlying writer
+    pub fn get_ref(&self) -> &PipeWriter {
+        &self.writer
+    }
+
+    /// Converts the writer back into the underlying writer
+    pub fn into_inner(self) -> PipeWriter {
+        self.writer
+    }
+
+    /// Seals `data` as this writer's `sequence`th chunk with a fresh nonce, which is sent ahead of
+    /// the writer's stream and the ciphertext
+    fn seal(&self, data: &[u8], sequence: u64) -> io::Result<Vec<u8>> {
+        let mut nonce = [0; NONCE_LEN];
+        nonce[..8].copy_from_slice(&NONCES.fetch_add(1, Ordering::Relaxed).to_le_bytes());
+        let payload = Payload { msg: data, aad: &associated_data(self.stream, sequence) };
+        let sealed = self.cipher.encrypt(Nonce::from_slice(&nonce), payload)
+            .map_err(|_| io::Error::other("failed to encrypt chunk"))?;
+
+        let mut chunk = Vec::with_capacity(NONCE_LEN + STREAM_LEN + sealed.len());
+        chunk.extend_from_slice(&nonce);
+        chunk.extend_from_slice(&self.stream.to_le_bytes());
+        chunk.extend_from_slice(&sealed);
+        Ok(chunk)
+    }
+}
+
+impl Write for EncryptedPipeWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0)
+        }
+
+        let chunk = self.seal(buf, self.sequence)?;
+        self.writer.send_vec(chunk).map_err(|e| self.writer.io_error(e))?;
+        self.sequence += 1;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Clones the underlying writer, sharing the key. The clone's chunks are sequenced apart from this
+/// writer's.
+impl Clone for EncryptedPipeWriter {
+    fn clone(&self) -> Self {
+        EncryptedPipeWriter {
+            writer: self.writer.clone(),
+            cipher: self.cipher.clone(),
+            stream: STREAMS.fetch_add(1, Ordering::Relaxed),
+            sequence: 0,
+        }
+    }
+}
+
+impl fmt::Debug for EncryptedPipeWriter {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("EncryptedPipeWriter")
+            .field("writer", &self.writer)
+            .finish()
+    }
+}
+
+impl EncryptedPipeReader {
+    /// Decrypts everything read from `reader` under `key`
+    pub fn new(reader: PipeReader, key: &[u8; 32]) -> Self {
+        EncryptedPipeReader { reader, cipher: cipher(key), streams: HashMap::new(), buffer: Vec::new(), position: 0 }
+    }
+
+    /// Gets a reference to the underlying reader
+    pub fn get_ref(&self) -> &PipeReader {
+        &self.reader
+    }
+
+    /// Converts the reader back into the underlying reader, dropping any decrypted data that
+    /// hasn't been read yet
+    pub fn into_inner(self) -> PipeReader {
+        self.reader
+    }
+
+    /// Opens a chunk, which must be the next one in sequence from its writer
+    fn open(&mut self, chunk: &[u8]) -> io::Result<Vec<u8>> {
+        if chunk.len() < NONCE_LEN + STREAM_LEN + TAG_LEN {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "encrypted chunk is truncated"))
+        }
+
+        let (nonce, chunk) = chunk.split_at(NONCE_LEN);
+        let (stream, sealed) = chunk.split_at(STREAM_LEN);
+        let mut bytes = [0; STREAM_LEN];
+        bytes.copy_from_slice(stream);
+        let stream = u64::from_le_bytes(bytes);
+        let sequence = self.streams.get(&stream).cloned().unwrap_or(0);
+
+        let payload = Payload { msg: sealed, aad: &associated_data(stream, sequence) };
+        let data = self.cipher.decrypt(Nonce::from_slice(nonce), payload)
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "encrypted chunk failed authentication"))?;
+        self.streams.insert(stream, sequence + 1);
+        Ok(data)
+    }
+}
+
+/// Binds a chunk to the writer that sealed it and its position among that writer's chunks
+fn associated_data(stream: u64, sequence: u64) -> [u8; 16] {
+    let mut data = [0; 16];
+    data[..8].copy_from_slice(&stream.to_le_bytes());
+    data[8..].copy_from_slice(&sequence.to_le_bytes());
+    data
+}
+
+impl BufRead for EncryptedPipeReader {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        while self.position >= self.buffer.len() {
+            match self.reader.recv_chunk()? {
+                // markers such as a `PipeBarrier`'s carry no data to decrypt
+                Some(chunk) if chunk.is_empty() => (),
+                Some(chunk) => {
+                    self.buffer = self.open(&chunk)?;
+                    self.position = 0;
+                },
+                None => break,
+            }
+        }
+
+        Ok(&self.buffer[min(self.position, self.buffer.len())..])
+    }
+
+    fn consume(&mut self, amt: usize) {
+        check_consume(amt, self.buffer.len().saturating_sub(self.position));
+        self.position += amt;
+    }
+}
+
+impl Read for EncryptedPipeReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let internal = self.fill_buf()?;
+        let len = min(buf.len(), internal.len());
+        buf[..len].copy_from_slice(&internal[..len]);
+        self.consume(len);
+        Ok(len)
+    }
+}
+
+impl fmt::Debug for EncryptedPipeReader {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("EncryptedPipeReader")
+            .field("reader", &self.reader)
+            .field("buffered", &self.buffer.len().saturating_sub(self.position))
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread::spawn;
+    use super::*;
+    use Chunk;
+
+    #[test]
+    fn encrypted() {
+        let (reader, writer) = pipe();
+        let mut reader = EncryptedPipeReader::new(reader, &[1; 32]);
+        let mut writer = EncryptedPipeWriter::new(writer, &[1; 32]);
+        let guard = spawn(move || {
+            writer.write_all(b"hello ").unwrap();
+            writer.write_all(b"world").unwrap();
+            writer.into_inner().send_chunk(Chunk::from(vec![0; 64])).unwrap();
+        });
+
+        let mut buf = [0; 11];
+        reader.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"hello world");
+        assert_eq!(reader.read(&mut buf).unwrap_err().kind(), io::ErrorKind::InvalidData);
+        guard.join().unwrap();
+    }
+
+    #[test]
+    fn wrong_key() {
+        let (reader, writer) = pipe();
+        let mut reader = EncryptedPipeReader::new(reader, &[1; 32]);
+        let mut writer = EncryptedPipeWriter::new(writer, &[2; 32]);
+        let guard = spawn(move || writer.write_all(b"data"));
+        assert_eq!(reader.read(&mut [0; 4]).unwrap_err().kind(), io::ErrorKind::InvalidData);
+        drop(reader);
+        let _ = guard.join().unwrap();
+    }
+
+    #[test]
+    fn out_of_sequence() {
+        let (reader, raw) = pipe();
+        let mut reader = EncryptedPipeReader::new(reader, &[1; 32]);
+        let writer = EncryptedPipeWriter::new(raw.clone(), &[1; 32]);
+        let first = writer.seal(b"first", 0).unwrap();
+        let second = writer.seal(b"second", 1).unwrap();
+        let guard = spawn(move || {
+            raw.send_vec(first.clone()).unwrap();
+            // replayed
+            raw.send_vec(first).unwrap();
+            raw.send_vec(second.clone()).unwrap();
+            raw.send_vec(second).unwrap();
+        });
+
+        let mut buf = [0; 5];
+        reader.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"first");
+        assert_eq!(reader.read(&mut buf).unwrap_err().kind(), io::ErrorKind::InvalidData);
+        let mut buf = [0; 6];
+        reader.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"second");
+        assert_eq!(reader.read(&mut buf).unwrap_err().kind(), io::ErrorKind::InvalidData);
+        guard.join().unwrap();
+    }
+
+    #[test]
+    fn dropped_chunk() {
+        let (reader, raw) = pipe();
+        let mut reader = EncryptedPipeReader::new(reader, &[1; 32]);
+        let writer = EncryptedPipeWriter::new(raw.clone(), &[1; 32]);
+        let second = writer.seal(b"second", 1).unwrap();
+        let guard = spawn(move || raw.send_vec(second).unwrap());
+        assert_eq!(reader.read(&mut [0; 6]).unwrap_err().kind(), io::ErrorKind::InvalidData);
+        guard.join().unwrap();
+    }
+}
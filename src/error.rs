@@ -0,0 +1,127 @@
+use std::error::Error;
+use std::fmt;
+use std::io;
+use Chunk;
+
+/// The error code an OS pipe fails with once its reader is gone (`EPIPE` or `ERROR_BROKEN_PIPE`)
+#[cfg(unix)]
+pub(crate) const OS_EPIPE: Option<i32> = Some(32);
+#[cfg(windows)]
+pub(crate) const OS_EPIPE: Option<i32> = Some(109);
+#[cfg(not(any(unix, windows)))]
+pub(crate) const OS_EPIPE: Option<i32> = None;
+
+/// The ways a pipe operation can fail
+///
+/// This is returned by the pipe's own APIs, such as `PipeWriter::send()`, while the `std::io`
+/// trait implementations report the equivalent `io::Error` (see the `From` impl for the mapping
+/// of `ErrorKind`s).
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum PipeError {
+    /// The other end of the pipe has been dropped
+    Disconnected,
+    /// The operation didn't complete before its deadline
+    TimedOut,
+    /// The operation would have to block
+    WouldBlock,
+    /// A lock shared by the pipe was poisoned by a panicking thread
+    Poisoned,
+    /// More data was involved than the operation allows
+    TooLarge,
+    /// The writer has used up its `Quota`
+    QuotaExceeded,
+    /// A chunk's data no longer matches the checksum it was sent with
+    Corrupted,
+    /// The writer's validator rejected the data (see `PipeWriter::set_validator()`)
+    Rejected,
+}
+
+impl PipeError {
+    /// Returns the `io::ErrorKind` this error is reported as
+    pub fn kind(&self) -> io::ErrorKind {
+        match self {
+            PipeError::Disconnected => io::ErrorKind::BrokenPipe,
+            PipeError::TimedOut => io::ErrorKind::TimedOut,
+            PipeError::WouldBlock => io::ErrorKind::WouldBlock,
+            PipeError::Poisoned => io::ErrorKind::Other,
+            PipeError::TooLarge => io::ErrorKind::InvalidData,
+            PipeError::QuotaExceeded => io::ErrorKind::WouldBlock,
+            PipeError::Corrupted => io::ErrorKind::InvalidData,
+            PipeError::Rejected => io::ErrorKind::InvalidInput,
+        }
+    }
+}
+
+impl fmt::Display for PipeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            PipeError::Disconnected => "pipe reader has been dropped",
+            PipeError::TimedOut => "pipe operation timed out",
+            PipeError::WouldBlock => "pipe operation would block",
+            PipeError::Poisoned => "pipe lock was poisoned",
+            PipeError::TooLarge => "pipe data exceeded the size limit",
+            PipeError::QuotaExceeded => "pipe writer exceeded its quota",
+            PipeError::Corrupted => "pipe chunk failed its checksum",
+            PipeError::Rejected => "pipe writer rejected the data",
+        })
+    }
+}
+
+impl Error for PipeError { }
+
+/// The resulting `io::Error` wraps the `PipeError`, so it can be recovered with
+/// `io::Error::get_ref()` and `downcast_ref()`.
+impl From<PipeError> for io::Error {
+    fn from(err: PipeError) -> Self {
+        io::Error::new(err.kind(), err)
+    }
+}
+
+/// The failure of `PipeWriter::send_all()`, with what is left to send
+pub struct SendAllError<I> {
+    /// Why the send failed
+    pub error: PipeError,
+    /// The chunk that failed to send
+    pub failed: Box<Chunk>,
+    /// The chunks that weren't tried yet
+    pub remaining: I,
+    /// How many chunks were sent before the failure
+    pub sent: usize,
+}
+
+impl<I> fmt::Debug for SendAllError<I> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("SendAllError")
+            .field("error", &self.error)
+            .field("failed", &self.failed)
+            .field("sent", &self.sent)
+            .finish()
+    }
+}
+
+impl<I> fmt::Display for SendAllError<I> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(&self.error, f)
+    }
+}
+
+impl<I> Error for SendAllError<I> {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        Some(&self.error)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+    use super::*;
+
+    #[test]
+    fn into_io_error() {
+        let err: io::Error = PipeError::TooLarge.into();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert_eq!(err.get_ref().and_then(|e| e.downcast_ref()), Some(&PipeError::TooLarge));
+        assert_eq!(err.to_string(), "pipe data exceeded the size limit");
+    }
+}
@@ -0,0 +1,83 @@
+use crossbeam_channel::{self, Receiver, Sender};
+use std::sync::Mutex;
+use std::time::Duration;
+use Stall;
+
+/// A change in the topology or state of a pipe (see `PipeEvents`)
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum PipeEvent {
+    /// A reader of the pipe has been dropped
+    ReaderDropped,
+    /// A writer of the pipe has been dropped
+    WriterDropped,
+    /// The last writer has been dropped, so the reader will see EOF once it has read everything
+    Finished,
+    /// A write didn't fit into the pipe's memory limit or `MemoryBudget`
+    Overflowed,
+    /// The pipe started spooling data to a temporary file
+    SpilledToDisk,
+    /// A buffered writer handed its buffered data over to the reader (see
+    /// `PipeBufWriter::last_flush()`)
+    Flushed,
+    /// The pipe has made no progress for a while, as judged by a `Watchdog`
+    Stalled(Stall),
+}
+
+/// A subscription to the events of a pipe
+///
+/// Subscriptions are created by the `events()` method of any of the pipe's handles, and receive
+/// every event that happens from then on. Events are queued without bound until they are
+/// received, so that a supervisor never slows down the pipe itself.
+pub struct PipeEvents {
+    receiver: Receiver<PipeEvent>,
+}
+
+impl PipeEvents {
+    /// Blocks until the next event
+    ///
+    /// Returns `None` once the pipe is gone, after every event has been received.
+    pub fn recv(&self) -> Option<PipeEvent> {
+        self.receiver.recv().ok()
+    }
+
+    /// Returns the next event if there is one, without blocking
+    pub fn try_recv(&self) -> Option<PipeEvent> {
+        self.receiver.try_recv().ok()
+    }
+
+    /// Waits up to `timeout` for the next event
+    pub fn recv_timeout(&self, timeout: Duration) -> Option<PipeEvent> {
+        self.receiver.recv_timeout(timeout).ok()
+    }
+}
+
+impl Iterator for PipeEvents {
+    type Item = PipeEvent;
+
+    fn next(&mut self) -> Option<PipeEvent> {
+        self.recv()
+    }
+}
+
+/// The subscribers to a pipe's events
+pub(crate) struct Events {
+    subscribers: Mutex<Vec<Sender<PipeEvent>>>,
+}
+
+impl Events {
+    pub(crate) fn new() -> Self {
+        Events { subscribers: Mutex::new(Vec::new()) }
+    }
+
+    pub(crate) fn subscribe(&self) -> PipeEvents {
+        let (sender, receiver) = crossbeam_channel::unbounded();
+        self.subscribers.lock().unwrap_or_else(|e| e.into_inner()).push(sender);
+        PipeEvents { receiver }
+    }
+
+    pub(crate) fn emit(&self, event: PipeEvent) {
+        let mut subscribers = self.subscribers.lock().unwrap_or_else(|e| e.into_inner());
+        subscribers.retain(|subscriber| subscriber.send(event).is_ok());
+    }
+}
@@ -0,0 +1,52 @@
+use std::sync::{Condvar, Mutex, MutexGuard};
+
+struct Tickets {
+    next: u64,
+    serving: u64,
+}
+
+/// Admits writers one at a time, in the order they arrived.
+///
+/// A writer that finishes its send and immediately sends again has to queue up behind everyone
+/// already waiting, so contending writers are served round-robin.
+pub(crate) struct Turnstile {
+    tickets: Mutex<Tickets>,
+    turn: Condvar,
+}
+
+/// A writer's turn at the turnstile, which passes to the next writer when dropped
+pub(crate) struct Turn<'a> {
+    turnstile: &'a Turnstile,
+}
+
+impl Turnstile {
+    pub(crate) fn new() -> Self {
+        Turnstile {
+            tickets: Mutex::new(Tickets { next: 0, serving: 0 }),
+            turn: Condvar::new(),
+        }
+    }
+
+    /// Blocks until every writer that arrived earlier has had its turn
+    pub(crate) fn enter(&self) -> Turn<'_> {
+        let mut tickets = self.lock();
+        let ticket = tickets.next;
+        tickets.next += 1;
+        while tickets.serving != ticket {
+            tickets = self.turn.wait(tickets).unwrap_or_else(|e| e.into_inner());
+        }
+
+        Turn { turnstile: self }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Tickets> {
+        self.tickets.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl Drop for Turn<'_> {
+    fn drop(&mut self) {
+        self.turnstile.lock().serving += 1;
+        self.turnstile.turn.notify_all();
+    }
+}
@@ -0,0 +1,301 @@
+use core::cell::UnsafeCell;
+use core::cmp::min;
+use core::hint::spin_loop;
+use core::ptr;
+use core::sync::atomic::{AtomicU8, AtomicUsize, Ordering};
+#[cfg(feature = "std")]
+use std::io::{self, Read, Write};
+#[cfg(not(feature = "std"))]
+use io::{self, Read, Write};
+
+const READER: u8 = 1;
+const WRITER: u8 = 2;
+
+#[cfg(feature = "std")]
+fn broken_pipe() -> io::Error {
+    ::epipe()
+}
+
+#[cfg(not(feature = "std"))]
+fn broken_pipe() -> io::Error {
+    io::Error::BrokenPipe
+}
+
+/// A pipe whose storage is a fixed `N` byte array, so that it needs no heap allocation.
+///
+/// The pipe can be placed in a `static` and split into its reader and writer ends with `split()`.
+/// Blocked ends busy-wait, and `try_read()`/`try_write()` never block at all, making the writer
+/// usable from contexts such as interrupt handlers.
+///
+/// ```
+/// use pipe::StaticPipe;
+///
+/// static PIPE: StaticPipe<64> = StaticPipe::new();
+///
+/// let (mut reader, mut writer) = PIPE.split().unwrap();
+/// assert_eq!(writer.try_write(b"hello"), 5);
+/// let mut buf = [0; 8];
+/// assert_eq!(reader.try_read(&mut buf), 5);
+/// ```
+pub struct StaticPipe<const N: usize> {
+    buffer: UnsafeCell<[u8; N]>,
+    /// Where the reader is, modulo `2 * N` so that a full pipe can be told apart from an empty one
+    head: AtomicUsize,
+    /// Where the writer is, modulo `2 * N`
+    tail: AtomicUsize,
+    ends: AtomicU8,
+}
+
+// SAFETY: the reader and writer only ever access disjoint regions of the buffer, and there is at
+// most one of each
+unsafe impl<const N: usize> Sync for StaticPipe<N> { }
+
+/// The `Read` end of a `StaticPipe`
+pub struct StaticPipeReader<'a, const N: usize> {
+    pipe: &'a StaticPipe<N>,
+}
+
+/// The `Write` end of a `StaticPipe`
+pub struct StaticPipeWriter<'a, const N: usize> {
+    pipe: &'a StaticPipe<N>,
+}
+
+impl<const N: usize> StaticPipe<N> {
+    /// Creates an empty pipe
+    ///
+    /// # Panics
+    ///
+    /// Panics if `N` is zero, or too large for positions within `2 * N` to be added up.
+    pub const fn new() -> Self {
+        assert!(N > 0, "StaticPipe must have a non-zero size");
+        assert!(N <= usize::MAX / 4, "StaticPipe is too large");
+
+        StaticPipe {
+            buffer: UnsafeCell::new([0; N]),
+            head: AtomicUsize::new(0),
+            tail: AtomicUsize::new(0),
+            ends: AtomicU8::new(0),
+        }
+    }
+
+    /// Splits the pipe into its reader and writer ends
+    ///
+    /// Returns `None` if either end from a previous split is still alive. Otherwise the pipe is
+    /// emptied and can be used again.
+    pub fn split(&self) -> Option<(StaticPipeReader<'_, N>, StaticPipeWriter<'_, N>)> {
+        self.ends.compare_exchange(0, READER | WRITER, Ordering::Acquire, Ordering::Relaxed).ok()?;
+        self.head.store(0, Ordering::Relaxed);
+        self.tail.store(0, Ordering::Release);
+
+        Some((StaticPipeReader { pipe: self }, StaticPipeWriter { pipe: self }))
+    }
+
+    /// Returns the number of bytes the pipe can hold
+    pub fn capacity(&self) -> usize {
+        N
+    }
+
+    fn has(&self, end: u8) -> bool {
+        self.ends.load(Ordering::Acquire) & end != 0
+    }
+
+    fn base(&self) -> *mut u8 {
+        self.buffer.get() as *mut u8
+    }
+
+    /// Returns the number of bytes from position `from` up to position `to`
+    fn distance(from: usize, to: usize) -> usize {
+        (to + 2 * N - from) % (2 * N)
+    }
+
+    /// Returns the position `len` bytes past `index`
+    ///
+    /// Positions wrap around at `2 * N` rather than at `usize::MAX`, which isn't a multiple of `N`
+    /// in general, so that they keep mapping onto the right slot of the buffer.
+    fn advance(index: usize, len: usize) -> usize {
+        (index + len) % (2 * N)
+    }
+}
+
+impl<const N: usize> Default for StaticPipe<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const N: usize> StaticPipeReader<'_, N> {
+    /// Copies as much queued data as possible into `buf` without blocking, returning the number
+    /// of bytes read
+    pub fn try_read(&mut self, buf: &mut [u8]) -> usize {
+        let pipe = self.pipe;
+        let head = pipe.head.load(Ordering::Relaxed);
+        let tail = pipe.tail.load(Ordering::Acquire);
+        let len = min(buf.len(), StaticPipe::<N>::distance(head, tail));
+
+        let start = head % N;
+        let first = min(len, N - start);
+        // SAFETY: `head..tail` has been written and released by the writer, which won't touch it
+        // until `head` is advanced past it
+        unsafe {
+            ptr::copy_nonoverlapping(pipe.base().add(start), buf.as_mut_ptr(), first);
+            ptr::copy_nonoverlapping(pipe.base(), buf.as_mut_ptr().add(first), len - first);
+        }
+
+        pipe.head.store(StaticPipe::<N>::advance(head, len), Ordering::Release);
+        len
+    }
+
+    /// Returns the number of bytes waiting to be read
+    pub fn len(&self) -> usize {
+        StaticPipe::<N>::distance(self.pipe.head.load(Ordering::Relaxed), self.pipe.tail.load(Ordering::Acquire))
+    }
+
+    /// Returns `true` if no data is waiting to be read
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl<const N: usize> Read for StaticPipeReader<'_, N> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0)
+        }
+
+        loop {
+            // check for the writer first, so that its final write isn't missed
+            let closed = !self.pipe.has(WRITER);
+            match self.try_read(buf) {
+                0 if !closed => spin_loop(),
+                len => return Ok(len),
+            }
+        }
+    }
+}
+
+impl<const N: usize> Drop for StaticPipeReader<'_, N> {
+    fn drop(&mut self) {
+        self.pipe.ends.fetch_and(!READER, Ordering::Release);
+    }
+}
+
+impl<const N: usize> StaticPipeWriter<'_, N> {
+    /// Copies as much of `buf` as fits into the pipe without blocking, returning the number of
+    /// bytes written
+    pub fn try_write(&mut self, buf: &[u8]) -> usize {
+        let pipe = self.pipe;
+        let tail = pipe.tail.load(Ordering::Relaxed);
+        let head = pipe.head.load(Ordering::Acquire);
+        let len = min(buf.len(), N - StaticPipe::<N>::distance(head, tail));
+
+        let start = tail % N;
+        let first = min(len, N - start);
+        // SAFETY: `tail..head + N` has been released by the reader, which won't touch it until
+        // `tail` is advanced past it
+        unsafe {
+            ptr::copy_nonoverlapping(buf.as_ptr(), pipe.base().add(start), first);
+            ptr::copy_nonoverlapping(buf.as_ptr().add(first), pipe.base(), len - first);
+        }
+
+        pipe.tail.store(StaticPipe::<N>::advance(tail, len), Ordering::Release);
+        len
+    }
+
+    /// Returns `true` if the reader end still exists
+    pub fn is_connected(&self) -> bool {
+        self.pipe.has(READER)
+    }
+}
+
+impl<const N: usize> Write for StaticPipeWriter<'_, N> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0)
+        }
+
+        loop {
+            if !self.is_connected() {
+                return Err(broken_pipe())
+            }
+            match self.try_write(buf) {
+                0 => spin_loop(),
+                len => return Ok(len),
+            }
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl<const N: usize> Drop for StaticPipeWriter<'_, N> {
+    fn drop(&mut self) {
+        self.pipe.ends.fetch_and(!WRITER, Ordering::Release);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread::spawn;
+    use std::vec::Vec;
+    use super::*;
+
+    static PIPE: StaticPipe<16> = StaticPipe::new();
+
+    #[test]
+    fn static_pipe() {
+        let (mut r, mut w) = PIPE.split().unwrap();
+        assert!(PIPE.split().is_none());
+        let i: Vec<u8> = (0..100).collect();
+        let guard = {
+            let i = i.clone();
+            spawn(move || {
+                w.write_all(&i).unwrap();
+            })
+        };
+
+        let mut o = Vec::new();
+        let mut buf = [0; 7];
+        loop {
+            match r.read(&mut buf).unwrap() {
+                0 => break,
+                n => o.extend_from_slice(&buf[..n]),
+            }
+        }
+        assert_eq!(i, o);
+
+        guard.join().unwrap();
+        drop(r);
+        assert!(PIPE.split().is_some());
+    }
+
+    #[test]
+    fn static_pipe_wrap() {
+        // the positions wrap around many times, with `N` not being a power of two
+        let pipe = StaticPipe::<3>::new();
+        let (mut r, mut w) = pipe.split().unwrap();
+        let mut buf = [0; 3];
+        for i in 0..1000u32 {
+            let data = [i as u8, (i + 1) as u8, (i + 2) as u8];
+            let len = (i % 3 + 1) as usize;
+            assert_eq!(w.try_write(&data[..len]), len);
+            assert_eq!(w.try_write(&data), 3 - len);
+            assert_eq!(r.len(), 3);
+            assert_eq!(r.try_read(&mut buf), 3);
+            assert_eq!(&buf[..len], &data[..len]);
+            assert_eq!(&buf[len..], &data[..3 - len]);
+        }
+        assert!(r.is_empty());
+    }
+
+    #[test]
+    fn static_pipe_full() {
+        let pipe = StaticPipe::<4>::new();
+        let (r, mut w) = pipe.split().unwrap();
+        assert_eq!(w.try_write(b"hello"), 4);
+        assert_eq!(w.try_write(b"o"), 0);
+        drop(r);
+        assert!(w.write(b"o").is_err());
+    }
+}
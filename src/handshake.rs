@@ -0,0 +1,84 @@
+use std::io;
+use std::time::{Duration, Instant};
+use {Chunk, PipeReader, PipeWriter};
+
+/// Greets the other end of a pair of pipes with `hello`, and waits for it to reply with `expected`
+///
+/// This is the client side of an initial exchange, such as a version negotiation, over pipes set
+/// up as a socket pair (see `bipipe()`); the other end calls `handshake_server()`. Fails with
+/// `io::ErrorKind::TimedOut` if the exchange doesn't complete within `timeout`, or with
+/// `io::ErrorKind::InvalidData` if the reply doesn't match.
+///
+/// # Example
+///
+/// ```
+/// use std::thread::spawn;
+/// use std::time::Duration;
+///
+/// let (mut client_read, server_write) = pipe::pipe();
+/// let (mut server_read, client_write) = pipe::pipe();
+/// let timeout = Duration::from_secs(5);
+/// let server = spawn(move || {
+///     pipe::handshake_server(&mut server_read, &server_write, b"HELLO v1", b"OK", timeout)
+/// });
+///
+/// pipe::handshake_client(&mut client_read, &client_write, b"HELLO v1", b"OK", timeout).unwrap();
+/// server.join().unwrap().unwrap();
+/// ```
+pub fn handshake_client(reader: &mut PipeReader, writer: &PipeWriter, hello: &[u8], expected: &[u8], timeout: Duration) -> io::Result<()> {
+    let deadline = Instant::now() + timeout;
+    send(writer, hello, deadline)?;
+    expect(reader, expected, deadline)
+}
+
+/// Waits for the other end of a pair of pipes to greet with `expected`, and replies with `reply`
+///
+/// This is the server side of `handshake_client()`, and fails the same way. A greeting that
+/// doesn't match isn't replied to.
+pub fn handshake_server(reader: &mut PipeReader, writer: &PipeWriter, expected: &[u8], reply: &[u8], timeout: Duration) -> io::Result<()> {
+    let deadline = Instant::now() + timeout;
+    expect(reader, expected, deadline)?;
+    send(writer, reply, deadline)
+}
+
+fn send(writer: &PipeWriter, data: &[u8], deadline: Instant) -> io::Result<()> {
+    writer.send_chunk_until(Chunk::from(data), Some(deadline))
+        .map_err(From::from)
+}
+
+fn expect(reader: &mut PipeReader, expected: &[u8], deadline: Instant) -> io::Result<()> {
+    let mut data = vec![0; expected.len()];
+    reader.read_exact_deadline(&mut data, deadline)?;
+    match data == expected {
+        true => Ok(()),
+        false => Err(io::Error::new(io::ErrorKind::InvalidData, format!("handshake expected {:?} but received {:?}", String::from_utf8_lossy(expected), String::from_utf8_lossy(&data)))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread::spawn;
+    use pipe;
+    use super::*;
+
+    #[test]
+    fn handshake_mismatch() {
+        let (mut client_read, server_write) = pipe();
+        let (mut server_read, client_write) = pipe();
+        let timeout = Duration::from_secs(5);
+        let server = spawn(move || handshake_server(&mut server_read, &server_write, b"v2", b"ok", timeout));
+
+        let err = handshake_client(&mut client_read, &client_write, b"v1", b"ok", timeout).unwrap_err();
+        // the server hangs up rather than replying
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+        assert_eq!(server.join().unwrap().unwrap_err().kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn handshake_timeout() {
+        let (mut r, _w) = pipe();
+        let (_r2, w2) = pipe();
+        let err = handshake_client(&mut r, &w2, b"hi", b"ok", Duration::from_millis(10)).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+    }
+}
@@ -0,0 +1,274 @@
+use std::cmp::min;
+use std::collections::VecDeque;
+use std::io::{self, BufRead, Read, Write};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use {check_consume, Checkpoint};
+use checkpoint::invalid;
+
+struct State {
+    history: VecDeque<u8>,
+    capacity: usize,
+    written: u64,
+    writers: usize,
+}
+
+impl State {
+    /// The stream position of the oldest byte still retained
+    fn start(&self) -> u64 {
+        self.written - self.history.len() as u64
+    }
+}
+
+struct Shared {
+    state: Mutex<State>,
+    changed: Condvar,
+}
+
+impl Shared {
+    fn lock(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn subscribe(self: &Arc<Self>) -> HistoryPipeReader {
+        let position = self.lock().start();
+        HistoryPipeReader { shared: self.clone(), buffer: Vec::new(), consumed: 0, position, skipped: 0 }
+    }
+}
+
+/// The `Read` end of a history pipe (see `pipe_with_history()`)
+pub struct HistoryPipeReader {
+    shared: Arc<Shared>,
+    buffer: Vec<u8>,
+    consumed: usize,
+    position: u64,
+    skipped: u64,
+}
+
+/// The `Write` end of a history pipe (see `pipe_with_history()`)
+pub struct HistoryPipeWriter {
+    shared: Arc<Shared>,
+}
+
+/// Creates a memory pipe that retains the last `history` bytes written, for readers that attach
+/// later on
+///
+/// Any number of readers can be attached with `subscribe()`, and each one first receives the
+/// retained history, then everything written from then on, like `tail -f`. Writers never block
+/// and never fail, even with no readers attached; instead, a reader that falls more than
+/// `history` bytes behind skips ahead to the oldest data still retained (see
+/// `HistoryPipeReader::skipped()`).
+///
+/// # Example
+///
+/// ```
+/// use std::io::{Read, Write};
+///
+/// let (_, mut writer) = pipe::pipe_with_history(1024);
+/// writer.write_all(b"starting up\n").unwrap();
+/// let mut console = writer.subscribe();
+/// writer.write_all(b"ready\n").unwrap();
+/// drop(writer);
+///
+/// let mut log = String::new();
+/// console.read_to_string(&mut log).unwrap();
+/// assert_eq!(log, "starting up\nready\n");
+/// ```
+pub fn pipe_with_history(history: usize) -> (HistoryPipeReader, HistoryPipeWriter) {
+    let shared = Arc::new(Shared {
+        state: Mutex::new(State {
+            history: VecDeque::with_capacity(history),
+            capacity: history,
+            written: 0,
+            writers: 1,
+        }),
+        changed: Condvar::new(),
+    });
+
+    (shared.subscribe(), HistoryPipeWriter { shared })
+}
+
+impl HistoryPipeReader {
+    /// Attaches another reader to the pipe, starting with the history it currently retains
+    pub fn subscribe(&self) -> HistoryPipeReader {
+        self.shared.subscribe()
+    }
+
+    /// Returns the number of bytes the reader missed by falling too far behind the writers
+    pub fn skipped(&self) -> u64 {
+        self.skipped
+    }
+
+    /// Returns a reference to the internally buffered data.
+    pub fn buffer(&self) -> &[u8] {
+        &self.buffer[self.consumed..]
+    }
+
+    /// Returns a checkpoint at the next byte to be read, which this or any other reader of the
+    /// pipe can resume from with `resume_from()`
+    pub fn checkpoint(&self) -> Checkpoint {
+        let unread = (self.buffer.len() - self.consumed) as u64;
+        Checkpoint::new(Arc::as_ptr(&self.shared) as usize, self.position - unread)
+    }
+
+    /// Moves the reader to `checkpoint`, so that it picks up where the reader that took it left
+    /// off rather than replaying the whole history or starting at the end
+    ///
+    /// If the history no longer reaches back that far, the reader skips ahead to the oldest data
+    /// it retains (see `skipped()`). Fails with `io::ErrorKind::InvalidInput` if the checkpoint
+    /// wasn't taken on this pipe.
+    pub fn resume_from(&mut self, checkpoint: Checkpoint) -> io::Result<()> {
+        self.position = checkpoint.check(Arc::as_ptr(&self.shared) as usize)?;
+        if self.position > self.shared.lock().written {
+            return Err(invalid("checkpoint is past the end of the pipe"))
+        }
+        self.buffer.clear();
+        self.consumed = 0;
+        Ok(())
+    }
+}
+
+impl BufRead for HistoryPipeReader {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        if self.consumed >= self.buffer.len() {
+            let mut state = self.shared.lock();
+            while self.position >= state.written && state.writers > 0 {
+                state = self.shared.changed.wait(state).unwrap_or_else(|e| e.into_inner());
+            }
+
+            let start = state.start();
+            if self.position < start {
+                self.skipped += start - self.position;
+                self.position = start;
+            }
+
+            let offset = (self.position - start) as usize;
+            let (front, back) = state.history.as_slices();
+            self.buffer.clear();
+            self.consumed = 0;
+            if offset < front.len() {
+                self.buffer.extend_from_slice(&front[offset..]);
+                self.buffer.extend_from_slice(back);
+            } else {
+                self.buffer.extend_from_slice(&back[offset - front.len()..]);
+            }
+            self.position = state.written;
+        }
+
+        Ok(&self.buffer[self.consumed..])
+    }
+
+    fn consume(&mut self, amt: usize) {
+        check_consume(amt, self.buffer.len() - self.consumed);
+        self.consumed += amt
+    }
+}
+
+impl Read for HistoryPipeReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+
+        let internal = self.fill_buf()?;
+
+        let len = min(buf.len(), internal.len());
+        if len > 0 {
+            buf[..len].copy_from_slice(&internal[..len]);
+            self.consume(len);
+        }
+        Ok(len)
+    }
+}
+
+impl HistoryPipeWriter {
+    /// Attaches a reader to the pipe, starting with the history it currently retains
+    pub fn subscribe(&self) -> HistoryPipeReader {
+        self.shared.subscribe()
+    }
+}
+
+impl Clone for HistoryPipeWriter {
+    fn clone(&self) -> Self {
+        self.shared.lock().writers += 1;
+        HistoryPipeWriter { shared: self.shared.clone() }
+    }
+}
+
+/// Writes always succeed in full, as the oldest history is dropped to make room.
+impl Write for HistoryPipeWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut state = self.shared.lock();
+        let retained = &buf[buf.len() - min(buf.len(), state.capacity)..];
+        let excess = (state.history.len() + retained.len()).saturating_sub(state.capacity);
+        state.history.drain(..excess);
+        state.history.extend(retained);
+        state.written += buf.len() as u64;
+        self.shared.changed.notify_all();
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Drop for HistoryPipeWriter {
+    fn drop(&mut self) {
+        let mut state = self.shared.lock();
+        state.writers -= 1;
+        if state.writers == 0 {
+            self.shared.changed.notify_all();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Read, Write};
+    use std::thread::spawn;
+    use super::*;
+
+    #[test]
+    fn history_then_live() {
+        let (mut r, mut w) = pipe_with_history(4);
+        w.write_all(b"abcdef").unwrap();
+        let mut late = r.subscribe();
+        assert_eq!(late.fill_buf().unwrap(), b"cdef");
+        late.consume(4);
+
+        let guard = spawn(move || w.write_all(b"gh").unwrap());
+        assert_eq!(late.fill_buf().unwrap(), b"gh");
+        guard.join().unwrap();
+        let mut o = Vec::new();
+        late.read_to_end(&mut o).unwrap();
+        assert_eq!(o, b"gh");
+        assert_eq!(late.skipped(), 0);
+
+        // the first reader was attached from the start, but fell behind
+        o.clear();
+        r.read_to_end(&mut o).unwrap();
+        assert_eq!(o, b"efgh");
+        assert_eq!(r.skipped(), 4);
+    }
+
+    #[test]
+    fn history_checkpoint() {
+        let (mut r, mut w) = pipe_with_history(16);
+        w.write_all(b"abcdef").unwrap();
+        let mut buf = [0; 2];
+        r.read_exact(&mut buf).unwrap();
+        let checkpoint = r.checkpoint();
+        assert_eq!(checkpoint.offset(), 2);
+        drop(r);
+
+        let mut resumed = w.subscribe();
+        resumed.resume_from(checkpoint).unwrap();
+        drop(w);
+        let mut o = Vec::new();
+        resumed.read_to_end(&mut o).unwrap();
+        assert_eq!(o, b"cdef");
+
+        let (other, _) = pipe_with_history(16);
+        assert_eq!(resumed.resume_from(other.checkpoint()).unwrap_err().kind(), io::ErrorKind::InvalidInput);
+    }
+}
@@ -0,0 +1,66 @@
+//! Minimal stand-ins for the `std::io` traits, used when the `std` feature is disabled
+
+use core::fmt;
+
+/// The error type for pipe I/O without `std`
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Error {
+    /// The other end of the pipe has been dropped
+    BrokenPipe,
+    /// A `write_all` call could not write the whole buffer
+    WriteZero,
+    /// A `read_exact` call hit EOF before filling the buffer
+    UnexpectedEof,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match *self {
+            Error::BrokenPipe => "pipe reader has been dropped",
+            Error::WriteZero => "failed to write whole buffer",
+            Error::UnexpectedEof => "failed to fill whole buffer",
+        })
+    }
+}
+
+/// A specialized `Result` type for pipe I/O without `std`
+pub type Result<T> = core::result::Result<T, Error>;
+
+/// A minimal version of `std::io::Read`
+pub trait Read {
+    /// Pull some bytes from this source into the specified buffer, returning how many bytes were
+    /// read. A return value of 0 signifies EOF.
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize>;
+
+    /// Read the exact number of bytes required to fill `buf`
+    fn read_exact(&mut self, mut buf: &mut [u8]) -> Result<()> {
+        while !buf.is_empty() {
+            match self.read(buf)? {
+                0 => return Err(Error::UnexpectedEof),
+                n => buf = &mut buf[n..],
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A minimal version of `std::io::Write`
+pub trait Write {
+    /// Write a buffer into this writer, returning how many bytes were written
+    fn write(&mut self, buf: &[u8]) -> Result<usize>;
+
+    /// Flush this output stream, ensuring that all intermediately buffered contents reach their
+    /// destination
+    fn flush(&mut self) -> Result<()>;
+
+    /// Attempts to write an entire buffer into this writer
+    fn write_all(&mut self, mut buf: &[u8]) -> Result<()> {
+        while !buf.is_empty() {
+            match self.write(buf)? {
+                0 => return Err(Error::WriteZero),
+                n => buf = &buf[n..],
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,99 @@
+use std::collections::VecDeque;
+use std::time::Duration;
+
+/// How many of the most recent samples the percentiles are taken over
+const WINDOW: usize = 1024;
+
+/// Handoff latencies of the chunks a reader received, from the time they were sent (see
+/// `PipeReader::latency_stats()`)
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct LatencyStats {
+    /// Number of chunks measured since the reader was created
+    pub samples: u64,
+    /// Median latency of the most recent chunks
+    pub p50: Duration,
+    /// 90th percentile latency of the most recent chunks
+    pub p90: Duration,
+    /// 99th percentile latency of the most recent chunks
+    pub p99: Duration,
+    /// Highest latency of the most recent chunks
+    pub max: Duration,
+}
+
+/// The latencies of the most recent chunks received
+#[derive(Default)]
+pub(crate) struct LatencyWindow {
+    recent: VecDeque<Duration>,
+    samples: u64,
+}
+
+impl LatencyWindow {
+    pub(crate) fn record(&mut self, latency: Duration) {
+        if self.recent.len() == WINDOW {
+            self.recent.pop_front();
+        }
+        self.recent.push_back(latency);
+        self.samples += 1;
+    }
+
+    pub(crate) fn last(&self) -> Option<Duration> {
+        self.recent.back().copied()
+    }
+
+    pub(crate) fn stats(&self) -> LatencyStats {
+        let mut sorted: Vec<_> = self.recent.iter().copied().collect();
+        sorted.sort_unstable();
+        let percentile = |p: usize| match sorted.len() {
+            0 => Duration::from_secs(0),
+            len => sorted[(len - 1) * p / 100],
+        };
+
+        LatencyStats {
+            samples: self.samples,
+            p50: percentile(50),
+            p90: percentile(90),
+            p99: percentile(99),
+            max: percentile(100),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread::{sleep, spawn};
+    use super::*;
+    use pipe;
+
+    #[test]
+    fn percentiles() {
+        let mut window = LatencyWindow::default();
+        assert_eq!(window.stats(), LatencyStats::default());
+        for ms in (1..=WINDOW as u64 + 100).rev() {
+            window.record(Duration::from_millis(ms));
+        }
+        let stats = window.stats();
+        assert_eq!(stats.samples, WINDOW as u64 + 100);
+        assert_eq!(stats.max, Duration::from_millis(WINDOW as u64));
+        assert_eq!(stats.p50, Duration::from_millis(WINDOW as u64 / 2));
+        assert_eq!(window.last(), Some(Duration::from_millis(1)));
+    }
+
+    #[test]
+    fn chunk_latency() {
+        let (mut r, mut w) = pipe();
+        w.set_timestamps(true);
+        assert_eq!(r.last_chunk_latency(), None);
+        let probe = w.clone();
+        let guard = spawn(move || w.send(&b"data"[..]).unwrap());
+        while probe.pressure().queued_bytes == 0 {
+            std::thread::yield_now();
+        }
+        drop(probe);
+        sleep(Duration::from_millis(10));
+
+        r.recv_chunk().unwrap().unwrap();
+        assert!(r.last_chunk_latency().unwrap() >= Duration::from_millis(10));
+        assert_eq!(r.latency_stats().samples, 1);
+        guard.join().unwrap();
+    }
+}
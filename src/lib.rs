@@ -1,5 +1,5 @@
 #![deny(missing_docs)]
-#![doc(html_root_url = "https://docs.rs/pipe/0.4.0")]
+#![doc(html_root_url = "https://docs.rs/pipe/0.5.0")]
 #![cfg_attr(feature = "unstable-doc-cfg", feature(doc_cfg))]
 
 //! Synchronous in-memory pipe
@@ -7,6 +7,7 @@
 //! ## Example
 //!
 //! ```
+//! # #[cfg(feature = "std")] {
 //! use std::thread::spawn;
 //! use std::io::{Read, Write};
 //!
@@ -19,422 +20,247 @@
 //! read.read_to_string(&mut s).unwrap();
 //!
 //! assert_eq!(&s, message);
+//! # }
 //! ```
+//!
+//! ## `no_std`
+//!
+//! Disabling the default `std` feature builds the crate with only `alloc`. The basic `pipe()` is
+//! then backed by a spin lock rather than a channel, and implements the minimal `io::Read` and
+//! `io::Write` traits provided by this crate instead of their `std::io` counterparts.
+//!
+//! This is also the build without any dependencies. The `std` pipes are built on
+//! `crossbeam-channel`: besides being exposed by `PipeWriter::sender()` and
+//! `PipeReader::into_inner()`, its select, deadline and timeout operations back `PipeSet`, the
+//! `WaitStrategy` options and the timed sends and receives, so a `std` build without it would
+//! need its own implementation of each rather than a different feature selection.
+
+#![cfg_attr(not(any(feature = "std", test)), no_std)]
 
 #[cfg(feature="readwrite")]
 extern crate readwrite;
+#[cfg(feature = "std")]
 extern crate crossbeam_channel;
-
-use crossbeam_channel::{Sender, Receiver, SendError, TrySendError};
-use std::io::{self, BufRead, Read, Write};
-use std::cmp::min;
-use std::mem::replace;
-use std::hint::unreachable_unchecked;
+#[cfg(feature = "zstd")]
+extern crate zstd;
+#[cfg(feature = "encryption")]
+extern crate chacha20poly1305;
+#[cfg(all(feature = "readiness", unix))]
+extern crate libc;
+#[cfg(feature = "tokio")]
+extern crate tokio;
+#[cfg(feature = "tokio-util")]
+extern crate tokio_util;
+#[cfg(feature = "http-body")]
+extern crate http_body;
+#[cfg(feature = "http-body")]
+extern crate bytes;
+#[cfg(test)]
+extern crate futures;
+#[cfg(not(feature = "std"))]
+extern crate alloc;
+#[cfg(any(feature = "std", test))]
+extern crate core;
+
+#[cfg(feature = "std")]
+use std::io;
+
+#[cfg(feature = "std")]
+mod chunk;
+#[cfg(feature = "std")]
+mod channel;
+#[cfg(feature = "std")]
+mod error;
+#[cfg(feature = "std")]
+mod clock;
+#[cfg(feature = "std")]
+mod ratelimit;
+#[cfg(feature = "std")]
+mod progress;
+#[cfg(feature = "std")]
+mod spool;
+#[cfg(feature = "std")]
+mod budget;
+#[cfg(feature = "std")]
+mod pool;
+#[cfg(feature = "std")]
+mod local;
+#[cfg(feature = "std")]
+mod wait;
+#[cfg(feature = "std")]
+mod priority;
+#[cfg(feature = "std")]
+mod watch;
+#[cfg(feature = "std")]
+mod set;
+#[cfg(feature = "std")]
+mod copy;
+#[cfg(feature = "std")]
+mod pump;
+#[cfg(feature = "std")]
+mod quota;
+#[cfg(feature = "std")]
+mod control;
+#[cfg(all(feature = "readiness", unix))]
+mod readiness;
+#[cfg(feature = "tokio")]
+mod async_io;
+#[cfg(feature = "tokio-util")]
+mod codec;
+#[cfg(feature = "http-body")]
+mod body;
+#[cfg(feature = "std")]
+mod fair;
+#[cfg(feature = "std")]
+mod state;
+#[cfg(feature = "std")]
+mod receipt;
+#[cfg(feature = "std")]
+mod checksum;
+#[cfg(feature = "std")]
+mod events;
+#[cfg(feature = "std")]
+mod pipeline;
+#[cfg(feature = "std")]
+mod tee;
+#[cfg(feature = "std")]
+mod history;
+#[cfg(feature = "std")]
+mod lines;
+#[cfg(feature = "std")]
+mod handshake;
+#[cfg(feature = "std")]
+mod barrier;
+#[cfg(feature = "std")]
+mod logger;
+#[cfg(feature = "std")]
+mod pull;
+#[cfg(feature = "std")]
+mod traits;
+#[cfg(feature = "std")]
+mod watchdog;
+#[cfg(feature = "std")]
+mod merge;
+#[cfg(feature = "std")]
+mod checkpoint;
+#[cfg(feature = "zstd")]
+mod compress;
+#[cfg(feature = "std")]
+mod supervisor;
+#[cfg(feature = "std")]
+mod coalesce;
+#[cfg(feature = "std")]
+mod latency;
+#[cfg(feature = "encryption")]
+mod encrypt;
+mod fixed;
+#[cfg(not(feature = "std"))]
+mod spin;
+#[cfg(not(feature = "std"))]
+pub mod io;
+#[cfg(feature = "bench")]
+#[cfg_attr(feature = "unstable-doc-cfg", doc(cfg(feature = "bench")))]
+pub mod bench;
+
+#[cfg(feature = "std")]
+pub use channel::{pipe, pipe_buffered, pipe_buffered_named, pipe_fair, pipe_named, BytesIter, Chunks, PipeReader, PipeWriter, PipeBufWriter, AbandonHook, EmptyWrites, Validator, WeakPipeWriter};
+#[cfg(feature = "bidirectional")]
+pub use channel::{bipipe, bipipe_buffered, bipipe_with_initial};
+#[cfg(feature = "std")]
+pub use chunk::Chunk;
+#[cfg(feature = "std")]
+pub use error::{PipeError, SendAllError};
+#[cfg(feature = "std")]
+pub use state::{PipeSnapshot, PipeState, Pressure};
+#[cfg(feature = "std")]
+pub use receipt::SendHandle;
+#[cfg(feature = "std")]
+pub use clock::{Clock, ManualClock, SystemClock};
+#[cfg(feature = "std")]
+pub use ratelimit::{RateLimiter, RateLimitedWriter};
+#[cfg(feature = "std")]
+pub use progress::ProgressReader;
+#[cfg(feature = "std")]
+pub use spool::{pipe_spooled, pipe_spooled_in, pipe_spooled_with_budget, SpoolStats, SpooledPipeReader, SpooledPipeWriter};
+#[cfg(feature = "zstd")]
+pub use spool::pipe_spooled_compressed;
+#[cfg(feature = "std")]
+pub use budget::{BudgetPolicy, MemoryBudget};
+#[cfg(feature = "std")]
+pub use pool::{BufferPool, PoolStats};
+#[cfg(feature = "std")]
+pub use local::{pipe_local, pipe_local_buffered, pipe_local_duplex, LocalPipeBufWriter, LocalPipeReader, LocalPipeStream, LocalPipeWriter, YieldHook};
+#[cfg(feature = "std")]
+pub use wait::WaitStrategy;
+#[cfg(feature = "std")]
+pub use priority::{pipe_priority, PriorityPipeReader, PriorityPipeWriter};
+#[cfg(feature = "std")]
+pub use watch::{pipe_watch, WatchPipeReader, WatchPipeWriter};
+#[cfg(feature = "std")]
+pub use history::{pipe_with_history, HistoryPipeReader, HistoryPipeWriter};
+#[cfg(feature = "std")]
+pub use set::PipeSet;
+#[cfg(feature = "std")]
+pub use copy::{copy, CopyEnd, CopyOptions, CopyReport};
+#[cfg(feature = "std")]
+pub use pump::{pipe_prefetch, pump, Prefetch, PumpHandle};
+#[cfg(feature = "std")]
+pub use handshake::{handshake_client, handshake_server};
+#[cfg(feature = "std")]
+pub use barrier::PipeBarrier;
+#[cfg(feature = "std")]
+pub use logger::LogWriter;
+#[cfg(feature = "std")]
+pub use pull::{pipe_pull, PullReader};
+#[cfg(feature = "std")]
+pub use traits::{as_pipe_buf_writer, as_pipe_reader, as_pipe_writer, AnyRead, AnyWrite, ChunkReceiver, ChunkSender};
+#[cfg(feature = "std")]
+pub use watchdog::{Stall, StallThresholds, Watchdog};
+#[cfg(feature = "std")]
+pub use merge::{merge_by_time, MergedReader};
+#[cfg(feature = "std")]
+pub use checkpoint::Checkpoint;
+#[cfg(feature = "zstd")]
+pub use compress::Compression;
+#[cfg(feature = "std")]
+pub use supervisor::{RestartPolicy, Supervisor, SupervisorError, TaskError, TaskFailure};
+#[cfg(feature = "std")]
+pub use latency::LatencyStats;
+#[cfg(feature = "encryption")]
+pub use encrypt::{pipe_encrypted, EncryptedPipeReader, EncryptedPipeWriter};
+#[cfg(feature = "std")]
+pub use quota::{Quota, QuotaPolicy};
+#[cfg(feature = "http-body")]
+pub use body::{pipe_body, PipeBody};
+#[cfg(feature = "tokio-util")]
+pub use codec::Frames;
+#[cfg(feature = "std")]
+pub use control::PipeControl;
+#[cfg(feature = "std")]
+pub use events::{PipeEvent, PipeEvents};
+#[cfg(feature = "std")]
+pub use pipeline::{ErrorPolicy, Pipeline, PipelineError, StageError, StageId};
+#[cfg(feature = "std")]
+pub use tee::{tee_n, TeePolicy};
+pub use fixed::{StaticPipe, StaticPipeReader, StaticPipeWriter};
+#[cfg(not(feature = "std"))]
+pub use spin::{pipe, pipe_with_relax, PipeReader, PipeWriter};
 
 // value for libstd
+#[cfg(feature = "std")]
 const DEFAULT_BUF_SIZE: usize = 8 * 1024;
 
-/// The `Read` end of a pipe (see `pipe()`)
-pub struct PipeReader {
-    receiver: Receiver<Vec<u8>>,
-    buffer: Vec<u8>,
-    position: usize,
-}
-
-/// The `Write` end of a pipe (see `pipe()`)
-#[derive(Clone)]
-pub struct PipeWriter {
-    sender: Sender<Vec<u8>>
-}
-
-/// The `Write` end of a pipe (see `pipe()`) that will buffer small writes before sending
-/// to the reader end.
-pub struct PipeBufWriter {
-    sender: Option<Sender<Vec<u8>>>,
-    buffer: Vec<u8>,
-    size: usize,
-}
-
-/// Creates a synchronous memory pipe
-pub fn pipe() -> (PipeReader, PipeWriter) {
-    let (sender, receiver) = crossbeam_channel::bounded(0);
-
-    (
-        PipeReader { receiver, buffer: Vec::new(), position: 0 },
-        PipeWriter { sender },
-    )
-}
-
-/// Creates a synchronous memory pipe with buffered writer
-pub fn pipe_buffered() -> (PipeReader, PipeBufWriter) {
-    let (tx, rx) = crossbeam_channel::bounded(0);
-
-    (PipeReader { receiver: rx, buffer: Vec::new(), position: 0 }, PipeBufWriter { sender: Some(tx), buffer: Vec::with_capacity(DEFAULT_BUF_SIZE), size: DEFAULT_BUF_SIZE } )
-}
-
-/// Creates a pair of pipes for bidirectional communication, a bit like UNIX's `socketpair(2)`.
-#[cfg(feature = "bidirectional")]
-#[cfg_attr(feature = "unstable-doc-cfg", doc(cfg(feature = "bidirectional")))]
-pub fn bipipe() -> (readwrite::ReadWrite<PipeReader, PipeWriter>, readwrite::ReadWrite<PipeReader, PipeWriter>) {
-    let (r1,w1) = pipe();
-    let (r2,w2) = pipe();
-    ((r1,w2).into(), (r2,w1).into())
-}
-
-/// Creates a pair of pipes for bidirectional communication using buffered writer, a bit like UNIX's `socketpair(2)`.
-#[cfg(feature = "bidirectional")]
-#[cfg_attr(feature = "unstable-doc-cfg", doc(cfg(feature = "bidirectional")))]
-pub fn bipipe_buffered() -> (readwrite::ReadWrite<PipeReader, PipeBufWriter>, readwrite::ReadWrite<PipeReader, PipeBufWriter>) {
-    let (r1,w1) = pipe_buffered();
-    let (r2,w2) = pipe_buffered();
-    ((r1,w2).into(), (r2,w1).into())
-}
-
+#[cfg(feature = "std")]
 fn epipe() -> io::Error {
-    io::Error::new(io::ErrorKind::BrokenPipe, "pipe reader has been dropped")
+    PipeError::Disconnected.into()
 }
 
-impl PipeWriter {
-    /// Extracts the inner `Sender` from the writer
-    pub fn into_inner(self) -> Sender<Vec<u8>> {
-        self.sender
-    }
-
-    /// Gets a reference to the underlying `Sender`
-    pub fn sender(&self) -> &Sender<Vec<u8>> {
-        &self.sender
-    }
-
-    /// Write data to the associated `PipeReader`
-    pub fn send<B: Into<Vec<u8>>>(&self, bytes: B) -> io::Result<()> {
-        self.sender.send(bytes.into())
-            .map_err(|_| epipe())
-            .map(drop)
-    }
-}
-
-impl PipeBufWriter {
-    /// Extracts the inner `Sender` from the writer, and any pending buffered data
-    pub fn into_inner(mut self) -> (Sender<Vec<u8>>, Vec<u8>) {
-        let sender = match replace(&mut self.sender, None) {
-            Some(sender) => sender,
-            None => unsafe {
-                // SAFETY: this is safe as long as `into_inner()` is the only method
-                // that clears the sender
-                unreachable_unchecked()
-            },
-        };
-        (sender, replace(&mut self.buffer, Vec::new()))
-    }
-
-    #[inline]
-    /// Gets a reference to the underlying `Sender`
-    pub fn sender(&self) -> &Sender<Vec<u8>> {
-        match &self.sender {
-            Some(sender) => sender,
-            None => unsafe {
-                // SAFETY: this is safe as long as `into_inner()` is the only method
-                // that clears the sender, and this fn is never called afterward
-                unreachable_unchecked()
-            },
-        }
-    }
-
-    /// Returns a reference to the internally buffered data.
-    pub fn buffer(&self) -> &[u8] {
-        &self.buffer
-    }
-
-    /// Returns the number of bytes the internal buffer can hold without flushing.
-    pub fn capacity(&self) -> usize {
-        self.size
-    }
-}
-
-/// Creates a new handle to the `PipeBufWriter` with a fresh new buffer. Any pending data is still
-/// owned by the existing writer and should be flushed if necessary.
-impl Clone for PipeBufWriter {
-    fn clone(&self) -> Self {
-        Self {
-            sender: self.sender.clone(),
-            buffer: Vec::with_capacity(self.size),
-            size: self.size,
-        }
-    }
-}
-
-impl PipeReader {
-    /// Extracts the inner `Receiver` from the writer, and any pending buffered data
-    pub fn into_inner(mut self) -> (Receiver<Vec<u8>>, Vec<u8>) {
-        self.buffer.drain(..self.position);
-        (self.receiver, self.buffer)
-    }
-
-    /// Returns a reference to the internally buffered data.
-    pub fn buffer(&self) -> &[u8] {
-        &self.buffer[self.position..]
-    }
-}
-
-/// Creates a new handle to the `PipeReader` with a fresh new buffer. Any pending data is still
-/// owned by the existing reader and will not be accessible from the new handle.
-impl Clone for PipeReader {
-    fn clone(&self) -> Self {
-        Self {
-            receiver: self.receiver.clone(),
-            buffer: Vec::new(),
-            position: 0,
-        }
-    }
-}
-
-impl BufRead for PipeReader {
-    fn fill_buf(&mut self) -> io::Result<&[u8]> {
-        while self.position >= self.buffer.len() {
-            match self.receiver.recv() {
-                // The only existing error is EOF
-                Err(_) => break,
-                Ok(data) => {
-                    self.buffer = data;
-                    self.position = 0;
-                }
-            }
-        }
-
-        Ok(&self.buffer[self.position..])
-    }
-
-    fn consume(&mut self, amt: usize) {
-        debug_assert!(self.buffer.len() - self.position >= amt);
-        self.position += amt
-    }
-}
-
-impl Read for PipeReader {
-    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
-        if buf.is_empty() {
-            return Ok(0);
-        }
-
-        let internal = self.fill_buf()?;
-
-        let len = min(buf.len(), internal.len());
-        if len > 0 {
-            buf[..len].copy_from_slice(&internal[..len]);
-            self.consume(len);
-        }
-        Ok(len)
-    }
-}
-
-impl Write for &'_ PipeWriter {
-    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
-        let data = buf.to_vec();
-
-        self.send(data)
-            .map(|_| buf.len())
-    }
-
-    fn flush(&mut self) -> io::Result<()> {
-        Ok(())
-    }
-}
-
-impl Write for PipeWriter {
-    #[inline]
-    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
-        Write::write(&mut &*self, buf)
-    }
-
-    #[inline]
-    fn flush(&mut self) -> io::Result<()> {
-        Write::flush(&mut &*self)
-    }
-}
-
-impl Write for PipeBufWriter {
-    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
-        let buffer_len = self.buffer.len();
-        let bytes_written = if buf.len() > self.size {
-            // bypass buffering for big writes
-            buf.len()
-        } else {
-            // avoid resizing of the buffer
-            min(buf.len(), self.size - buffer_len)
-        };
-        self.buffer.extend_from_slice(&buf[..bytes_written]);
-
-        if self.buffer.len() >= self.size {
-            self.flush()?;
-        } else {
-            // reserve capacity later to avoid needless allocations
-            let data = replace(&mut self.buffer, Vec::new());
-
-            // buffer still has space but try to send it in case the other side already awaits
-            match self.sender().try_send(data) {
-                Ok(_) => self.buffer.reserve(self.size),
-                Err(TrySendError::Full(data)) =>
-                    self.buffer = data,
-                Err(TrySendError::Disconnected(data)) => {
-                    self.buffer = data;
-                    self.buffer.truncate(buffer_len);
-                    return Err(epipe())
-                },
-            }
-        }
-
-        Ok(bytes_written)
-    }
-
-    fn flush(&mut self) -> io::Result<()> {
-        if self.buffer.is_empty() {
-            Ok(())
-        } else {
-            let data = replace(&mut self.buffer, Vec::new());
-            match self.sender().send(data) {
-                Ok(_) => {
-                    self.buffer.reserve(self.size);
-                    Ok(())
-                },
-                Err(SendError(data)) => {
-                    self.buffer = data;
-                    Err(epipe())
-                },
-            }
-        }
-    }
-}
-
-/// Flushes the contents of the buffer before the writer is dropped. Errors are ignored, so it is
-/// recommended that `flush()` be used explicitly instead of relying on Drop.
+/// Guards `BufRead::consume()` implementations against skipping past the buffered data
 ///
-/// This final flush can be avoided by using `drop(writer.into_inner())`.
-impl Drop for PipeBufWriter {
-    fn drop(&mut self) {
-        if !self.buffer.is_empty() {
-            let data = replace(&mut self.buffer, Vec::new());
-            let _ = self.sender().send(data);
-        }
-    }
-}
-
-#[cfg(test)]
-mod tests {
-    use std::thread::spawn;
-    use std::io::{Read, Write};
-    use super::*;
-
-    #[test]
-    fn pipe_reader() {
-        let i = b"hello there";
-        let mut o = Vec::with_capacity(i.len());
-        let (mut r, mut w) = pipe();
-        let guard = spawn(move || {
-            w.write_all(&i[..5]).unwrap();
-            w.write_all(&i[5..]).unwrap();
-            drop(w);
-        });
-
-        r.read_to_end(&mut o).unwrap();
-        assert_eq!(i, &o[..]);
-
-        guard.join().unwrap();
-    }
-
-    #[test]
-    fn pipe_writer_fail() {
-        let i = b"hi";
-        let (r, mut w) = pipe();
-        let guard = spawn(move || {
-            drop(r);
-        });
-
-        assert!(w.write_all(i).is_err());
-
-        guard.join().unwrap();
-    }
-
-    #[test]
-    fn small_reads() {
-        let block_cnt = 20;
-        const BLOCK: usize = 20;
-        let (mut r, mut w) = pipe();
-        let guard = spawn(move || {
-            for _ in 0..block_cnt {
-                let data = &[0; BLOCK];
-                w.write_all(data).unwrap();
-            }
-        });
-
-        let mut buff = [0; BLOCK / 2];
-        let mut read = 0;
-        while let Ok(size) = r.read(&mut buff) {
-            // 0 means EOF
-            if size == 0 {
-                break;
-            }
-            read += size;
-        }
-        assert_eq!(block_cnt * BLOCK, read);
-
-        guard.join().unwrap();
-    }
-
-    #[test]
-    fn pipe_reader_buffered() {
-        let i = b"hello there";
-        let mut o = Vec::with_capacity(i.len());
-        let (mut r, mut w) = pipe_buffered();
-        let guard = spawn(move || {
-            w.write_all(&i[..5]).unwrap();
-            w.write_all(&i[5..]).unwrap();
-            w.flush().unwrap();
-            drop(w);
-        });
-
-        r.read_to_end(&mut o).unwrap();
-        assert_eq!(i, &o[..]);
-
-        guard.join().unwrap();
-    }
-
-    #[test]
-    fn pipe_writer_fail_buffered() {
-        let i = &[0; DEFAULT_BUF_SIZE * 2];
-        let (r, mut w) = pipe_buffered();
-        let guard = spawn(move || {
-            drop(r);
-        });
-
-        assert!(w.write_all(i).is_err());
-
-        guard.join().unwrap();
-    }
-
-    #[test]
-    fn small_reads_buffered() {
-        let block_cnt = 20;
-        const BLOCK: usize = 20;
-        let (mut r, mut w) = pipe_buffered();
-        let guard = spawn(move || {
-            for _ in 0..block_cnt {
-                let data = &[0; BLOCK];
-                w.write_all(data).unwrap();
-            }
-            w.flush().unwrap();
-        });
-
-        let mut buff = [0; BLOCK / 2];
-        let mut read = 0;
-        while let Ok(size) = r.read(&mut buff) {
-            // 0 means EOF
-            if size == 0 {
-                break;
-            }
-            read += size;
-        }
-        assert_eq!(block_cnt * BLOCK, read);
-
-        guard.join().unwrap();
-    }
+/// This is checked in release builds too, since reading on from a corrupted position would hand
+/// out the wrong data rather than fail.
+#[cfg(feature = "std")]
+#[track_caller]
+fn check_consume(amt: usize, available: usize) {
+    assert!(amt <= available, "consumed {} bytes with only {} available", amt, available);
 }
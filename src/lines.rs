@@ -0,0 +1,73 @@
+use crossbeam_channel::{self, Receiver};
+use std::io::{self, BufRead};
+use std::thread::{spawn, JoinHandle};
+use PipeReader;
+
+impl PipeReader {
+    /// Splits the rest of the pipe into lines on a thread of its own, and returns a channel
+    /// receiving each of them
+    ///
+    /// Lines are split like `BufRead::lines()` does, without their `\n` or `\r\n` terminator, and
+    /// a final line is still received if the pipe ends without terminating it. The returned thread
+    /// finishes at EOF, or once the channel is dropped, and returns the number of bytes read. Data
+    /// that isn't valid UTF-8 fails it with `io::ErrorKind::InvalidData`.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use std::io::Write;
+    ///
+    /// let (reader, mut writer) = pipe::pipe_buffered();
+    /// let (lines, thread) = reader.into_blocking_lines_channel();
+    /// writer.write_all(b"building...\ndone").unwrap();
+    /// drop(writer);
+    ///
+    /// assert_eq!(lines.iter().collect::<Vec<_>>(), ["building...", "done"]);
+    /// assert_eq!(thread.join().unwrap().unwrap(), 16);
+    /// ```
+    pub fn into_blocking_lines_channel(mut self) -> (Receiver<String>, JoinHandle<io::Result<u64>>) {
+        let (sender, receiver) = crossbeam_channel::unbounded();
+        let thread = spawn(move || {
+            let mut total = 0;
+            loop {
+                let mut line = String::new();
+                match self.read_line(&mut line)? {
+                    0 => break,
+                    len => total += len as u64,
+                }
+
+                if line.ends_with('\n') {
+                    line.pop();
+                    if line.ends_with('\r') {
+                        line.pop();
+                    }
+                }
+                if sender.send(line).is_err() {
+                    break
+                }
+            }
+            Ok(total)
+        });
+        (receiver, thread)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread::spawn;
+    use pipe;
+
+    #[test]
+    fn lines_channel() {
+        let (r, w) = pipe();
+        let guard = spawn(move || {
+            w.send(b"one\r\ntw").unwrap();
+            w.send(b"o\n\nthree\n").unwrap();
+        });
+
+        let (lines, thread) = r.into_blocking_lines_channel();
+        assert_eq!(lines.iter().collect::<Vec<_>>(), ["one", "two", "", "three"]);
+        assert_eq!(thread.join().unwrap().unwrap(), 16);
+        guard.join().unwrap();
+    }
+}
@@ -0,0 +1,593 @@
+use std::cell::RefCell;
+use std::cmp::min;
+use std::collections::VecDeque;
+use std::io::{self, BufRead, Read, Write};
+use std::rc::Rc;
+use std::task::{Context, Poll, Waker};
+use {check_consume, epipe, DEFAULT_BUF_SIZE};
+
+struct State {
+    queue: VecDeque<Vec<u8>>,
+    queued: usize,
+    capacity: usize,
+    writers: usize,
+    reader: bool,
+    /// Whether the reader found the pipe empty since it last received data
+    starved: bool,
+    read_waker: Option<Waker>,
+    write_waker: Option<Waker>,
+}
+
+impl State {
+    fn wake_reader(&mut self) {
+        if let Some(waker) = self.read_waker.take() {
+            waker.wake();
+        }
+    }
+
+    fn wake_writer(&mut self) {
+        if let Some(waker) = self.write_waker.take() {
+            waker.wake();
+        }
+    }
+}
+
+fn would_block() -> io::Error {
+    io::Error::new(io::ErrorKind::WouldBlock, "pipe operation would block")
+}
+
+/// Called in place of returning `WouldBlock` (see `LocalPipeReader::set_yield_hook()`)
+pub type YieldHook = Box<dyn FnMut() -> io::Result<()>>;
+
+/// Retries `op` for as long as it would block and `hook` lets it
+fn retry<T, F: FnMut() -> io::Result<T>>(hook: &mut Option<YieldHook>, mut op: F) -> io::Result<T> {
+    loop {
+        match (op(), &mut *hook) {
+            (Err(ref e), Some(hook)) if e.kind() == io::ErrorKind::WouldBlock => hook()?,
+            (res, _) => return res,
+        }
+    }
+}
+
+/// The `Read` end of a non-blocking single-threaded pipe (see `pipe_local()`)
+pub struct LocalPipeReader {
+    state: Rc<RefCell<State>>,
+    buffer: Vec<u8>,
+    position: usize,
+    hook: Option<YieldHook>,
+}
+
+/// The `Write` end of a non-blocking single-threaded pipe (see `pipe_local()`)
+pub struct LocalPipeWriter {
+    state: Rc<RefCell<State>>,
+    hook: Option<YieldHook>,
+}
+
+/// Creates a non-blocking memory pipe for use within a single thread
+///
+/// Neither end ever blocks: reads return `ErrorKind::WouldBlock` while the pipe is empty, and
+/// writes do the same once `capacity` bytes are queued. This makes the pipe usable where blocking
+/// is impossible, such as `wasm32-unknown-unknown`. The `poll_read()` and `poll_write()` methods
+/// register a `Waker` to be notified when the pipe becomes ready instead.
+///
+/// Under a cooperative scheduler, or in a test that interleaves both ends on one thread, each end
+/// can be given a yield hook instead (see `LocalPipeReader::set_yield_hook()`). Rather than
+/// failing with `WouldBlock`, the end calls its hook and tries again, so the hook can switch to
+/// whatever task drives the other end.
+///
+/// # Panics
+///
+/// Panics if `capacity` is zero.
+pub fn pipe_local(capacity: usize) -> (LocalPipeReader, LocalPipeWriter) {
+    assert!(capacity > 0, "capacity must be non-zero");
+
+    let state = Rc::new(RefCell::new(State {
+        queue: VecDeque::new(),
+        queued: 0,
+        capacity,
+        writers: 1,
+        reader: true,
+        starved: false,
+        read_waker: None,
+        write_waker: None,
+    }));
+
+    (
+        LocalPipeReader { state: state.clone(), buffer: Vec::new(), position: 0, hook: None },
+        LocalPipeWriter { state, hook: None },
+    )
+}
+
+/// One end of a pair of non-blocking single-threaded pipes (see `pipe_local_duplex()`)
+pub struct LocalPipeStream {
+    reader: LocalPipeReader,
+    writer: LocalPipeWriter,
+}
+
+/// Creates a pair of non-blocking single-threaded pipes for bidirectional communication (see
+/// `pipe_local()`)
+///
+/// # Panics
+///
+/// Panics if `capacity` is zero.
+pub fn pipe_local_duplex(capacity: usize) -> (LocalPipeStream, LocalPipeStream) {
+    let (r1, w1) = pipe_local(capacity);
+    let (r2, w2) = pipe_local(capacity);
+    (
+        LocalPipeStream { reader: r1, writer: w2 },
+        LocalPipeStream { reader: r2, writer: w1 },
+    )
+}
+
+/// The `Write` end of a non-blocking single-threaded pipe that buffers small writes (see
+/// `pipe_local_buffered()`)
+pub struct LocalPipeBufWriter {
+    writer: LocalPipeWriter,
+    buffer: Vec<u8>,
+    size: usize,
+}
+
+/// Creates a non-blocking single-threaded pipe with a buffered writer (see `pipe_local()`)
+///
+/// Like `PipeBufWriter`, the writer gathers small writes into a buffer before queueing them, but
+/// it also queues what it has buffered as soon as the reader has found the pipe empty. Data
+/// therefore never sits in the buffer while the reader's task waits for it, even if the buffer
+/// is never filled or flushed.
+///
+/// # Panics
+///
+/// Panics if `capacity` is zero.
+pub fn pipe_local_buffered(capacity: usize) -> (LocalPipeReader, LocalPipeBufWriter) {
+    let (reader, writer) = pipe_local(capacity);
+    (reader, LocalPipeBufWriter { writer, buffer: Vec::new(), size: DEFAULT_BUF_SIZE })
+}
+
+impl LocalPipeStream {
+    /// Gets a reference to the reading half of the stream
+    pub fn reader(&self) -> &LocalPipeReader {
+        &self.reader
+    }
+
+    /// Gets a mutable reference to the reading half of the stream
+    pub fn reader_mut(&mut self) -> &mut LocalPipeReader {
+        &mut self.reader
+    }
+
+    /// Gets a reference to the writing half of the stream
+    pub fn writer(&self) -> &LocalPipeWriter {
+        &self.writer
+    }
+
+    /// Gets a mutable reference to the writing half of the stream
+    pub fn writer_mut(&mut self) -> &mut LocalPipeWriter {
+        &mut self.writer
+    }
+
+    /// Splits the stream into its reading and writing halves
+    pub fn into_inner(self) -> (LocalPipeReader, LocalPipeWriter) {
+        (self.reader, self.writer)
+    }
+}
+
+impl BufRead for LocalPipeStream {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        self.reader.fill_buf()
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.reader.consume(amt)
+    }
+}
+
+impl Read for LocalPipeStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.reader.read(buf)
+    }
+}
+
+impl Write for LocalPipeStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.writer.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+impl LocalPipeReader {
+    /// Returns a reference to the internally buffered data.
+    pub fn buffer(&self) -> &[u8] {
+        &self.buffer[self.position..]
+    }
+
+    /// Attempts to read into `buf`, registering the current task to be woken once data is
+    /// available if the pipe is empty.
+    pub fn poll_read(&mut self, cx: &mut Context, buf: &mut [u8]) -> Poll<io::Result<usize>> {
+        match self.read(buf) {
+            Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {
+                self.register(cx);
+                Poll::Pending
+            },
+            res => Poll::Ready(res),
+        }
+    }
+
+    /// Wakes the current task once data is available
+    pub(crate) fn register(&self, cx: &Context) {
+        self.state.borrow_mut().read_waker = Some(cx.waker().clone());
+    }
+
+    /// Sets a hook to call whenever a read would block, after which the read is tried again
+    ///
+    /// The hook is expected to let the writer make progress, such as by yielding to a cooperative
+    /// scheduler. Any error it returns is returned by the read instead, so it can give up with
+    /// `WouldBlock` to fall back to the default behaviour, which also lets `poll_read()` return
+    /// `Pending`.
+    pub fn set_yield_hook(&mut self, hook: Option<YieldHook>) {
+        self.hook = hook;
+    }
+
+    fn fill(&mut self) -> io::Result<()> {
+        while self.position >= self.buffer.len() {
+            let mut state = self.state.borrow_mut();
+            match state.queue.pop_front() {
+                Some(data) => {
+                    state.starved = false;
+                    state.queued -= data.len();
+                    state.wake_writer();
+                    self.buffer = data;
+                    self.position = 0;
+                },
+                None if state.writers == 0 => break,
+                None => {
+                    state.starved = true;
+                    return Err(would_block())
+                },
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl BufRead for LocalPipeReader {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        let mut hook = self.hook.take();
+        let res = retry(&mut hook, || self.fill());
+        self.hook = hook;
+        res.map(move |_| &self.buffer[self.position..])
+    }
+
+    fn consume(&mut self, amt: usize) {
+        check_consume(amt, self.buffer.len() - self.position);
+        self.position += amt
+    }
+}
+
+impl Read for LocalPipeReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+
+        let internal = self.fill_buf()?;
+
+        let len = min(buf.len(), internal.len());
+        if len > 0 {
+            buf[..len].copy_from_slice(&internal[..len]);
+            self.consume(len);
+        }
+        Ok(len)
+    }
+}
+
+impl Drop for LocalPipeReader {
+    fn drop(&mut self) {
+        let mut state = self.state.borrow_mut();
+        state.reader = false;
+        state.queue.clear();
+        state.queued = 0;
+        state.wake_writer();
+    }
+}
+
+impl LocalPipeWriter {
+    /// Attempts to write `buf`, registering the current task to be woken once there is room in
+    /// the pipe if it is full.
+    pub fn poll_write(&mut self, cx: &mut Context, buf: &[u8]) -> Poll<io::Result<usize>> {
+        match self.write(buf) {
+            Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {
+                self.register(cx);
+                Poll::Pending
+            },
+            res => Poll::Ready(res),
+        }
+    }
+
+    /// Wakes the current task once there is room in the pipe
+    pub(crate) fn register(&self, cx: &Context) {
+        self.state.borrow_mut().write_waker = Some(cx.waker().clone());
+    }
+
+    /// Sets a hook to call whenever a write would block, after which the write is tried again
+    /// (see `LocalPipeReader::set_yield_hook()`)
+    pub fn set_yield_hook(&mut self, hook: Option<YieldHook>) {
+        self.hook = hook;
+    }
+
+    /// Returns the number of bytes that can be queued before writes block
+    pub fn capacity(&self) -> usize {
+        self.state.borrow().capacity
+    }
+
+    /// Changes the number of bytes that can be queued before writes block
+    ///
+    /// Growing the pipe wakes a writer waiting for room right away. Shrinking it below what is
+    /// already queued drops none of that data: writes block until the reader has drained the pipe
+    /// below the new capacity.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `capacity` is zero.
+    pub fn set_capacity(&self, capacity: usize) {
+        assert!(capacity > 0, "capacity must be non-zero");
+
+        let mut state = self.state.borrow_mut();
+        let grown = capacity > state.capacity;
+        state.capacity = capacity;
+        if grown {
+            state.wake_writer();
+        }
+    }
+
+    fn try_write(&self, buf: &[u8]) -> io::Result<usize> {
+        let mut state = self.state.borrow_mut();
+        if !state.reader {
+            return Err(epipe())
+        }
+        if buf.is_empty() {
+            return Ok(0)
+        }
+
+        // the capacity may have been shrunk below what is already queued
+        let len = min(buf.len(), state.capacity.saturating_sub(state.queued));
+        if len == 0 {
+            return Err(would_block())
+        }
+
+        state.queue.push_back(buf[..len].to_vec());
+        state.queued += len;
+        state.wake_reader();
+        Ok(len)
+    }
+}
+
+impl Clone for LocalPipeWriter {
+    fn clone(&self) -> Self {
+        self.state.borrow_mut().writers += 1;
+        LocalPipeWriter { state: self.state.clone(), hook: None }
+    }
+}
+
+impl Write for LocalPipeWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut hook = self.hook.take();
+        let res = retry(&mut hook, || self.try_write(buf));
+        self.hook = hook;
+        res
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl LocalPipeBufWriter {
+    /// Returns a reference to the internally buffered data
+    pub fn buffer(&self) -> &[u8] {
+        &self.buffer
+    }
+
+    /// Returns the number of bytes the writer buffers before queueing them
+    pub fn capacity(&self) -> usize {
+        self.size
+    }
+
+    /// Gets a reference to the underlying writer
+    pub fn get_ref(&self) -> &LocalPipeWriter {
+        &self.writer
+    }
+
+    /// Attempts to write `buf`, registering the current task to be woken once there is room if
+    /// neither the buffer nor the pipe has any
+    pub fn poll_write(&mut self, cx: &mut Context, buf: &[u8]) -> Poll<io::Result<usize>> {
+        match self.write(buf) {
+            Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {
+                self.writer.register(cx);
+                Poll::Pending
+            },
+            res => Poll::Ready(res),
+        }
+    }
+
+    /// Attempts to queue all buffered data, registering the current task to be woken once there
+    /// is room in the pipe if it is full
+    pub fn poll_flush(&mut self, cx: &mut Context) -> Poll<io::Result<()>> {
+        match self.flush() {
+            Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {
+                self.writer.register(cx);
+                Poll::Pending
+            },
+            res => Poll::Ready(res),
+        }
+    }
+
+    /// Sets a hook to call whenever a write or flush would block (see
+    /// `LocalPipeReader::set_yield_hook()`)
+    pub fn set_yield_hook(&mut self, hook: Option<YieldHook>) {
+        self.writer.set_yield_hook(hook);
+    }
+
+    /// Queues as much of the buffer as fits into the pipe
+    fn try_flush(&mut self) -> io::Result<()> {
+        while !self.buffer.is_empty() {
+            let len = self.writer.try_write(&self.buffer)?;
+            self.buffer.drain(..len);
+        }
+        Ok(())
+    }
+}
+
+/// Writes fail with `WouldBlock` only once both the buffer and the pipe are full.
+impl Write for LocalPipeBufWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return self.writer.try_write(buf)
+        }
+        if self.buffer.len() >= self.size {
+            self.flush()?;
+        }
+
+        let len = min(buf.len(), self.size - self.buffer.len());
+        self.buffer.extend_from_slice(&buf[..len]);
+        let starved = self.writer.state.borrow().starved;
+        if self.buffer.len() >= self.size || starved {
+            // the data has been accepted either way, and is queued once there is room
+            match self.try_flush() {
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => (),
+                res => res?,
+            }
+        }
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        let mut hook = self.writer.hook.take();
+        let res = retry(&mut hook, || self.try_flush());
+        self.writer.hook = hook;
+        res
+    }
+}
+
+/// Queues any buffered data that fits into the pipe before the writer is dropped. Errors are
+/// ignored, so `flush()` should be used explicitly instead of relying on Drop.
+impl Drop for LocalPipeBufWriter {
+    fn drop(&mut self) {
+        let _ = self.try_flush();
+    }
+}
+
+impl Drop for LocalPipeWriter {
+    fn drop(&mut self) {
+        let mut state = self.state.borrow_mut();
+        state.writers -= 1;
+        if state.writers == 0 {
+            state.wake_reader();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{self, Read, Write};
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::task::{Context, Poll, Wake, Waker};
+    use super::*;
+
+    struct CountWaker(AtomicUsize);
+
+    impl Wake for CountWaker {
+        fn wake(self: Arc<Self>) {
+            self.0.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    #[test]
+    fn local_would_block() {
+        let (mut r, mut w) = pipe_local(4);
+        let mut buf = [0; 8];
+        assert_eq!(r.read(&mut buf).unwrap_err().kind(), io::ErrorKind::WouldBlock);
+        assert_eq!(w.write(b"hello").unwrap(), 4);
+        assert_eq!(w.write(b"o").unwrap_err().kind(), io::ErrorKind::WouldBlock);
+        assert_eq!(r.read(&mut buf).unwrap(), 4);
+        assert_eq!(&buf[..4], b"hell");
+        drop(w);
+        assert_eq!(r.read(&mut buf).unwrap(), 0);
+    }
+
+    #[test]
+    fn local_yield_hook() {
+        let (mut r, mut w) = pipe_local(2);
+        let mut data = b"hello".iter();
+        r.set_yield_hook(Some(Box::new(move || {
+            match data.next() {
+                Some(&b) => w.write_all(&[b]),
+                None => Err(io::ErrorKind::WouldBlock.into()),
+            }
+        })));
+
+        let mut buf = [0; 5];
+        r.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"hello");
+        assert_eq!(r.read(&mut buf).unwrap_err().kind(), io::ErrorKind::WouldBlock);
+    }
+
+    #[test]
+    fn local_buffered() {
+        let (mut r, mut w) = pipe_local_buffered(4);
+        let mut buf = [0; 8];
+        w.write_all(b"a").unwrap();
+        assert_eq!(w.buffer(), b"a");
+
+        // once the reader has come up empty, writes are queued right away
+        assert_eq!(r.read(&mut buf).unwrap_err().kind(), io::ErrorKind::WouldBlock);
+        w.write_all(b"bcdef").unwrap();
+        assert_eq!(w.buffer(), b"ef");
+        assert_eq!(r.read(&mut buf).unwrap(), 4);
+        assert_eq!(&buf[..4], b"abcd");
+
+        drop(w);
+        assert_eq!(r.read(&mut buf).unwrap(), 2);
+        assert_eq!(&buf[..2], b"ef");
+    }
+
+    #[test]
+    fn local_set_capacity() {
+        let (mut r, mut w) = pipe_local(4);
+        let mut buf = [0; 8];
+        w.write_all(b"abcd").unwrap();
+        w.set_capacity(2);
+        assert_eq!(w.capacity(), 2);
+        assert_eq!(w.write(b"e").unwrap_err().kind(), io::ErrorKind::WouldBlock);
+
+        // the queued data is kept, and room only opens up once it has drained below the limit
+        assert_eq!(r.read(&mut buf).unwrap(), 4);
+        assert_eq!(w.write(b"efg").unwrap(), 2);
+        w.set_capacity(8);
+        assert_eq!(w.write(b"ghijkl").unwrap(), 6);
+        assert_eq!(r.read(&mut buf).unwrap(), 2);
+        assert_eq!(&buf[..2], b"ef");
+    }
+
+    #[test]
+    fn local_wakers() {
+        let count = Arc::new(CountWaker(AtomicUsize::new(0)));
+        let waker = Waker::from(count.clone());
+        let mut cx = Context::from_waker(&waker);
+        let (mut r, mut w) = pipe_local(2);
+        let mut buf = [0; 2];
+
+        assert!(r.poll_read(&mut cx, &mut buf).is_pending());
+        w.write_all(b"hi").unwrap();
+        assert_eq!(count.0.load(Ordering::SeqCst), 1);
+
+        assert!(w.poll_write(&mut cx, b"!").is_pending());
+        match r.poll_read(&mut cx, &mut buf) {
+            Poll::Ready(Ok(2)) => (),
+            _ => panic!("expected data"),
+        }
+        assert_eq!(count.0.load(Ordering::SeqCst), 2);
+    }
+}
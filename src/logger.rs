@@ -0,0 +1,134 @@
+use std::fmt;
+use std::io::{self, Write};
+use std::mem::take;
+use PipeWriter;
+
+/// A writer for log output that sends whole lines only (see `PipeWriter::into_log_writer()`)
+///
+/// Loggers often format a record with several writes, and a logger shared between threads may
+/// interleave them. A `LogWriter` buffers each write until it ends a line, then sends all of the
+/// complete lines as a single chunk, so lines written by different clones of the writer never mix.
+/// A trailing partial line is sent by `flush()`, or when the writer is dropped.
+pub struct LogWriter {
+    writer: PipeWriter,
+    buffer: Vec<u8>,
+}
+
+impl PipeWriter {
+    /// Converts the writer into a `LogWriter`, for installing as the output of a logger
+    ///
+    /// This lets tests capture their log output through the reading end of the pipe, which needs
+    /// to be read concurrently like that of any other pipe.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use std::io::Write;
+    ///
+    /// let (reader, writer) = pipe::pipe();
+    /// let (lines, _thread) = reader.into_blocking_lines_channel();
+    /// let mut log = writer.into_log_writer();
+    /// // env_logger::Builder::new().target(env_logger::Target::Pipe(Box::new(log)))
+    /// write!(log, "INFO ").unwrap();
+    /// writeln!(log, "started").unwrap();
+    /// assert_eq!(lines.recv().unwrap(), "INFO started");
+    /// ```
+    pub fn into_log_writer(self) -> LogWriter {
+        LogWriter { writer: self, buffer: Vec::new() }
+    }
+}
+
+impl LogWriter {
+    /// Returns a reference to the partial line buffered so far
+    pub fn buffer(&self) -> &[u8] {
+        &self.buffer
+    }
+
+    /// Gets a reference to the underlying writer
+    pub fn get_ref(&self) -> &PipeWriter {
+        &self.writer
+    }
+
+    fn send(&mut self, data: Vec<u8>) -> io::Result<()> {
+        self.writer.send_vec(data).map_err(|e| self.writer.io_error(e))
+    }
+}
+
+impl Write for LogWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.buffer.extend_from_slice(buf);
+        if let Some(end) = buf.iter().rposition(|&b| b == b'\n') {
+            let rest = self.buffer.split_off(self.buffer.len() - (buf.len() - end - 1));
+            let lines = take(&mut self.buffer);
+            self.buffer = rest;
+            self.send(lines)?;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if !self.buffer.is_empty() {
+            let data = take(&mut self.buffer);
+            self.send(data)?;
+        }
+        Ok(())
+    }
+}
+
+/// Clones the underlying writer, without any of the partial line buffered so far.
+impl Clone for LogWriter {
+    fn clone(&self) -> Self {
+        self.writer.clone().into_log_writer()
+    }
+}
+
+impl Drop for LogWriter {
+    fn drop(&mut self) {
+        let _ = self.flush();
+    }
+}
+
+impl fmt::Debug for LogWriter {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("LogWriter")
+            .field("writer", &self.writer)
+            .field("buffer", &self.buffer.len())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread::spawn;
+    use super::*;
+    use pipe;
+
+    #[test]
+    fn log_lines() {
+        let (reader, writer) = pipe();
+        let (lines, thread) = reader.into_blocking_lines_channel();
+        let log = writer.into_log_writer();
+        let threads = (0..4).map(|i| {
+            let mut log = log.clone();
+            spawn(move || for _ in 0..10 {
+                for b in format!("thread {}\n", i).bytes() {
+                    log.write_all(&[b]).unwrap();
+                }
+            })
+        }).collect::<Vec<_>>();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+
+        let mut log = log;
+        log.write_all(b"partial").unwrap();
+        assert_eq!(log.buffer(), b"partial");
+        drop(log);
+
+        let lines = lines.iter().collect::<Vec<_>>();
+        assert_eq!(lines.len(), 41);
+        assert!(lines[..40].iter().all(|line| line.len() == 8 && line.starts_with("thread ")));
+        assert_eq!(lines[40], "partial");
+        assert_eq!(thread.join().unwrap().unwrap(), 40 * 9 + 7);
+    }
+}
@@ -0,0 +1,172 @@
+use std::cmp::min;
+use std::fmt;
+use std::io::{self, BufRead, Read};
+use std::mem::replace;
+use {check_consume, Chunk, ChunkReceiver, PipeReader};
+
+/// A reader interleaving several pipes in timestamp order (see `merge_by_time()`)
+pub struct MergedReader {
+    readers: Vec<PipeReader>,
+    /// The next chunk of each reader, or `None` once it has reached EOF
+    heads: Vec<Option<Chunk>>,
+    started: bool,
+    buffer: Chunk,
+    position: usize,
+}
+
+/// Merges the chunks of several pipes into a single stream, ordered by their timestamps
+///
+/// Each pipe is expected to carry chunks in timestamp order, such as events recorded by one
+/// thread and sent with `Chunk::with_timestamp()`. The merged reader only holds the next chunk of
+/// each pipe, and delivers whichever of them is the earliest. Since a later chunk may still arrive
+/// on a pipe that is lagging behind, every pipe that hasn't reached EOF needs a chunk lined up
+/// before anything is delivered. Chunks without a timestamp count as earlier than any other, and
+/// ties go to the pipe that comes first in `readers`.
+///
+/// # Example
+///
+/// ```
+/// use std::io::Read;
+/// use std::thread::spawn;
+/// use std::time::{Duration, Instant};
+/// use pipe::Chunk;
+///
+/// let start = Instant::now();
+/// let (a, a_writer) = pipe::pipe();
+/// let (b, b_writer) = pipe::pipe();
+/// let threads = [
+///     spawn(move || a_writer.send_chunk(Chunk::from(&b"world"[..]).with_timestamp(start + Duration::from_secs(2)))),
+///     spawn(move || b_writer.send_chunk(Chunk::from(&b"hello "[..]).with_timestamp(start + Duration::from_secs(1)))),
+/// ];
+///
+/// let mut merged = String::new();
+/// pipe::merge_by_time(vec![a, b]).read_to_string(&mut merged).unwrap();
+/// assert_eq!(merged, "hello world");
+/// # for thread in threads { thread.join().unwrap().unwrap(); }
+/// ```
+pub fn merge_by_time(readers: Vec<PipeReader>) -> MergedReader {
+    MergedReader {
+        heads: readers.iter().map(|_| None).collect(),
+        readers,
+        started: false,
+        buffer: Chunk::new(),
+        position: 0,
+    }
+}
+
+impl MergedReader {
+    /// Converts the merged reader back into the readers it was created from
+    ///
+    /// Chunks that were lined up for merging are dropped.
+    pub fn into_inner(self) -> Vec<PipeReader> {
+        self.readers
+    }
+
+    /// Receives the next chunk in timestamp order, or `None` once every pipe has reached EOF
+    pub fn recv_chunk(&mut self) -> io::Result<Option<Chunk>> {
+        if self.position < self.buffer.len() {
+            let chunk = self.buffer.slice(self.position..self.buffer.len());
+            self.buffer = Chunk::new();
+            self.position = 0;
+            return Ok(Some(chunk))
+        }
+
+        if !self.started {
+            for (reader, head) in self.readers.iter_mut().zip(&mut self.heads) {
+                *head = reader.recv_chunk()?;
+            }
+            self.started = true;
+        }
+
+        let next = self.heads.iter().enumerate()
+            .filter_map(|(index, head)| head.as_ref().map(|head| (head.timestamp(), index)))
+            .min();
+        let index = match next {
+            Some((_, index)) => index,
+            None => return Ok(None),
+        };
+        // line up the pipe's next chunk before handing this one over
+        let next = self.readers[index].recv_chunk()?;
+        Ok(replace(&mut self.heads[index], next))
+    }
+}
+
+impl BufRead for MergedReader {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        while self.position >= self.buffer.len() {
+            match self.recv_chunk()? {
+                Some(chunk) => {
+                    self.buffer = chunk;
+                    self.position = 0;
+                },
+                None => break,
+            }
+        }
+
+        Ok(&self.buffer[min(self.position, self.buffer.len())..])
+    }
+
+    fn consume(&mut self, amt: usize) {
+        check_consume(amt, self.buffer.len().saturating_sub(self.position));
+        self.position += amt;
+    }
+}
+
+impl Read for MergedReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let internal = self.fill_buf()?;
+        let len = min(buf.len(), internal.len());
+        buf[..len].copy_from_slice(&internal[..len]);
+        self.consume(len);
+        Ok(len)
+    }
+}
+
+impl ChunkReceiver for MergedReader {
+    fn recv_chunk(&mut self) -> io::Result<Option<Chunk>> {
+        MergedReader::recv_chunk(self)
+    }
+}
+
+impl fmt::Debug for MergedReader {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("MergedReader")
+            .field("readers", &self.readers.len())
+            .field("buffered", &self.buffer.len().saturating_sub(self.position))
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread::spawn;
+    use std::time::{Duration, Instant};
+    use super::*;
+    use pipe;
+
+    #[test]
+    fn merge() {
+        let start = Instant::now();
+        let at = move |secs| start + Duration::from_secs(secs);
+        let mut readers = Vec::new();
+        let mut threads = Vec::new();
+        for times in [vec![1, 4, 5], vec![2, 3], vec![]] {
+            let (r, w) = pipe();
+            readers.push(r);
+            threads.push(spawn(move || for time in times {
+                w.send_chunk(Chunk::from(vec![time as u8]).with_timestamp(at(time))).unwrap();
+            }));
+        }
+
+        let mut merged = merge_by_time(readers);
+        let mut order = Vec::new();
+        while let Some(chunk) = merged.recv_chunk().unwrap() {
+            assert_eq!(chunk.timestamp(), Some(at(chunk[0] as u64)));
+            order.push(chunk[0]);
+        }
+        assert_eq!(order, [1, 2, 3, 4, 5]);
+        for thread in threads {
+            thread.join().unwrap();
+        }
+    }
+}
@@ -0,0 +1,408 @@
+use std::error::Error;
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread::spawn;
+use {pipe, pipe_buffered_named, PipeBufWriter, PipeControl, PipeReader, PipeSet, PipeWriter, DEFAULT_BUF_SIZE};
+
+type Stage = Box<dyn FnOnce(&mut PipeReader, &mut PipeBufWriter) -> io::Result<()> + Send>;
+
+/// Builds a chain of threads connected by pipes
+///
+/// The source is read on a thread of its own, and every stage runs on its own thread, reading the
+/// output of the previous stage and writing to the next one. Whatever the last stage writes is
+/// copied into the sink by the thread calling `run()`.
+///
+/// # Example
+///
+/// ```
+/// use std::io::{BufRead, Write};
+/// use pipe::Pipeline;
+///
+/// let output = Pipeline::new(&b"hello\nworld\n"[..])
+///     .then(|r, w| {
+///         for line in r.lines() {
+///             writeln!(w, "{}", line?.to_uppercase())?;
+///         }
+///         Ok(())
+///     })
+///     .sink(Vec::new())
+///     .run()
+///     .unwrap();
+/// assert_eq!(output, b"HELLO\nWORLD\n");
+/// ```
+pub struct Pipeline<R, W = io::Sink> {
+    source: R,
+    stages: Vec<Stage>,
+    sink: W,
+    policy: ErrorPolicy,
+}
+
+impl<R: Read + Send + 'static> Pipeline<R> {
+    /// Starts a pipeline reading from `source`
+    pub fn new(source: R) -> Self {
+        Pipeline {
+            source,
+            stages: Vec::new(),
+            sink: io::sink(),
+            policy: ErrorPolicy::default(),
+        }
+    }
+}
+
+impl<R: Read + Send + 'static, W: Write> Pipeline<R, W> {
+    /// Adds a stage that reads the output of the previous stage and writes to the next
+    ///
+    /// The stage's ends of the pipes are dropped once it returns, with the writer being flushed
+    /// first, unless the stage failed under `ErrorPolicy::Abort`.
+    pub fn then<F>(mut self, stage: F) -> Self where
+        F: FnOnce(&mut PipeReader, &mut PipeBufWriter) -> io::Result<()> + Send + 'static,
+    {
+        self.stages.push(Box::new(stage));
+        self
+    }
+
+    /// Sets where the output of the last stage goes, replacing any previous sink
+    pub fn sink<S: Write>(self, sink: S) -> Pipeline<R, S> {
+        Pipeline {
+            source: self.source,
+            stages: self.stages,
+            sink,
+            policy: self.policy,
+        }
+    }
+
+    /// Sets what happens to the rest of the pipeline when a stage fails
+    pub fn on_error(mut self, policy: ErrorPolicy) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    /// Runs the pipeline to completion, returning the sink
+    ///
+    /// Once a stage fails, the stage before it gets a broken pipe, so everything upstream of it
+    /// winds down, while the stages downstream are handled according to the `ErrorPolicy`. The
+    /// error then lists every part of the pipeline that failed, and singles out the failure that
+    /// caused the others.
+    pub fn run(self) -> Result<W, PipelineError> {
+        let Pipeline { mut source, stages, mut sink, policy } = self;
+        let mut threads = Vec::with_capacity(stages.len() + 1);
+
+        // every pipe is set up front, so that an abort can reach all of them
+        let pipes: Vec<_> = (0..=stages.len()).map(|index| match index {
+            0 => pipe_buffered_named("pipeline source"),
+            index => pipe_buffered_named(format!("pipeline stage {}", index - 1)),
+        }).collect();
+        let (signal, abort) = pipe();
+        let teardown = Arc::new(Teardown {
+            policy,
+            aborted: AtomicBool::new(false),
+            pipes: pipes.iter().map(|(reader, _)| reader.control()).collect(),
+            abort,
+        });
+        let mut pipes = pipes.into_iter();
+
+        let (mut reader, mut writer) = pipes.next().expect("the source has a pipe");
+        let teardown_ = teardown.clone();
+        threads.push(spawn(move || {
+            let res = io::copy(&mut source, &mut writer).map(|_| ());
+            finish(res, writer, &teardown_)
+        }));
+
+        for (stage, (next, mut output)) in stages.into_iter().zip(pipes) {
+            let mut input = reader;
+            reader = next;
+            let teardown = teardown.clone();
+            threads.push(spawn(move || {
+                let res = stage(&mut input, &mut output);
+                drop(input);
+                finish(res, output, &teardown)
+            }));
+        }
+
+        let mut output = PipeSet::new(vec![reader, signal]);
+        let res = drain(&mut output, &mut sink, &teardown.aborted);
+        drop(output);
+        let mut errors = Vec::new();
+        for (index, thread) in threads.into_iter().enumerate() {
+            let res = thread.join().unwrap_or_else(|_| Err(io::Error::other("pipeline stage panicked")));
+            if let Err(error) = res {
+                let stage = match index {
+                    0 => StageId::Source,
+                    index => StageId::Stage(index - 1),
+                };
+                errors.push(StageError { stage, error });
+            }
+        }
+        if let Err(error) = res {
+            errors.push(StageError { stage: StageId::Sink, error });
+        }
+
+        match errors.is_empty() {
+            true => Ok(sink),
+            false => Err(PipelineError::new(errors)),
+        }
+    }
+}
+
+/// What the threads of a running `Pipeline` share to wind it down
+struct Teardown {
+    policy: ErrorPolicy,
+    aborted: AtomicBool,
+    /// Every pipe of the pipeline, in order
+    pipes: Vec<PipeControl>,
+    /// Wakes the thread draining the pipeline into the sink
+    abort: PipeWriter,
+}
+
+impl Teardown {
+    /// Cuts off every pipe of the pipeline, and tells the sink's thread to stop
+    fn abort(&self) {
+        self.aborted.store(true, Ordering::SeqCst);
+        for pipe in &self.pipes {
+            pipe.shared().disconnect();
+        }
+        // fails once the sink's thread has finished
+        let _ = self.abort.send(&[0][..]);
+    }
+}
+
+/// Flushes a stage's output, applying the error policy if the stage failed
+fn finish(res: io::Result<()>, mut output: PipeBufWriter, teardown: &Teardown) -> io::Result<()> {
+    match (res, &teardown.policy) {
+        (Ok(()), _) => output.flush(),
+        (Err(e), ErrorPolicy::Abort) => {
+            teardown.abort();
+            // whatever the stage had buffered is dropped rather than flushed
+            drop(output.into_inner());
+            Err(e)
+        },
+        (Err(e), ErrorPolicy::Drain) => {
+            let _ = output.flush();
+            Err(e)
+        },
+        (Err(e), ErrorPolicy::Marker(marker)) => {
+            let _ = output.write_all(marker).and_then(|_| output.flush());
+            Err(e)
+        },
+    }
+}
+
+/// Copies the output of the pipeline into the sink, until EOF or the pipeline is aborted
+///
+/// `output` holds the reader of the last pipe, followed by the one `Teardown::abort()` signals.
+fn drain<W: Write>(output: &mut PipeSet, sink: &mut W, aborted: &AtomicBool) -> io::Result<()> {
+    let mut buf = vec![0; DEFAULT_BUF_SIZE];
+    loop {
+        let (index, len) = output.read_any(&mut buf)?;
+        if aborted.load(Ordering::SeqCst) || (index, len) == (0, 0) {
+            return sink.flush()
+        }
+
+        if index == 0 {
+            sink.write_all(&buf[..len])?;
+        }
+    }
+}
+
+/// What happens to the rest of a `Pipeline` once one of its stages fails
+#[derive(Clone, Debug, PartialEq, Eq, Default)]
+pub enum ErrorPolicy {
+    /// Stop the pipeline as soon as possible, discarding any data that hasn't reached the sink
+    ///
+    /// Every pipe of the pipeline is cut off, so the other stages see EOF and their writes fail
+    /// with `BrokenPipe`. Whatever the failed stage had buffered is dropped without being passed
+    /// on.
+    Abort,
+    /// Let the downstream stages finish processing the data the failed stage had already passed
+    /// on, as if the failed stage had reached EOF
+    #[default]
+    Drain,
+    /// Like `Drain`, but the failed stage's output ends with the given marker, so that downstream
+    /// stages can tell that the stream was cut short
+    Marker(Vec<u8>),
+}
+
+/// Identifies a part of a `Pipeline`
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum StageId {
+    /// The thread reading from the source
+    Source,
+    /// The stage with the given index, in the order the stages were added
+    Stage(usize),
+    /// The thread writing to the sink
+    Sink,
+}
+
+impl fmt::Display for StageId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            StageId::Source => f.write_str("pipeline source"),
+            StageId::Stage(index) => write!(f, "pipeline stage {}", index),
+            StageId::Sink => f.write_str("pipeline sink"),
+        }
+    }
+}
+
+/// The failure of one part of a `Pipeline`
+#[derive(Debug)]
+pub struct StageError {
+    /// The part of the pipeline that failed
+    pub stage: StageId,
+    /// The error it failed with
+    pub error: io::Error,
+}
+
+impl fmt::Display for StageError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} failed: {}", self.stage, self.error)
+    }
+}
+
+impl Error for StageError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        Some(&self.error)
+    }
+}
+
+/// The failures of a `Pipeline` run, in pipeline order (see `Pipeline::run()`)
+#[derive(Debug)]
+pub struct PipelineError {
+    errors: Vec<StageError>,
+    cause: usize,
+}
+
+impl PipelineError {
+    fn new(errors: Vec<StageError>) -> Self {
+        // a broken pipe is only a symptom of a failure further downstream
+        let cause = errors.iter().position(|e| e.error.kind() != io::ErrorKind::BrokenPipe).unwrap_or(0);
+        PipelineError { errors, cause }
+    }
+
+    /// Returns every failure, in pipeline order
+    pub fn errors(&self) -> &[StageError] {
+        &self.errors
+    }
+
+    /// Returns the failure that brought the pipeline down
+    pub fn cause(&self) -> &StageError {
+        &self.errors[self.cause]
+    }
+
+    /// Converts the report into its list of failures
+    pub fn into_errors(self) -> Vec<StageError> {
+        self.errors
+    }
+}
+
+impl fmt::Display for PipelineError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(self.cause(), f)
+    }
+}
+
+impl Error for PipelineError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        Some(self.cause())
+    }
+}
+
+/// Reports the cause of the failure, with the whole `PipelineError` as its inner error.
+impl From<PipelineError> for io::Error {
+    fn from(err: PipelineError) -> Self {
+        io::Error::new(err.cause().error.kind(), err)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{self, Read, Write};
+    use std::sync::mpsc;
+    use super::*;
+
+    #[test]
+    fn stages() {
+        let output = Pipeline::new(&b"abc"[..])
+            .then(|r, w| {
+                let mut data = Vec::new();
+                r.read_to_end(&mut data)?;
+                w.write_all(&data)?;
+                w.write_all(&data)
+            })
+            .then(|r, w| io::copy(r, w).map(|_| ()))
+            .sink(Vec::new())
+            .run()
+            .unwrap();
+        assert_eq!(output, b"abcabc");
+    }
+
+    #[test]
+    fn stage_error() {
+        let err = Pipeline::new(io::repeat(0))
+            .then(|r, _| {
+                r.read_exact(&mut [0; 16])?;
+                Err(io::Error::new(io::ErrorKind::InvalidData, "bad input"))
+            })
+            .then(|r, w| io::copy(r, w).map(|_| ()))
+            .sink(Vec::new())
+            .run()
+            .unwrap_err();
+        assert_eq!(err.to_string(), "pipeline stage 0 failed: bad input");
+        let stages: Vec<_> = err.errors().iter().map(|e| (e.stage, e.error.kind())).collect();
+        assert_eq!(stages, [
+            (StageId::Source, io::ErrorKind::BrokenPipe),
+            (StageId::Stage(0), io::ErrorKind::InvalidData),
+        ]);
+    }
+
+    #[test]
+    fn error_abort() {
+        let (seen, received) = mpsc::channel();
+        let err = Pipeline::new(&b"abc"[..])
+            .then(|r, w| {
+                // keep the data buffered until the stage returns
+                w.set_eager_sends(false);
+                io::copy(r, w)?;
+                Err(io::Error::new(io::ErrorKind::InvalidData, "bad input"))
+            })
+            .on_error(ErrorPolicy::Abort)
+            .then(move |r, w| {
+                let mut data = Vec::new();
+                r.read_to_end(&mut data)?;
+                seen.send(data).unwrap();
+                w.write_all(b"late")?;
+                w.flush()
+            })
+            .sink(Vec::new())
+            .run()
+            .unwrap_err();
+        assert_eq!(err.cause().stage, StageId::Stage(0));
+        // the failed stage's buffered output never made it downstream
+        assert_eq!(received.recv().unwrap(), b"");
+        let stages: Vec<_> = err.errors().iter().map(|e| (e.stage, e.error.kind())).collect();
+        assert_eq!(stages, [
+            (StageId::Stage(0), io::ErrorKind::InvalidData),
+            (StageId::Stage(1), io::ErrorKind::BrokenPipe),
+        ]);
+    }
+
+    #[test]
+    fn error_marker() {
+        let err = Pipeline::new(&b"abc"[..])
+            .then(|r, w| {
+                io::copy(r, w)?;
+                Err(io::Error::new(io::ErrorKind::InvalidData, "bad input"))
+            })
+            .on_error(ErrorPolicy::Marker(b"!".to_vec()))
+            .then(|r, _| {
+                let mut data = Vec::new();
+                r.read_to_end(&mut data)?;
+                assert_eq!(data, b"abc!");
+                Ok(())
+            })
+            .run()
+            .unwrap_err();
+        assert_eq!(err.cause().stage, StageId::Stage(0));
+    }
+}
@@ -0,0 +1,113 @@
+use std::sync::{Arc, Mutex, MutexGuard};
+
+/// Allocation counters for a `BufferPool`
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct PoolStats {
+    /// Number of buffers allocated because the pool had nothing suitable
+    pub allocations: u64,
+    /// Total capacity of all buffers allocated by the pool, in bytes
+    pub allocated_bytes: u64,
+    /// Number of requests served from a recycled buffer
+    pub hits: u64,
+    /// Number of requests that needed a new allocation
+    pub misses: u64,
+    /// Number of buffers currently held for reuse
+    pub pooled: usize,
+    /// Total capacity of the buffers currently held for reuse, in bytes
+    pub pooled_bytes: usize,
+}
+
+struct Inner {
+    buffers: Vec<Vec<u8>>,
+    max_buffers: usize,
+    stats: PoolStats,
+}
+
+/// A pool of recycled chunk buffers.
+///
+/// Readers given a pool (see `PipeReader::set_pool()`) return chunks to it once they have been
+/// consumed, and buffered writers (see `PipeBufWriter::set_pool()`) take their buffers from it
+/// rather than allocating a fresh one for every chunk sent. Clones refer to the same pool.
+#[derive(Clone)]
+pub struct BufferPool {
+    inner: Arc<Mutex<Inner>>,
+}
+
+impl BufferPool {
+    /// Creates a pool that holds on to at most `max_buffers` unused buffers
+    pub fn new(max_buffers: usize) -> Self {
+        BufferPool {
+            inner: Arc::new(Mutex::new(Inner {
+                buffers: Vec::new(),
+                max_buffers,
+                stats: PoolStats::default(),
+            })),
+        }
+    }
+
+    /// Returns a snapshot of the pool's allocation counters
+    pub fn stats(&self) -> PoolStats {
+        self.lock().stats
+    }
+
+    /// Changes the number of unused buffers the pool may hold, releasing any excess
+    pub fn set_max_buffers(&self, max_buffers: usize) {
+        let mut inner = self.lock();
+        inner.max_buffers = max_buffers;
+        inner.trim(max_buffers);
+    }
+
+    /// Releases all unused buffers held by the pool
+    pub fn shrink_to_fit(&self) {
+        self.lock().trim(0);
+    }
+
+    /// Takes an empty buffer with at least `capacity` bytes of capacity from the pool,
+    /// allocating one if necessary
+    pub fn take(&self, capacity: usize) -> Vec<u8> {
+        let mut inner = self.lock();
+        if let Some(buffer) = inner.buffers.pop() {
+            inner.stats.pooled -= 1;
+            inner.stats.pooled_bytes -= buffer.capacity();
+            if buffer.capacity() >= capacity {
+                inner.stats.hits += 1;
+                return buffer
+            }
+        }
+
+        inner.stats.misses += 1;
+        inner.stats.allocations += 1;
+        inner.stats.allocated_bytes += capacity as u64;
+        Vec::with_capacity(capacity)
+    }
+
+    /// Returns a buffer to the pool for reuse
+    pub fn put(&self, mut buffer: Vec<u8>) {
+        if buffer.capacity() == 0 {
+            return
+        }
+
+        let mut inner = self.lock();
+        if inner.buffers.len() < inner.max_buffers {
+            buffer.clear();
+            inner.stats.pooled += 1;
+            inner.stats.pooled_bytes += buffer.capacity();
+            inner.buffers.push(buffer);
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Inner> {
+        self.inner.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl Inner {
+    fn trim(&mut self, len: usize) {
+        while self.buffers.len() > len {
+            if let Some(buffer) = self.buffers.pop() {
+                self.stats.pooled -= 1;
+                self.stats.pooled_bytes -= buffer.capacity();
+            }
+        }
+    }
+}
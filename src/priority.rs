@@ -0,0 +1,290 @@
+use std::cmp::min;
+use std::collections::VecDeque;
+use std::io::{self, BufRead, Read, Write};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use {check_consume, PipeError, DEFAULT_BUF_SIZE};
+
+struct State {
+    lanes: Vec<VecDeque<Vec<u8>>>,
+    queued: usize,
+    capacity: usize,
+    writers: usize,
+    reader: bool,
+}
+
+struct Shared {
+    state: Mutex<State>,
+    readable: Condvar,
+    writable: Condvar,
+}
+
+impl Shared {
+    fn lock(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// The `Read` end of a priority pipe (see `pipe_priority()`)
+pub struct PriorityPipeReader {
+    shared: Arc<Shared>,
+    buffer: Vec<u8>,
+    position: usize,
+    priority: usize,
+}
+
+/// The `Write` end of a priority pipe (see `pipe_priority()`)
+pub struct PriorityPipeWriter {
+    shared: Arc<Shared>,
+    priority: usize,
+}
+
+/// Creates a memory pipe whose chunks are delivered in order of priority
+///
+/// Chunks are tagged with a priority from `0` to `levels - 1`, and the reader always receives the
+/// highest priority chunk available, in the order they were written within each level. Up to
+/// 8KiB of data is queued before writers block, which `PriorityPipeWriter::set_capacity()` can
+/// change.
+///
+/// # Panics
+///
+/// Panics if `levels` is zero.
+pub fn pipe_priority(levels: usize) -> (PriorityPipeReader, PriorityPipeWriter) {
+    assert!(levels > 0, "a priority pipe needs at least one level");
+
+    let shared = Arc::new(Shared {
+        state: Mutex::new(State {
+            lanes: (0..levels).map(|_| VecDeque::new()).collect(),
+            queued: 0,
+            capacity: DEFAULT_BUF_SIZE,
+            writers: 1,
+            reader: true,
+        }),
+        readable: Condvar::new(),
+        writable: Condvar::new(),
+    });
+
+    (
+        PriorityPipeReader { shared: shared.clone(), buffer: Vec::new(), position: 0, priority: 0 },
+        PriorityPipeWriter { shared, priority: 0 },
+    )
+}
+
+impl PriorityPipeReader {
+    /// Returns a reference to the internally buffered data.
+    pub fn buffer(&self) -> &[u8] {
+        &self.buffer[self.position..]
+    }
+
+    /// Returns the priority of the chunk currently being read
+    pub fn priority(&self) -> usize {
+        self.priority
+    }
+}
+
+impl BufRead for PriorityPipeReader {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        while self.position >= self.buffer.len() {
+            let mut state = self.shared.lock();
+            let next = state.lanes.iter_mut().enumerate().rev()
+                .filter_map(|(priority, lane)| lane.pop_front().map(|data| (priority, data)))
+                .next();
+            match next {
+                Some((priority, data)) => {
+                    state.queued -= data.len();
+                    self.shared.writable.notify_all();
+                    self.buffer = data;
+                    self.position = 0;
+                    self.priority = priority;
+                },
+                None if state.writers == 0 => break,
+                None => drop(self.shared.readable.wait(state).unwrap_or_else(|e| e.into_inner())),
+            }
+        }
+
+        Ok(&self.buffer[self.position..])
+    }
+
+    fn consume(&mut self, amt: usize) {
+        check_consume(amt, self.buffer.len() - self.position);
+        self.position += amt
+    }
+}
+
+impl Read for PriorityPipeReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+
+        let internal = self.fill_buf()?;
+
+        let len = min(buf.len(), internal.len());
+        if len > 0 {
+            buf[..len].copy_from_slice(&internal[..len]);
+            self.consume(len);
+        }
+        Ok(len)
+    }
+}
+
+impl Drop for PriorityPipeReader {
+    fn drop(&mut self) {
+        let mut state = self.shared.lock();
+        state.reader = false;
+        state.lanes.iter_mut().for_each(VecDeque::clear);
+        state.queued = 0;
+        self.shared.writable.notify_all();
+    }
+}
+
+impl PriorityPipeWriter {
+    /// Sets the priority used by `write()` and `send()`
+    ///
+    /// # Panics
+    ///
+    /// Panics if `priority` is not less than the number of levels the pipe was created with.
+    pub fn set_priority(&mut self, priority: usize) {
+        assert!(priority < self.shared.lock().lanes.len(), "priority out of range");
+        self.priority = priority;
+    }
+
+    /// Returns the priority used by `write()` and `send()`
+    pub fn priority(&self) -> usize {
+        self.priority
+    }
+
+    /// Returns the number of bytes that can be queued before writes block
+    pub fn capacity(&self) -> usize {
+        self.shared.lock().capacity
+    }
+
+    /// Changes the number of bytes that can be queued before writes block
+    ///
+    /// Growing the pipe wakes any writers waiting for room right away. Shrinking it below what is
+    /// already queued drops none of that data: writes block until the reader has drained the pipe
+    /// below the new capacity.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `capacity` is zero.
+    pub fn set_capacity(&self, capacity: usize) {
+        assert!(capacity > 0, "capacity must be non-zero");
+
+        let mut state = self.shared.lock();
+        let grown = capacity > state.capacity;
+        state.capacity = capacity;
+        if grown {
+            self.shared.writable.notify_all();
+        }
+    }
+
+    /// Write data to the associated `PriorityPipeReader` at the writer's current priority
+    pub fn send<B: Into<Vec<u8>>>(&self, bytes: B) -> Result<(), PipeError> {
+        self.send_priority(self.priority, bytes)
+    }
+
+    /// Write data to the associated `PriorityPipeReader` at the given priority
+    ///
+    /// # Panics
+    ///
+    /// Panics if `priority` is not less than the number of levels the pipe was created with.
+    pub fn send_priority<B: Into<Vec<u8>>>(&self, priority: usize, bytes: B) -> Result<(), PipeError> {
+        let bytes = bytes.into();
+        let mut state = self.shared.lock();
+        assert!(priority < state.lanes.len(), "priority out of range");
+        while state.reader && state.queued >= state.capacity {
+            state = self.shared.writable.wait(state).unwrap_or_else(|e| e.into_inner());
+        }
+        if !state.reader {
+            return Err(PipeError::Disconnected)
+        }
+
+        state.queued += bytes.len();
+        state.lanes[priority].push_back(bytes);
+        self.shared.readable.notify_one();
+        Ok(())
+    }
+}
+
+impl Clone for PriorityPipeWriter {
+    fn clone(&self) -> Self {
+        self.shared.lock().writers += 1;
+        PriorityPipeWriter { shared: self.shared.clone(), priority: self.priority }
+    }
+}
+
+impl Write for PriorityPipeWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.send(buf)
+            .map(|_| buf.len())
+            .map_err(From::from)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Drop for PriorityPipeWriter {
+    fn drop(&mut self) {
+        let mut state = self.shared.lock();
+        state.writers -= 1;
+        if state.writers == 0 {
+            self.shared.readable.notify_all();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+    use std::thread::spawn;
+    use super::*;
+
+    #[test]
+    fn priority_order() {
+        let (mut r, w) = pipe_priority(3);
+        w.send_priority(0, &b"bulk1"[..]).unwrap();
+        w.send_priority(2, &b"ctl1"[..]).unwrap();
+        w.send_priority(0, &b"bulk2"[..]).unwrap();
+        w.send_priority(1, &b"mid"[..]).unwrap();
+        w.send_priority(2, &b"ctl2"[..]).unwrap();
+        drop(w);
+
+        let mut o = String::new();
+        r.read_to_string(&mut o).unwrap();
+        assert_eq!(o, "ctl1ctl2midbulk1bulk2");
+    }
+
+    #[test]
+    fn priority_set_capacity() {
+        let (mut r, w) = pipe_priority(1);
+        w.send(vec![1; DEFAULT_BUF_SIZE]).unwrap();
+        let writer = w.clone();
+        let guard = spawn(move || writer.send(&b"more"[..]).unwrap());
+        w.set_capacity(DEFAULT_BUF_SIZE * 2);
+        assert_eq!(w.capacity(), DEFAULT_BUF_SIZE * 2);
+        guard.join().unwrap();
+
+        w.set_capacity(4);
+        let mut buf = vec![0; DEFAULT_BUF_SIZE];
+        r.read_exact(&mut buf).unwrap();
+        let writer = w.clone();
+        let guard = spawn(move || writer.send(&b"last"[..]).unwrap());
+        r.read_exact(&mut buf[..4]).unwrap();
+        assert_eq!(&buf[..4], b"more");
+        guard.join().unwrap();
+        drop(w);
+
+        let mut o = Vec::new();
+        r.read_to_end(&mut o).unwrap();
+        assert_eq!(o, b"last");
+    }
+
+    #[test]
+    fn priority_writer_fail() {
+        let (r, mut w) = pipe_priority(2);
+        drop(r);
+        assert!(w.write_all(b"hi").is_err());
+    }
+}
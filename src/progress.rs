@@ -0,0 +1,119 @@
+use std::io::{self, BufRead, Read};
+use std::time::{Duration, Instant};
+use PipeReader;
+
+/// A reader that reports progress through a callback as data passes through it.
+///
+/// The callback receives the cumulative number of bytes read and the time elapsed since the
+/// reader was created. It is invoked once every time the total crosses a multiple of the
+/// configured interval, which is checked once per read rather than per byte.
+pub struct ProgressReader<F, R = PipeReader> {
+    inner: R,
+    callback: F,
+    every: u64,
+    next: u64,
+    total: u64,
+    start: Instant,
+}
+
+impl<F: FnMut(u64, Duration), R> ProgressReader<F, R> {
+    /// Wraps `inner`, calling `callback` whenever another `every` bytes have been read
+    ///
+    /// # Panics
+    ///
+    /// Panics if `every` is zero.
+    pub fn new(inner: R, every: usize, callback: F) -> Self {
+        assert!(every > 0, "progress interval must be non-zero");
+
+        ProgressReader {
+            inner,
+            callback,
+            every: every as u64,
+            next: every as u64,
+            total: 0,
+            start: Instant::now(),
+        }
+    }
+
+    /// Returns the number of bytes read so far
+    pub fn total(&self) -> u64 {
+        self.total
+    }
+
+    /// Returns the time elapsed since the reader was created
+    pub fn elapsed(&self) -> Duration {
+        self.start.elapsed()
+    }
+
+    /// Gets a reference to the underlying reader
+    pub fn get_ref(&self) -> &R {
+        &self.inner
+    }
+
+    /// Gets a mutable reference to the underlying reader
+    pub fn get_mut(&mut self) -> &mut R {
+        &mut self.inner
+    }
+
+    /// Extracts the underlying reader
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+
+    #[inline]
+    fn advance(&mut self, amt: usize) {
+        self.total += amt as u64;
+        if self.total >= self.next {
+            self.next = (self.total / self.every + 1) * self.every;
+            (self.callback)(self.total, self.start.elapsed());
+        }
+    }
+}
+
+impl<F: FnMut(u64, Duration), R: Read> Read for ProgressReader<F, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let len = self.inner.read(buf)?;
+        self.advance(len);
+        Ok(len)
+    }
+}
+
+impl<F: FnMut(u64, Duration), R: BufRead> BufRead for ProgressReader<F, R> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        self.inner.fill_buf()
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.inner.consume(amt);
+        self.advance(amt);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Read, Write};
+    use std::thread::spawn;
+    use super::*;
+    use pipe;
+
+    #[test]
+    fn progress_intervals() {
+        let (r, mut w) = pipe();
+        let guard = spawn(move || {
+            for _ in 0..10 {
+                w.write_all(&[0; 30]).unwrap();
+            }
+        });
+
+        let mut reports = Vec::new();
+        {
+            let mut r = ProgressReader::new(r, 100, |total, _| reports.push(total));
+            let mut o = Vec::new();
+            r.read_to_end(&mut o).unwrap();
+            assert_eq!(r.total(), 300);
+        }
+        assert_eq!(reports, [120, 210, 300]);
+
+        guard.join().unwrap();
+    }
+}
@@ -0,0 +1,125 @@
+use std::cmp::min;
+use std::fmt;
+use std::io::{self, BufRead, Read, Write};
+use check_consume;
+
+/// A reader that generates its data on demand (see `pipe_pull()`)
+pub struct PullReader<F> {
+    producer: Option<F>,
+    buffer: Vec<u8>,
+    position: usize,
+}
+
+/// Creates a reader whose data is produced by `producer` only as it is read
+///
+/// Whenever the reader runs out of data, it calls `producer` on its own thread to write the next
+/// piece of the stream into its buffer. Nothing is produced ahead of the reader, so an infinite
+/// generator needs neither a thread of its own nor to block on backpressure. A call that writes
+/// nothing ends the stream, and an error from `producer` is returned by the read that called it.
+///
+/// # Example
+///
+/// ```
+/// use std::io::Read;
+///
+/// let mut count = 0;
+/// let mut reader = pipe::pipe_pull(move |w| {
+///     count += 1;
+///     write!(w, "{} ", count)
+/// });
+/// let mut buf = [0; 6];
+/// reader.read_exact(&mut buf).unwrap();
+/// assert_eq!(&buf, b"1 2 3 ");
+/// ```
+pub fn pipe_pull<F: FnMut(&mut dyn Write) -> io::Result<()>>(producer: F) -> PullReader<F> {
+    PullReader { producer: Some(producer), buffer: Vec::new(), position: 0 }
+}
+
+impl<F> PullReader<F> {
+    /// Returns a reference to the data produced but not yet read
+    pub fn buffer(&self) -> &[u8] {
+        &self.buffer[self.position..]
+    }
+
+    /// Returns `true` once the producer has ended the stream
+    pub fn is_finished(&self) -> bool {
+        self.producer.is_none()
+    }
+}
+
+impl<F: FnMut(&mut dyn Write) -> io::Result<()>> BufRead for PullReader<F> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        if self.position >= self.buffer.len() {
+            self.buffer.clear();
+            self.position = 0;
+            if let Some(producer) = &mut self.producer {
+                producer(&mut self.buffer)?;
+                if self.buffer.is_empty() {
+                    self.producer = None;
+                }
+            }
+        }
+
+        Ok(&self.buffer[self.position..])
+    }
+
+    fn consume(&mut self, amt: usize) {
+        check_consume(amt, self.buffer.len() - self.position);
+        self.position += amt;
+    }
+}
+
+impl<F: FnMut(&mut dyn Write) -> io::Result<()>> Read for PullReader<F> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let internal = self.fill_buf()?;
+        let len = min(buf.len(), internal.len());
+        buf[..len].copy_from_slice(&internal[..len]);
+        self.consume(len);
+        Ok(len)
+    }
+}
+
+impl<F> fmt::Debug for PullReader<F> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("PullReader")
+            .field("buffered", &(self.buffer.len() - self.position))
+            .field("finished", &self.producer.is_none())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pull() {
+        let mut calls = 0;
+        let mut reader = pipe_pull(|w| {
+            calls += 1;
+            match calls {
+                1 => w.write_all(b"hello "),
+                2 => w.write_all(b"world"),
+                _ => Ok(()),
+            }
+        });
+        let mut buf = [0; 3];
+        reader.read_exact(&mut buf).unwrap();
+        assert_eq!(reader.buffer(), b"lo ");
+
+        let mut rest = String::new();
+        reader.read_to_string(&mut rest).unwrap();
+        assert_eq!(rest, "lo world");
+        assert!(reader.is_finished());
+        assert_eq!(reader.read(&mut buf).unwrap(), 0);
+        drop(reader);
+        assert_eq!(calls, 3);
+    }
+
+    #[test]
+    fn pull_error() {
+        let mut reader = pipe_pull(|_| Err(io::Error::other("failed")));
+        assert_eq!(reader.read(&mut [0; 4]).unwrap_err().kind(), io::ErrorKind::Other);
+        assert!(!reader.is_finished());
+    }
+}
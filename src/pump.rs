@@ -0,0 +1,300 @@
+use std::any::Any;
+use std::io::{self, Read, Write};
+use std::panic::resume_unwind;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::thread::{self, spawn, JoinHandle};
+use std::time::{Duration, Instant};
+use channel::queued;
+use state::Shared;
+use {copy, CopyEnd, CopyOptions, CopyReport, PipeReader, PipeWriter, DEFAULT_BUF_SIZE};
+
+struct PumpState {
+    bytes: AtomicU64,
+    stop: AtomicBool,
+    abort: AtomicBool,
+}
+
+/// Ends the copy early once the pump is told to stop
+struct Tap<T> {
+    inner: T,
+    state: Arc<PumpState>,
+}
+
+impl<R: Read> Read for Tap<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self.state.stop.load(Ordering::Acquire) {
+            true => Ok(0),
+            false => self.inner.read(buf),
+        }
+    }
+}
+
+impl<W: Write> Write for Tap<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let len = self.inner.write(buf)?;
+        self.state.bytes.fetch_add(len as u64, Ordering::Relaxed);
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// A thread relaying data from a reader to a writer (see `pump()`)
+pub struct PumpHandle {
+    thread: JoinHandle<CopyReport>,
+    state: Arc<PumpState>,
+}
+
+/// Relays everything from `reader` to `writer` on a thread of its own, such as to feed a pipe
+/// from a file or socket
+///
+/// The writer is flushed once the reader reaches EOF, and both are dropped once the pump is done.
+/// The returned handle tracks progress, can stop the pump early, and reports how it ended.
+///
+/// When relaying from one pipe's `PipeReader` into another's `PipeWriter`, the chunks received are
+/// passed on as they are, rather than being copied through a buffer of the pump's own.
+///
+/// # Example
+///
+/// ```
+/// use std::io::Read;
+///
+/// let (mut reader, writer) = pipe::pipe();
+/// let pump = pipe::pump(&b"hello"[..], writer);
+/// let mut data = String::new();
+/// reader.read_to_string(&mut data).unwrap();
+/// assert_eq!(data, "hello");
+/// assert_eq!(pump.join().into_result().unwrap(), 5);
+/// ```
+pub fn pump<R, W>(reader: R, writer: W) -> PumpHandle where
+    R: Read + Send + 'static,
+    W: Write + Send + 'static,
+{
+    pump_with(reader, writer, CopyOptions::default(), true)
+}
+
+/// How far `pipe_prefetch()` reads ahead of the pipe's reader
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Prefetch {
+    /// How many chunks may be queued up for the reader
+    pub chunks: usize,
+    /// The size of each chunk read from the source
+    pub chunk_size: usize,
+}
+
+impl Default for Prefetch {
+    fn default() -> Self {
+        Prefetch { chunks: 4, chunk_size: DEFAULT_BUF_SIZE }
+    }
+}
+
+/// Creates a pipe fed from `source` by a pump that reads ahead of the pipe's reader
+///
+/// A plain `pump()` hands over one chunk at a time, so every stall in a slow or bursty source,
+/// such as a network stream, reaches the reader. Here the pump keeps reading while up to
+/// `Prefetch::chunks` chunks of `Prefetch::chunk_size` bytes are queued for the reader, so that
+/// the reader only waits on the source once that much has been used up.
+///
+/// # Example
+///
+/// ```
+/// use std::io::Read;
+///
+/// let (mut reader, pump) = pipe::pipe_prefetch(&b"hello"[..], pipe::Prefetch::default());
+/// let mut data = String::new();
+/// reader.read_to_string(&mut data).unwrap();
+/// assert_eq!(data, "hello");
+/// assert_eq!(pump.join().into_result().unwrap(), 5);
+/// ```
+pub fn pipe_prefetch<R: Read + Send + 'static>(source: R, prefetch: Prefetch) -> (PipeReader, PumpHandle) {
+    let (reader, writer) = queued(Shared::new(), prefetch.chunks);
+    let opts = CopyOptions { buffer_size: prefetch.chunk_size, ..CopyOptions::default() };
+    (reader, pump_with(source, writer, opts, false))
+}
+
+/// Runs the pump, passing chunks straight on if `forward` is set and both ends are pipes
+fn pump_with<R, W>(reader: R, writer: W, opts: CopyOptions, forward: bool) -> PumpHandle where
+    R: Read + Send + 'static,
+    W: Write + Send + 'static,
+{
+    let state = Arc::new(PumpState {
+        bytes: AtomicU64::new(0),
+        stop: AtomicBool::new(false),
+        abort: AtomicBool::new(false),
+    });
+    let mut reader = Tap { inner: reader, state: state.clone() };
+    let mut writer = Tap { inner: writer, state: state.clone() };
+    let thread = spawn(move || {
+        let pipes = match forward {
+            true => (&mut reader.inner as &mut dyn Any).downcast_mut::<PipeReader>()
+                .zip((&writer.inner as &dyn Any).downcast_ref::<PipeWriter>()),
+            false => None,
+        };
+        let mut report = match pipes {
+            Some((r, w)) => forward_chunks(r, w, &writer.state),
+            None => copy(&mut reader, &mut writer, &opts),
+        };
+        let state = &writer.state;
+        if let CopyEnd::Eof = report.end {
+            if state.stop.load(Ordering::Acquire) {
+                report.end = CopyEnd::Stopped;
+            }
+            if !state.abort.load(Ordering::Acquire) {
+                if let Err(e) = writer.flush() {
+                    report.end = CopyEnd::WriteFailed(e);
+                }
+            }
+        }
+        report
+    });
+
+    PumpHandle { thread, state }
+}
+
+/// Relays the chunks received by `reader` into `writer` without copying their data
+fn forward_chunks(reader: &mut PipeReader, writer: &PipeWriter, state: &PumpState) -> CopyReport {
+    let mut report = CopyReport {
+        bytes: 0,
+        read_time: Duration::from_secs(0),
+        write_time: Duration::from_secs(0),
+        end: CopyEnd::Eof,
+    };
+
+    while !state.stop.load(Ordering::Acquire) {
+        let start = Instant::now();
+        let received = reader.recv_chunk();
+        report.read_time += start.elapsed();
+        let chunk = match received {
+            Ok(Some(chunk)) => chunk,
+            Ok(None) => break,
+            Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => {
+                report.end = CopyEnd::ReadFailed(e);
+                break
+            },
+        };
+        // like `copy()`, which never reads markers such as a `PipeBarrier`'s
+        if chunk.is_empty() {
+            continue
+        }
+
+        let len = chunk.len() as u64;
+        let start = Instant::now();
+        let sent = writer.send_vec(chunk.into_vec());
+        report.write_time += start.elapsed();
+        if let Err(e) = sent {
+            report.end = CopyEnd::WriteFailed(writer.io_error(e));
+            break
+        }
+        report.bytes += len;
+        state.bytes.fetch_add(len, Ordering::Relaxed);
+    }
+
+    report
+}
+
+impl PumpHandle {
+    /// Returns the number of bytes written so far
+    pub fn bytes(&self) -> u64 {
+        self.state.bytes.load(Ordering::Relaxed)
+    }
+
+    /// Returns `true` once the pump has finished, so that `join()` won't block
+    pub fn is_finished(&self) -> bool {
+        self.thread.is_finished()
+    }
+
+    /// Tells the pump to stop once the data in flight has been written, and to flush the writer
+    ///
+    /// A read or write that is blocked at the time isn't interrupted, so the pump only stops once
+    /// it returns.
+    pub fn stop(&self) {
+        self.state.stop.store(true, Ordering::Release);
+    }
+
+    /// Tells the pump to stop like `stop()` does, but to drop the writer without flushing it
+    pub fn abort(&self) {
+        self.state.abort.store(true, Ordering::Release);
+        self.stop();
+    }
+
+    /// Waits for the pump to finish, and reports how it went
+    ///
+    /// # Panics
+    ///
+    /// Resumes the panic if the reader or writer panicked.
+    pub fn join(self) -> CopyReport {
+        self.try_join().unwrap_or_else(|e| resume_unwind(e))
+    }
+
+    /// Waits for the pump to finish, returning the panic payload if the reader or writer panicked
+    pub(crate) fn try_join(self) -> thread::Result<CopyReport> {
+        self.thread.join()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+    use std::thread::spawn;
+    use pipe;
+    use super::*;
+
+    #[test]
+    fn pump_stop() {
+        let (mut r, w) = pipe();
+        let handle = pump(io::repeat(1), w);
+        let mut buf = [0; 16];
+        r.read_exact(&mut buf).unwrap();
+        handle.stop();
+
+        // the chunk in flight still gets through
+        let mut o = Vec::new();
+        r.read_to_end(&mut o).unwrap();
+        let report = handle.join();
+        assert!(matches!(report.end, CopyEnd::Stopped));
+        assert_eq!(report.bytes, 16 + o.len() as u64);
+    }
+
+    #[test]
+    fn prefetch() {
+        let (mut r, handle) = pipe_prefetch(io::repeat(1).take(64), Prefetch { chunks: 3, chunk_size: 8 });
+        // the pump only blocks once the queue is full
+        while handle.bytes() < 24 {
+            std::thread::yield_now();
+        }
+        assert!(!handle.is_finished());
+        let mut o = Vec::new();
+        r.read_to_end(&mut o).unwrap();
+        assert_eq!(o, [1; 64]);
+        assert_eq!(handle.join().into_result().unwrap(), 64);
+    }
+
+    #[test]
+    fn pump_forward() {
+        let (mut r, w) = pipe();
+        let (relay_r, relay_w) = pipe();
+        let payload = vec![1; 256];
+        let ptr = payload.as_ptr() as usize;
+        let handle = pump(relay_r, w);
+        let guard = spawn(move || relay_w.send_vec(payload).unwrap());
+
+        // the chunk is handed on as it is
+        let chunk = r.recv_chunk().unwrap().unwrap();
+        assert_eq!(chunk.as_ptr() as usize, ptr);
+        guard.join().unwrap();
+        assert_eq!(r.recv_chunk().unwrap(), None);
+        assert_eq!(handle.join().into_result().unwrap(), 256);
+    }
+
+    #[test]
+    fn pump_error() {
+        let (r, w) = pipe();
+        drop(r);
+        let report = pump(&b"hello"[..], w).join();
+        assert_eq!(report.into_result().unwrap_err().kind(), io::ErrorKind::BrokenPipe);
+    }
+}
@@ -0,0 +1,75 @@
+use std::time::{Duration, Instant};
+use clock::ClockHandle;
+use PipeError;
+
+/// What a writer does once it has used up its `Quota`
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum QuotaPolicy {
+    /// Block the writer until the next window starts
+    Block,
+    /// Fail the write with `PipeError::QuotaExceeded`
+    Error,
+}
+
+/// A limit on how many bytes a single writer may send within a window of time
+///
+/// Quotas are accounted separately for every clone of a writer (see `PipeWriter::set_quota()`),
+/// so that one busy producer can't crowd out the others sharing a pipe.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Quota {
+    /// Number of bytes allowed per window
+    pub bytes: u64,
+    /// Length of each window
+    pub window: Duration,
+    /// What happens to writes exceeding the quota
+    pub policy: QuotaPolicy,
+}
+
+impl Quota {
+    /// Creates a quota of `bytes` per `window`
+    pub fn new(bytes: u64, window: Duration, policy: QuotaPolicy) -> Self {
+        Quota { bytes, window, policy }
+    }
+}
+
+pub(crate) struct QuotaState {
+    quota: Quota,
+    start: Instant,
+    used: u64,
+}
+
+impl QuotaState {
+    pub(crate) fn new(quota: Quota, clock: &ClockHandle) -> Self {
+        QuotaState { quota, start: clock.now(), used: 0 }
+    }
+
+    pub(crate) fn quota(&self) -> Quota {
+        self.quota
+    }
+
+    /// Counts `amt` bytes against the quota, waiting for the next window if necessary
+    ///
+    /// A single write larger than the whole quota is let through at the start of a window.
+    pub(crate) fn acquire(&mut self, amt: u64, clock: &ClockHandle) -> Result<(), PipeError> {
+        let now = clock.now();
+        if now.duration_since(self.start) >= self.quota.window {
+            self.start = now;
+            self.used = 0;
+        }
+
+        if self.used > 0 && self.used + amt > self.quota.bytes {
+            match self.quota.policy {
+                QuotaPolicy::Error => return Err(PipeError::QuotaExceeded),
+                QuotaPolicy::Block => {
+                    let next = self.start + self.quota.window;
+                    clock.sleep(next.saturating_duration_since(now));
+                    self.start = clock.now();
+                    self.used = 0;
+                },
+            }
+        }
+
+        self.used += amt;
+        Ok(())
+    }
+}
@@ -0,0 +1,202 @@
+use std::cmp::min;
+use std::io::{self, Write};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use clock::ClockHandle;
+use {Clock, PipeWriter};
+
+struct Bucket {
+    rate: f64,
+    burst: f64,
+    tokens: f64,
+    last: Instant,
+}
+
+impl Bucket {
+    fn refill(&mut self, now: Instant) {
+        let elapsed = now.duration_since(self.last).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.burst);
+        self.last = now;
+    }
+}
+
+/// A token bucket limiting throughput to a number of bytes per second.
+///
+/// Cloned limiters share the same bucket, so a single limiter can pace several writers (and
+/// pipes) at once.
+#[derive(Clone)]
+pub struct RateLimiter {
+    bucket: Arc<Mutex<Bucket>>,
+    clock: ClockHandle,
+}
+
+impl RateLimiter {
+    /// Creates a limiter allowing `rate` bytes per second on average, and bursts of up to `burst`
+    /// bytes at a time.
+    ///
+    /// # Panics
+    ///
+    /// Panics if either `rate` or `burst` is zero.
+    pub fn new(rate: u64, burst: usize) -> Self {
+        Self::with_clock_handle(rate, burst, ClockHandle::default())
+    }
+
+    /// Creates a limiter like `new()`, that tells time and waits by `clock`
+    ///
+    /// # Panics
+    ///
+    /// Panics if either `rate` or `burst` is zero.
+    pub fn with_clock<C: Clock + 'static>(rate: u64, burst: usize, clock: C) -> Self {
+        Self::with_clock_handle(rate, burst, ClockHandle::new(clock))
+    }
+
+    fn with_clock_handle(rate: u64, burst: usize, clock: ClockHandle) -> Self {
+        assert!(rate > 0, "rate must be non-zero");
+        assert!(burst > 0, "burst must be non-zero");
+
+        RateLimiter {
+            bucket: Arc::new(Mutex::new(Bucket {
+                rate: rate as f64,
+                burst: burst as f64,
+                tokens: burst as f64,
+                last: clock.now(),
+            })),
+            clock,
+        }
+    }
+
+    /// Returns the number of bytes per second allowed by the limiter.
+    pub fn rate(&self) -> u64 {
+        self.lock().rate as u64
+    }
+
+    /// Returns the largest number of bytes the limiter will release at once.
+    pub fn burst(&self) -> usize {
+        self.lock().burst as usize
+    }
+
+    /// Blocks until up to `amt` bytes may be sent, returning the number of bytes granted.
+    ///
+    /// At most `burst()` bytes are granted at a time. Tokens are reserved before sleeping so that
+    /// concurrent users of a shared limiter are served in order.
+    pub fn acquire(&self, amt: usize) -> usize {
+        let (granted, delay) = self.reserve(amt);
+        if delay > Duration::from_secs(0) {
+            self.clock.sleep(delay);
+        }
+        granted
+    }
+
+    /// Returns unused tokens obtained from `acquire()` to the bucket.
+    pub fn release(&self, amt: usize) {
+        let mut bucket = self.lock();
+        bucket.tokens = (bucket.tokens + amt as f64).min(bucket.burst);
+    }
+
+    fn reserve(&self, amt: usize) -> (usize, Duration) {
+        let mut bucket = self.lock();
+        bucket.refill(self.clock.now());
+
+        let granted = min(amt, bucket.burst as usize);
+        bucket.tokens -= granted as f64;
+        let delay = if bucket.tokens < 0.0 {
+            Duration::from_secs_f64(-bucket.tokens / bucket.rate)
+        } else {
+            Duration::from_secs(0)
+        };
+        (granted, delay)
+    }
+
+    fn lock(&self) -> ::std::sync::MutexGuard<'_, Bucket> {
+        // the bucket is always left in a consistent state, so poisoning can be ignored
+        self.bucket.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// A writer that paces data handed to the inner writer (a `PipeWriter` by default) according to a
+/// `RateLimiter`.
+pub struct RateLimitedWriter<W = PipeWriter> {
+    inner: W,
+    limiter: RateLimiter,
+}
+
+impl<W: Write> RateLimitedWriter<W> {
+    /// Wraps `inner` so that writes are limited by `limiter`
+    pub fn new(inner: W, limiter: RateLimiter) -> Self {
+        RateLimitedWriter { inner, limiter }
+    }
+
+    /// Gets a reference to the limiter pacing this writer
+    pub fn limiter(&self) -> &RateLimiter {
+        &self.limiter
+    }
+
+    /// Gets a reference to the underlying writer
+    pub fn get_ref(&self) -> &W {
+        &self.inner
+    }
+
+    /// Gets a mutable reference to the underlying writer
+    pub fn get_mut(&mut self) -> &mut W {
+        &mut self.inner
+    }
+
+    /// Extracts the underlying writer
+    pub fn into_inner(self) -> W {
+        self.inner
+    }
+}
+
+impl<W: Write> Write for RateLimitedWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return self.inner.write(buf)
+        }
+
+        let granted = self.limiter.acquire(buf.len());
+        match self.inner.write(&buf[..granted]) {
+            Ok(written) => {
+                self.limiter.release(granted - written);
+                Ok(written)
+            },
+            Err(e) => {
+                self.limiter.release(granted);
+                Err(e)
+            },
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Read, Write};
+    use std::thread::spawn;
+    use std::time::{Duration, Instant};
+    use super::*;
+    use pipe;
+
+    #[test]
+    fn rate_limited() {
+        let (mut r, w) = pipe();
+        let limiter = RateLimiter::new(10_000, 500);
+        let mut w1 = RateLimitedWriter::new(w.clone(), limiter.clone());
+        let mut w2 = RateLimitedWriter::new(w, limiter);
+        let start = Instant::now();
+        let guard = spawn(move || {
+            w1.write_all(&[0; 1000]).unwrap();
+            w2.write_all(&[0; 1000]).unwrap();
+        });
+
+        let mut o = Vec::new();
+        r.read_to_end(&mut o).unwrap();
+        assert_eq!(o.len(), 2000);
+        // the initial burst is free, the remaining 1500 bytes take 150ms
+        assert!(start.elapsed() >= Duration::from_millis(140));
+
+        guard.join().unwrap();
+    }
+}
@@ -0,0 +1,85 @@
+use crossbeam_channel::{Sender, SendError, TrySendError};
+use libc;
+use std::io;
+use std::os::unix::io::RawFd;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// A self-pipe that is kept readable while writers are waiting for the reader
+///
+/// Writers that find no reader waiting count themselves and signal the pipe before blocking. The
+/// reader drains it after receiving each chunk, and signals it again if other writers are still
+/// waiting, so readiness may be spurious but is never lost.
+pub(crate) struct Readiness {
+    read: RawFd,
+    write: RawFd,
+    waiting: AtomicUsize,
+}
+
+impl Readiness {
+    pub(crate) fn new() -> io::Result<Self> {
+        let mut fds = [0; 2];
+        if unsafe { libc::pipe(fds.as_mut_ptr()) } != 0 {
+            return Err(io::Error::last_os_error())
+        }
+
+        let readiness = Readiness { read: fds[0], write: fds[1], waiting: AtomicUsize::new(0) };
+        for &fd in &fds {
+            unsafe {
+                let flags = libc::fcntl(fd, libc::F_GETFL);
+                if flags < 0 || libc::fcntl(fd, libc::F_SETFL, flags | libc::O_NONBLOCK) < 0
+                    || libc::fcntl(fd, libc::F_SETFD, libc::FD_CLOEXEC) < 0 {
+                    return Err(io::Error::last_os_error())
+                }
+            }
+        }
+
+        // writers may already be waiting
+        readiness.signal();
+        Ok(readiness)
+    }
+
+    pub(crate) fn fd(&self) -> RawFd {
+        self.read
+    }
+
+    pub(crate) fn signal(&self) {
+        // a full pipe is readable already
+        unsafe {
+            libc::write(self.write, [1u8].as_ptr() as *const _, 1);
+        }
+    }
+
+    /// Updates the readiness after the reader has received a chunk
+    pub(crate) fn received(&self) {
+        let mut buf = [0u8; 64];
+        while unsafe { libc::read(self.read, buf.as_mut_ptr() as *mut _, buf.len()) } > 0 { }
+
+        if self.waiting.load(Ordering::SeqCst) > 0 {
+            self.signal();
+        }
+    }
+
+    /// Hands `data` over if a reader is waiting, otherwise signals readiness and blocks in `send`
+    pub(crate) fn send<T>(&self, sender: &Sender<T>, data: T, send: impl FnOnce(T) -> Result<(), SendError<T>>) -> Result<(), SendError<T>> {
+        let data = match sender.try_send(data) {
+            Ok(()) => return Ok(()),
+            Err(TrySendError::Disconnected(data)) => return Err(SendError(data)),
+            Err(TrySendError::Full(data)) => data,
+        };
+
+        self.waiting.fetch_add(1, Ordering::SeqCst);
+        self.signal();
+        let res = send(data);
+        self.waiting.fetch_sub(1, Ordering::SeqCst);
+        res
+    }
+}
+
+impl Drop for Readiness {
+    fn drop(&mut self) {
+        unsafe {
+            libc::close(self.read);
+            libc::close(self.write);
+        }
+    }
+}
@@ -0,0 +1,115 @@
+use std::fmt;
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+use PipeError;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum Status {
+    Pending,
+    Consumed,
+    Discarded,
+}
+
+struct Delivery {
+    status: Mutex<Status>,
+    resolved: Condvar,
+}
+
+impl Delivery {
+    fn lock(&self) -> MutexGuard<'_, Status> {
+        self.status.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn resolve(&self, status: Status) {
+        let mut current = self.lock();
+        if *current == Status::Pending {
+            *current = status;
+            self.resolved.notify_all();
+        }
+    }
+}
+
+/// Tracks the delivery of a chunk sent with `PipeWriter::send_tracked()`
+///
+/// The handle resolves once a reader has consumed the whole chunk, or once the chunk is dropped
+/// without having been consumed, such as when the reader goes away first.
+#[derive(Clone)]
+pub struct SendHandle {
+    delivery: Arc<Delivery>,
+}
+
+impl SendHandle {
+    /// Returns `true` if the chunk has been fully consumed by a reader
+    pub fn is_consumed(&self) -> bool {
+        *self.delivery.lock() == Status::Consumed
+    }
+
+    /// Blocks until the chunk has been fully consumed by a reader
+    ///
+    /// Fails with `PipeError::Disconnected` if the chunk was dropped before being consumed.
+    pub fn wait(&self) -> Result<(), PipeError> {
+        let mut status = self.delivery.lock();
+        while *status == Status::Pending {
+            status = self.delivery.resolved.wait(status).unwrap_or_else(|e| e.into_inner());
+        }
+        Self::result(*status)
+    }
+
+    /// Like `wait()`, but fails with `PipeError::TimedOut` if the chunk hasn't been consumed
+    /// within `timeout`
+    pub fn wait_timeout(&self, timeout: Duration) -> Result<(), PipeError> {
+        let deadline = Instant::now() + timeout;
+        let mut status = self.delivery.lock();
+        while *status == Status::Pending {
+            let now = Instant::now();
+            if now >= deadline {
+                return Err(PipeError::TimedOut)
+            }
+            status = self.delivery.resolved.wait_timeout(status, deadline - now)
+                .unwrap_or_else(|e| e.into_inner()).0;
+        }
+        Self::result(*status)
+    }
+
+    fn result(status: Status) -> Result<(), PipeError> {
+        match status {
+            Status::Consumed => Ok(()),
+            _ => Err(PipeError::Disconnected),
+        }
+    }
+}
+
+impl fmt::Debug for SendHandle {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("SendHandle")
+            .field("status", &*self.delivery.lock())
+            .finish()
+    }
+}
+
+/// The sending side of a `SendHandle`, carried along with its chunk
+///
+/// Dropping a receipt that hasn't been acknowledged marks the chunk as discarded.
+pub(crate) struct Receipt {
+    delivery: Arc<Delivery>,
+}
+
+impl Receipt {
+    pub(crate) fn new() -> (Receipt, SendHandle) {
+        let delivery = Arc::new(Delivery {
+            status: Mutex::new(Status::Pending),
+            resolved: Condvar::new(),
+        });
+        (Receipt { delivery: delivery.clone() }, SendHandle { delivery })
+    }
+
+    pub(crate) fn acknowledge(self) {
+        self.delivery.resolve(Status::Consumed);
+    }
+}
+
+impl Drop for Receipt {
+    fn drop(&mut self) {
+        self.delivery.resolve(Status::Discarded);
+    }
+}
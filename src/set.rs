@@ -0,0 +1,195 @@
+use crossbeam_channel::{Select, TryRecvError};
+use std::cmp::min;
+use std::io;
+use {Chunk, PipeError, PipeReader};
+
+/// A group of pipes that can be read from whichever has data first
+///
+/// This lets a single thread service many pipes without blocking on any particular one of them.
+pub struct PipeSet {
+    readers: Vec<PipeReader>,
+    open: Vec<bool>,
+}
+
+impl PipeSet {
+    /// Creates a set from the given readers, which are identified by their index from then on
+    pub fn new<I: IntoIterator<Item = PipeReader>>(readers: I) -> Self {
+        let readers: Vec<_> = readers.into_iter().collect();
+        PipeSet {
+            open: vec![true; readers.len()],
+            readers,
+        }
+    }
+
+    /// Returns the number of pipes in the set
+    pub fn len(&self) -> usize {
+        self.readers.len()
+    }
+
+    /// Returns `true` if the set contains no pipes
+    pub fn is_empty(&self) -> bool {
+        self.readers.is_empty()
+    }
+
+    /// Returns the number of pipes that haven't reached EOF yet
+    pub fn remaining(&self) -> usize {
+        self.open.iter().filter(|&&open| open).count()
+    }
+
+    /// Gets a reference to the reader at `index`
+    pub fn get_ref(&self, index: usize) -> &PipeReader {
+        &self.readers[index]
+    }
+
+    /// Gets a mutable reference to the reader at `index`
+    pub fn get_mut(&mut self, index: usize) -> &mut PipeReader {
+        &mut self.readers[index]
+    }
+
+    /// Extracts the readers from the set
+    pub fn into_inner(self) -> Vec<PipeReader> {
+        self.readers
+    }
+
+    /// Blocks until any of the pipes can be read from, and reads from it into `buf`
+    ///
+    /// Returns the index of the pipe along with the number of bytes read. A pipe that reaches EOF
+    /// is reported once with a length of `0`, and is skipped from then on. Once every pipe has
+    /// been closed, this fails with `PipeError::Disconnected`.
+    pub fn read_any(&mut self, buf: &mut [u8]) -> io::Result<(usize, usize)> {
+        // chunks are decompressed and verified the way the reader would have
+        let (index, chunk) = match self.next(PipeReader::received)? {
+            Some((index, Some(chunk))) => (index, chunk),
+            Some((index, None)) => return Ok((index, 0)),
+            None => return Err(PipeError::Disconnected.into()),
+        };
+
+        let len = min(buf.len(), chunk.len());
+        buf[..len].copy_from_slice(&chunk[..len]);
+        self.readers[index].set_buffer(chunk, len);
+        Ok((index, len))
+    }
+
+    /// Blocks until any of the pipes has data, and receives everything it has buffered or the next
+    /// chunk written to it
+    ///
+    /// Pipes that reach EOF are skipped, and `None` is returned once every pipe has been closed.
+    /// Chunks are returned without verifying their checksums, which can be done with
+    /// `Chunk::verify()`, and chunks sent compressed are returned as they are (see
+    /// `Chunk::is_compressed()`).
+    pub fn recv_any(&mut self) -> Option<(usize, Chunk)> {
+        let accept = |reader: &PipeReader, chunk: &mut Chunk| {
+            reader.accept(chunk);
+            Ok(())
+        };
+        loop {
+            match self.next(accept) {
+                Ok(Some((index, Some(mut chunk)))) => {
+                    chunk.acknowledge();
+                    return Some((index, chunk))
+                },
+                Ok(Some((_, None))) => (),
+                // accepting a chunk never fails
+                Ok(None) | Err(..) => return None,
+            }
+        }
+    }
+
+    /// Finds the next pipe with data, returning `None` as its data if it reached EOF
+    ///
+    /// A chunk taken from a pipe's channel is passed to `receive` first, while data the reader
+    /// already had buffered has been received before.
+    fn next<F>(&mut self, receive: F) -> io::Result<Option<(usize, Option<Chunk>)>> where
+        F: FnOnce(&PipeReader, &mut Chunk) -> io::Result<()>,
+    {
+        if let Some(index) = self.readers.iter().position(|reader| !reader.buffer().is_empty()) {
+            self.readers[index].wait_resumed();
+            return Ok(Some((index, Some(self.readers[index].take_buffer()))))
+        }
+
+        loop {
+            let open: Vec<_> = (0..self.readers.len()).filter(|&index| self.open[index]).collect();
+            if open.is_empty() {
+                return Ok(None)
+            }
+
+            let mut select = Select::new();
+            for &index in &open {
+                select.recv(self.readers[index].receiver());
+            }
+            let index = open[select.ready()];
+
+            match self.readers[index].receiver().try_recv() {
+                Ok(mut chunk) => {
+                    receive(&self.readers[index], &mut chunk)?;
+                    self.readers[index].wait_resumed();
+                    return Ok(Some((index, Some(chunk))))
+                },
+                Err(TryRecvError::Disconnected) => {
+                    self.open[index] = false;
+                    return Ok(Some((index, None)))
+                },
+                // another clone of the reader got there first
+                Err(TryRecvError::Empty) => (),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread::spawn;
+    use std::io::Write;
+    use {pipe, PipeError};
+    use super::*;
+
+    #[test]
+    fn read_any() {
+        let (r1, mut w1) = pipe();
+        let (r2, mut w2) = pipe();
+        let mut set = PipeSet::new(vec![r1, r2]);
+
+        let guard = spawn(move || w2.write_all(b"hello").unwrap());
+
+        let mut buf = [0; 3];
+        assert_eq!(set.read_any(&mut buf).unwrap(), (1, 3));
+        assert_eq!(&buf, b"hel");
+        assert_eq!(set.read_any(&mut buf).unwrap(), (1, 2));
+        assert_eq!(&buf[..2], b"lo");
+        guard.join().unwrap();
+        assert_eq!(set.read_any(&mut buf).unwrap(), (1, 0));
+        assert_eq!(set.remaining(), 1);
+
+        let guard = spawn(move || w1.write_all(b"world").unwrap());
+
+        let (index, chunk) = set.recv_any().unwrap();
+        assert_eq!((index, &chunk[..]), (0, &b"world"[..]));
+        guard.join().unwrap();
+
+        assert!(set.recv_any().is_none());
+        let err = set.read_any(&mut buf).unwrap_err();
+        assert_eq!(err.get_ref().and_then(|e| e.downcast_ref()), Some(&PipeError::Disconnected));
+    }
+
+    #[cfg(feature = "zstd")]
+    #[test]
+    fn read_any_compressed() {
+        use std::io::Read;
+        use Compression;
+
+        let (r, mut w) = pipe();
+        w.set_compression(Some(Compression::default()));
+        w.set_checksums(true);
+        let mut set = PipeSet::new(vec![r]);
+        let guard = spawn(move || w.write_all(&[7; 4096]).unwrap());
+
+        let mut buf = [0; 1024];
+        assert_eq!(set.read_any(&mut buf).unwrap(), (0, 1024));
+        assert_eq!(&buf[..], &[7; 1024][..]);
+        // the rest is left to the reader, decompressed
+        let mut rest = Vec::new();
+        set.get_mut(0).read_to_end(&mut rest).unwrap();
+        assert_eq!(rest, vec![7; 3072]);
+        guard.join().unwrap();
+    }
+}
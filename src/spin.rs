@@ -0,0 +1,205 @@
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::cell::UnsafeCell;
+use core::cmp::min;
+use core::hint::spin_loop;
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use io::{self, Read, Write};
+
+struct SpinLock<T> {
+    locked: AtomicBool,
+    value: UnsafeCell<T>,
+}
+
+// SAFETY: access to `value` is serialized by `locked`
+unsafe impl<T: Send> Sync for SpinLock<T> { }
+
+impl<T> SpinLock<T> {
+    fn new(value: T) -> Self {
+        SpinLock { locked: AtomicBool::new(false), value: UnsafeCell::new(value) }
+    }
+
+    fn with<R, F: FnOnce(&mut T) -> R>(&self, relax: fn(), f: F) -> R {
+        while self.locked.compare_exchange_weak(false, true, Ordering::Acquire, Ordering::Relaxed).is_err() {
+            relax();
+        }
+        // SAFETY: the lock is held until the store below
+        let res = f(unsafe { &mut *self.value.get() });
+        self.locked.store(false, Ordering::Release);
+        res
+    }
+}
+
+struct Shared {
+    slot: SpinLock<Option<Vec<u8>>>,
+    writers: AtomicUsize,
+    reader: AtomicBool,
+    relax: fn(),
+}
+
+/// The `Read` end of a pipe (see `pipe()`)
+pub struct PipeReader {
+    shared: Arc<Shared>,
+    buffer: Vec<u8>,
+    position: usize,
+}
+
+/// The `Write` end of a pipe (see `pipe()`)
+pub struct PipeWriter {
+    shared: Arc<Shared>,
+}
+
+/// Creates a synchronous memory pipe
+///
+/// Without `std` there is no thread parking, so blocked ends busy-wait using the CPU's spin loop
+/// hint. See `pipe_with_relax()` to yield to a scheduler instead.
+pub fn pipe() -> (PipeReader, PipeWriter) {
+    pipe_with_relax(spin_loop)
+}
+
+/// Creates a synchronous memory pipe that calls `relax` while waiting on the other end, such as
+/// an RTOS's yield or sleep function
+pub fn pipe_with_relax(relax: fn()) -> (PipeReader, PipeWriter) {
+    let shared = Arc::new(Shared {
+        slot: SpinLock::new(None),
+        writers: AtomicUsize::new(1),
+        reader: AtomicBool::new(true),
+        relax,
+    });
+
+    (
+        PipeReader { shared: shared.clone(), buffer: Vec::new(), position: 0 },
+        PipeWriter { shared },
+    )
+}
+
+impl PipeWriter {
+    /// Write data to the associated `PipeReader`
+    pub fn send<B: Into<Vec<u8>>>(&self, bytes: B) -> io::Result<()> {
+        let shared = &*self.shared;
+        let mut bytes = Some(bytes.into());
+        loop {
+            if !shared.reader.load(Ordering::Acquire) {
+                return Err(io::Error::BrokenPipe)
+            }
+
+            shared.slot.with(shared.relax, |slot| if slot.is_none() {
+                *slot = bytes.take();
+            });
+            if bytes.is_none() {
+                return Ok(())
+            }
+
+            (shared.relax)();
+        }
+    }
+}
+
+impl Clone for PipeWriter {
+    fn clone(&self) -> Self {
+        self.shared.writers.fetch_add(1, Ordering::Relaxed);
+        PipeWriter { shared: self.shared.clone() }
+    }
+}
+
+impl Write for PipeWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.send(buf)
+            .map(|_| buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Drop for PipeWriter {
+    fn drop(&mut self) {
+        self.shared.writers.fetch_sub(1, Ordering::Release);
+    }
+}
+
+impl PipeReader {
+    /// Returns a reference to the internally buffered data.
+    pub fn buffer(&self) -> &[u8] {
+        &self.buffer[self.position..]
+    }
+
+    fn fill_buf(&mut self) -> &[u8] {
+        let shared = &*self.shared;
+        while self.position >= self.buffer.len() {
+            // check for writers first, so that a final chunk isn't missed
+            let closed = shared.writers.load(Ordering::Acquire) == 0;
+            match shared.slot.with(shared.relax, Option::take) {
+                Some(data) => {
+                    self.buffer = data;
+                    self.position = 0;
+                },
+                None if closed => break,
+                None => (shared.relax)(),
+            }
+        }
+
+        &self.buffer[self.position..]
+    }
+}
+
+impl Read for PipeReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+
+        let len = {
+            let internal = self.fill_buf();
+            let len = min(buf.len(), internal.len());
+            buf[..len].copy_from_slice(&internal[..len]);
+            len
+        };
+        self.position += len;
+        Ok(len)
+    }
+}
+
+impl Drop for PipeReader {
+    fn drop(&mut self) {
+        self.shared.reader.store(false, Ordering::Release);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread::spawn;
+    use std::vec::Vec;
+    use io::{Read, Write};
+    use super::*;
+
+    #[test]
+    fn pipe_reader() {
+        let i = b"hello there";
+        let (mut r, mut w) = pipe();
+        let guard = spawn(move || {
+            w.write_all(&i[..5]).unwrap();
+            w.write_all(&i[5..]).unwrap();
+        });
+
+        let mut o = Vec::new();
+        let mut buf = [0; 4];
+        loop {
+            match r.read(&mut buf).unwrap() {
+                0 => break,
+                n => o.extend_from_slice(&buf[..n]),
+            }
+        }
+        assert_eq!(i, &o[..]);
+
+        guard.join().unwrap();
+    }
+
+    #[test]
+    fn pipe_writer_fail() {
+        let (r, mut w) = pipe();
+        drop(r);
+        assert_eq!(w.write_all(b"hi"), Err(io::Error::BrokenPipe));
+    }
+}
@@ -0,0 +1,802 @@
+use std::collections::VecDeque;
+use std::env::temp_dir;
+use std::fs::{remove_file, File, OpenOptions};
+use std::io::{self, BufRead, Read, Seek, SeekFrom, Write};
+use std::path::PathBuf;
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::cmp::min;
+#[cfg(feature = "zstd")]
+use std::mem::take;
+#[cfg(feature = "zstd")]
+use zstd;
+use {check_consume, epipe, BudgetPolicy, Checkpoint, MemoryBudget, PipeEvent, PipeEvents, DEFAULT_BUF_SIZE};
+use checkpoint::invalid;
+use events::Events;
+
+static SPOOL_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+struct Spool {
+    file: File,
+    path: PathBuf,
+    /// Bytes of data read and written, before any compression
+    read: u64,
+    write: u64,
+    /// Positions in the file, which differ from the above when compressing
+    file_read: u64,
+    file_write: u64,
+    #[cfg(feature = "zstd")]
+    compression: Option<i32>,
+    /// Data waiting to be compressed as one segment
+    #[cfg(feature = "zstd")]
+    pending: Vec<u8>,
+}
+
+impl Spool {
+    fn create(shared: &Shared) -> io::Result<Self> {
+        loop {
+            let path = shared.dir.join(format!("pipe-spool-{}-{}", process::id(), SPOOL_COUNTER.fetch_add(1, Ordering::Relaxed)));
+            match OpenOptions::new().read(true).write(true).create_new(true).open(&path) {
+                Ok(file) => return Ok(Spool {
+                    file,
+                    path,
+                    read: 0,
+                    write: 0,
+                    file_read: 0,
+                    file_write: 0,
+                    #[cfg(feature = "zstd")]
+                    compression: shared.compression,
+                    #[cfg(feature = "zstd")]
+                    pending: Vec::new(),
+                }),
+                Err(ref e) if e.kind() == io::ErrorKind::AlreadyExists => continue,
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    fn len(&self) -> u64 {
+        self.write - self.read
+    }
+
+    fn append(&mut self, buf: &[u8]) -> io::Result<()> {
+        #[cfg(feature = "zstd")]
+        {
+            if let Some(level) = self.compression {
+                self.pending.extend_from_slice(buf);
+                self.write += buf.len() as u64;
+                if self.pending.len() >= DEFAULT_BUF_SIZE {
+                    self.write_segment(level)?;
+                }
+                return Ok(())
+            }
+        }
+
+        self.file.seek(SeekFrom::Start(self.file_write))?;
+        self.file.write_all(buf)?;
+        self.file_write += buf.len() as u64;
+        self.write += buf.len() as u64;
+        Ok(())
+    }
+
+    fn read_into(&mut self, buffer: &mut Vec<u8>) -> io::Result<()> {
+        #[cfg(feature = "zstd")]
+        {
+            if self.compression.is_some() {
+                return self.read_segment(buffer)
+            }
+        }
+
+        let len = min(self.len(), DEFAULT_BUF_SIZE as u64) as usize;
+        buffer.resize(len, 0);
+        self.file.seek(SeekFrom::Start(self.file_read))?;
+        self.file.read_exact(buffer)?;
+        self.file_read += len as u64;
+        self.read += len as u64;
+        Ok(())
+    }
+
+    /// Reads data `offset` bytes past the next byte to be read, without consuming anything
+    fn read_at(&mut self, offset: u64, buf: &mut [u8]) -> io::Result<usize> {
+        #[cfg(feature = "zstd")]
+        {
+            if self.compression.is_some() {
+                return self.read_segment_at(offset, buf)
+            }
+        }
+
+        let len = min(self.len().saturating_sub(offset), buf.len() as u64) as usize;
+        self.file.seek(SeekFrom::Start(self.file_read + offset))?;
+        self.file.read_exact(&mut buf[..len])?;
+        Ok(len)
+    }
+}
+
+/// Copies what `data` holds at `offset` into `buf`
+fn copy_at(data: &[u8], offset: u64, buf: &mut [u8]) -> usize {
+    let data = &data[min(offset, data.len() as u64) as usize..];
+    let len = min(data.len(), buf.len());
+    buf[..len].copy_from_slice(&data[..len]);
+    len
+}
+
+/// Compressed spools are made up of segments, each a zstd frame preceded by its length and the
+/// length of the data it decompresses to. Data that doesn't fill a segment yet is kept in memory,
+/// where the reader can take it from directly.
+#[cfg(feature = "zstd")]
+impl Spool {
+    fn write_segment(&mut self, level: i32) -> io::Result<()> {
+        let frame = zstd::bulk::compress(&self.pending, level)?;
+        let mut header = [0; 8];
+        header[..4].copy_from_slice(&(frame.len() as u32).to_le_bytes());
+        header[4..].copy_from_slice(&(self.pending.len() as u32).to_le_bytes());
+
+        self.file.seek(SeekFrom::Start(self.file_write))?;
+        self.file.write_all(&header)?;
+        self.file.write_all(&frame)?;
+        self.file_write += (header.len() + frame.len()) as u64;
+        self.pending.clear();
+        Ok(())
+    }
+
+    fn read_segment(&mut self, buffer: &mut Vec<u8>) -> io::Result<()> {
+        if self.file_read == self.file_write {
+            *buffer = take(&mut self.pending);
+        } else {
+            let mut header = [0; 8];
+            self.file.seek(SeekFrom::Start(self.file_read))?;
+            self.file.read_exact(&mut header)?;
+            let mut frame = vec![0; u32_le(&header[..4]) as usize];
+            self.file.read_exact(&mut frame)?;
+            *buffer = zstd::bulk::decompress(&frame, u32_le(&header[4..]) as usize)?;
+            self.file_read += (header.len() + frame.len()) as u64;
+        }
+        self.read += buffer.len() as u64;
+        Ok(())
+    }
+
+    /// Skips over whole segments to decompress only the one holding `offset`
+    fn read_segment_at(&mut self, mut offset: u64, buf: &mut [u8]) -> io::Result<usize> {
+        let mut position = self.file_read;
+        while position < self.file_write {
+            let mut header = [0; 8];
+            self.file.seek(SeekFrom::Start(position))?;
+            self.file.read_exact(&mut header)?;
+            let (frame_len, data_len) = (u32_le(&header[..4]), u32_le(&header[4..]));
+            if offset < data_len as u64 {
+                let mut frame = vec![0; frame_len as usize];
+                self.file.read_exact(&mut frame)?;
+                let data = zstd::bulk::decompress(&frame, data_len as usize)?;
+                return Ok(copy_at(&data, offset, buf))
+            }
+            offset -= data_len as u64;
+            position += (header.len() as u32 + frame_len) as u64;
+        }
+
+        Ok(copy_at(&self.pending, offset, buf))
+    }
+}
+
+#[cfg(feature = "zstd")]
+fn u32_le(bytes: &[u8]) -> u32 {
+    let mut buf = [0; 4];
+    buf.copy_from_slice(bytes);
+    u32::from_le_bytes(buf)
+}
+
+impl Drop for Spool {
+    fn drop(&mut self) {
+        let _ = remove_file(&self.path);
+    }
+}
+
+/// How much data a spooled pipe has spilled to disk over its lifetime (see
+/// `SpooledPipeReader::spool_stats()`)
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct SpoolStats {
+    /// Bytes of data spooled, before any compression
+    pub logical_bytes: u64,
+    /// Bytes actually written to spool files
+    ///
+    /// A compressed spool only writes data once it fills a segment, so this lags behind
+    /// `logical_bytes` by up to a segment, and data the reader takes before then is never written.
+    pub physical_bytes: u64,
+}
+
+impl SpoolStats {
+    /// Returns how many logical bytes each byte written to disk holds, or `None` if nothing has
+    /// been written yet
+    pub fn compression_ratio(&self) -> Option<f64> {
+        match self.physical_bytes {
+            0 => None,
+            physical => Some(self.logical_bytes as f64 / physical as f64),
+        }
+    }
+}
+
+struct State {
+    memory: VecDeque<Vec<u8>>,
+    memory_len: usize,
+    spool: Option<Spool>,
+    stats: SpoolStats,
+    writers: usize,
+    reader: bool,
+}
+
+struct Shared {
+    state: Mutex<State>,
+    ready: Condvar,
+    mem_limit: AtomicUsize,
+    dir: PathBuf,
+    budget: Option<MemoryBudget>,
+    events: Events,
+    #[cfg(feature = "zstd")]
+    compression: Option<i32>,
+}
+
+impl Shared {
+    fn new(mem_limit: usize, dir: PathBuf, budget: Option<MemoryBudget>) -> Self {
+        Shared {
+            state: Mutex::new(State {
+                memory: VecDeque::new(),
+                memory_len: 0,
+                spool: None,
+                stats: SpoolStats::default(),
+                writers: 1,
+                reader: true,
+            }),
+            ready: Condvar::new(),
+            mem_limit: AtomicUsize::new(mem_limit),
+            dir,
+            budget,
+            events: Events::new(),
+            #[cfg(feature = "zstd")]
+            compression: None,
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn release(&self, amt: usize) {
+        if let Some(budget) = &self.budget {
+            budget.release(amt);
+        }
+    }
+}
+
+/// The `Read` end of a spooled pipe (see `pipe_spooled()`)
+pub struct SpooledPipeReader {
+    shared: Arc<Shared>,
+    buffer: Vec<u8>,
+    position: usize,
+    /// Bytes consumed so far
+    offset: u64,
+    /// The offset of the last checkpoint, and everything consumed since
+    retained: Option<(u64, Vec<u8>)>,
+}
+
+/// The `Write` end of a spooled pipe (see `pipe_spooled()`)
+pub struct SpooledPipeWriter {
+    shared: Arc<Shared>,
+}
+
+/// Creates a memory pipe that spools data to a temporary file instead of blocking the writer
+///
+/// Up to `mem_limit` bytes are queued in memory. Anything written beyond that is appended to a
+/// temporary file, which the reader drains after the in-memory data. Once the reader catches up,
+/// the file is removed and the pipe returns to queueing in memory.
+pub fn pipe_spooled(mem_limit: usize) -> (SpooledPipeReader, SpooledPipeWriter) {
+    pipe_spooled_in(mem_limit, temp_dir())
+}
+
+/// Creates a spooled pipe (see `pipe_spooled()`) that places its temporary files in `dir`
+pub fn pipe_spooled_in<P: Into<PathBuf>>(mem_limit: usize, dir: P) -> (SpooledPipeReader, SpooledPipeWriter) {
+    spooled(Shared::new(mem_limit, dir.into(), None))
+}
+
+/// Creates a spooled pipe (see `pipe_spooled()`) whose in-memory queue also counts against a
+/// shared `MemoryBudget`
+///
+/// Once the budget is exhausted, writes either block or spill to disk depending on the budget's
+/// `BudgetPolicy`.
+pub fn pipe_spooled_with_budget(mem_limit: usize, budget: &MemoryBudget) -> (SpooledPipeReader, SpooledPipeWriter) {
+    spooled(Shared::new(mem_limit, temp_dir(), Some(budget.clone())))
+}
+
+/// Creates a spooled pipe (see `pipe_spooled()`) that compresses the data it spools to disk
+///
+/// Spilled data is compressed with zstd at the given `level` in segments of about 8KiB, and
+/// decompressed again as the reader catches up. This trades CPU time for much smaller spool files
+/// when the reader falls far behind.
+#[cfg(feature = "zstd")]
+#[cfg_attr(feature = "unstable-doc-cfg", doc(cfg(feature = "zstd")))]
+pub fn pipe_spooled_compressed(mem_limit: usize, level: i32) -> (SpooledPipeReader, SpooledPipeWriter) {
+    spooled(Shared {
+        compression: Some(level),
+        ..Shared::new(mem_limit, temp_dir(), None)
+    })
+}
+
+fn spooled(shared: Shared) -> (SpooledPipeReader, SpooledPipeWriter) {
+    let shared = Arc::new(shared);
+
+    (
+        SpooledPipeReader { shared: shared.clone(), buffer: Vec::new(), position: 0, offset: 0, retained: None },
+        SpooledPipeWriter { shared },
+    )
+}
+
+impl SpooledPipeReader {
+    /// Returns a reference to the internally buffered data.
+    pub fn buffer(&self) -> &[u8] {
+        &self.buffer[self.position..]
+    }
+
+    /// Returns the number of bytes queued in memory
+    pub fn memory_len(&self) -> usize {
+        self.shared.lock().memory_len
+    }
+
+    /// Returns the number of bytes waiting in the spool file
+    pub fn spooled_len(&self) -> u64 {
+        self.shared.lock().spool.as_ref().map(Spool::len).unwrap_or(0)
+    }
+
+    /// Returns how much data the pipe has spooled to disk, both before and after compression
+    pub fn spool_stats(&self) -> SpoolStats {
+        self.shared.lock().stats
+    }
+
+    /// Subscribes to the pipe's events
+    pub fn events(&self) -> PipeEvents {
+        self.shared.events.subscribe()
+    }
+
+    /// Marks everything consumed so far as processed, returning a checkpoint that the reader can
+    /// resume from with `resume_from()`
+    ///
+    /// Data is normally gone from a spooled pipe once it has been read. After a checkpoint, the
+    /// reader keeps everything it consumes until the next one, so that a consumer that fails
+    /// halfway through processing it can start over without losing any data.
+    pub fn checkpoint(&mut self) -> Checkpoint {
+        self.retained = Some((self.offset, Vec::new()));
+        Checkpoint::new(self.id(), self.offset)
+    }
+
+    /// Rewinds the reader to `checkpoint`, so that the data consumed since is read again
+    ///
+    /// Fails with `io::ErrorKind::InvalidInput` if the checkpoint wasn't taken on this pipe, or
+    /// if it is older than the last checkpoint, whose data has been let go of.
+    pub fn resume_from(&mut self, checkpoint: Checkpoint) -> io::Result<()> {
+        let offset = checkpoint.check(self.id())?;
+        let (start, retained) = match &mut self.retained {
+            Some((start, retained)) if (*start..=self.offset).contains(&offset) => (*start, retained),
+            _ => return Err(invalid("checkpoint is no longer retained")),
+        };
+
+        let mut replay = retained.split_off((offset - start) as usize);
+        replay.extend_from_slice(&self.buffer[self.position..]);
+        self.buffer = replay;
+        self.position = 0;
+        self.offset = offset;
+        Ok(())
+    }
+
+    fn id(&self) -> usize {
+        Arc::as_ptr(&self.shared) as usize
+    }
+
+    /// Reads from the backlog, starting `offset` bytes past the next byte to be read, without
+    /// consuming anything
+    ///
+    /// Data is read from wherever it is queued, whether that's the reader's own buffer, memory or
+    /// the spool file. As with `pread()`, this may read fewer bytes than fit in `buf`, and only
+    /// returns `0` if `offset` is past the end of the backlog or `buf` is empty. A compressed
+    /// spool decompresses the one segment holding `offset`.
+    pub fn read_at(&self, mut offset: u64, buf: &mut [u8]) -> io::Result<usize> {
+        let buffered = self.buffer();
+        if offset < buffered.len() as u64 {
+            return Ok(copy_at(buffered, offset, buf))
+        }
+        offset -= buffered.len() as u64;
+
+        let mut state = self.shared.lock();
+        for data in &state.memory {
+            if offset < data.len() as u64 {
+                return Ok(copy_at(data, offset, buf))
+            }
+            offset -= data.len() as u64;
+        }
+        match state.spool.as_mut() {
+            Some(spool) => spool.read_at(offset, buf),
+            None => Ok(0),
+        }
+    }
+}
+
+impl BufRead for SpooledPipeReader {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        if self.position >= self.buffer.len() {
+            let mut state = self.shared.lock();
+            loop {
+                if let Some(data) = state.memory.pop_front() {
+                    state.memory_len -= data.len();
+                    self.shared.release(data.len());
+                    self.buffer = data;
+                    self.position = 0;
+                } else if let Some(spool) = state.spool.as_mut() {
+                    spool.read_into(&mut self.buffer)?;
+                    self.position = 0;
+                    if spool.len() == 0 {
+                        // caught up, so the file is no longer needed
+                        state.spool = None;
+                    }
+                } else if state.writers == 0 {
+                    break
+                } else {
+                    state = self.shared.ready.wait(state).unwrap_or_else(|e| e.into_inner());
+                    continue
+                }
+
+                if self.position < self.buffer.len() {
+                    break
+                }
+            }
+        }
+
+        Ok(&self.buffer[self.position..])
+    }
+
+    fn consume(&mut self, amt: usize) {
+        check_consume(amt, self.buffer.len() - self.position);
+        if let Some((_, retained)) = &mut self.retained {
+            retained.extend_from_slice(&self.buffer[self.position..self.position + amt]);
+        }
+        self.position += amt;
+        self.offset += amt as u64;
+    }
+}
+
+impl Read for SpooledPipeReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+
+        let internal = self.fill_buf()?;
+
+        let len = min(buf.len(), internal.len());
+        if len > 0 {
+            buf[..len].copy_from_slice(&internal[..len]);
+            self.consume(len);
+        }
+        Ok(len)
+    }
+}
+
+impl Drop for SpooledPipeReader {
+    fn drop(&mut self) {
+        let mut state = self.shared.lock();
+        state.reader = false;
+        state.memory.clear();
+        self.shared.release(state.memory_len);
+        state.memory_len = 0;
+        state.spool = None;
+        self.shared.events.emit(PipeEvent::ReaderDropped);
+    }
+}
+
+impl SpooledPipeWriter {
+    /// Returns how much data the pipe has spooled to disk (see `SpooledPipeReader::spool_stats()`)
+    pub fn spool_stats(&self) -> SpoolStats {
+        self.shared.lock().stats
+    }
+
+    /// Subscribes to the pipe's events
+    pub fn events(&self) -> PipeEvents {
+        self.shared.events.subscribe()
+    }
+
+    /// Returns the number of bytes that can be queued in memory before writes spill to disk
+    pub fn mem_limit(&self) -> usize {
+        self.shared.mem_limit.load(Ordering::Relaxed)
+    }
+
+    /// Changes the number of bytes that can be queued in memory before writes spill to disk
+    ///
+    /// Shrinking the limit below what is already queued moves none of that data to disk: writes
+    /// spill until the reader has drained the queue below the new limit. Once data has been
+    /// spilled, writes keep going to disk until the reader has caught up with the spool file,
+    /// whatever the limit.
+    pub fn set_mem_limit(&self, mem_limit: usize) {
+        self.shared.mem_limit.store(mem_limit, Ordering::Relaxed);
+    }
+
+    /// Write data to the associated `SpooledPipeReader`, without blocking on the reader
+    pub fn send<B: Into<Vec<u8>>>(&self, bytes: B) -> io::Result<()> {
+        let bytes = bytes.into();
+        let len = bytes.len();
+        let mem_limit = self.mem_limit();
+        let mut state = self.shared.lock();
+        if !state.reader {
+            return Err(epipe())
+        }
+
+        let mut in_memory = state.spool.is_none() && state.memory_len + len <= mem_limit;
+        if in_memory {
+            if let Some(budget) = &self.shared.budget {
+                if !budget.try_acquire(len) {
+                    self.shared.events.emit(PipeEvent::Overflowed);
+                    match budget.policy() {
+                        BudgetPolicy::Spill => in_memory = false,
+                        BudgetPolicy::Block => {
+                            // the reader needs the lock to free up memory
+                            drop(state);
+                            budget.acquire(len);
+                            state = self.shared.lock();
+                            if !state.reader {
+                                budget.release(len);
+                                return Err(epipe())
+                            }
+                            if state.spool.is_some() {
+                                // another writer spilled while we were waiting
+                                budget.release(len);
+                                in_memory = false;
+                            }
+                        },
+                    }
+                }
+            }
+        }
+
+        if in_memory {
+            state.memory_len += len;
+            state.memory.push_back(bytes);
+        } else {
+            if state.spool.is_none() {
+                if state.memory_len + len > mem_limit {
+                    self.shared.events.emit(PipeEvent::Overflowed);
+                }
+                state.spool = Some(Spool::create(&self.shared)?);
+                self.shared.events.emit(PipeEvent::SpilledToDisk);
+            }
+            let state = &mut *state;
+            if let Some(spool) = state.spool.as_mut() {
+                let file_write = spool.file_write;
+                let res = spool.append(&bytes);
+                state.stats.physical_bytes += spool.file_write - file_write;
+                res?;
+                state.stats.logical_bytes += len as u64;
+            }
+        }
+
+        self.shared.ready.notify_one();
+        Ok(())
+    }
+}
+
+impl Clone for SpooledPipeWriter {
+    fn clone(&self) -> Self {
+        self.shared.lock().writers += 1;
+        SpooledPipeWriter { shared: self.shared.clone() }
+    }
+}
+
+impl Write for SpooledPipeWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.send(buf)
+            .map(|_| buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Drop for SpooledPipeWriter {
+    fn drop(&mut self) {
+        let mut state = self.shared.lock();
+        state.writers -= 1;
+        self.shared.events.emit(PipeEvent::WriterDropped);
+        if state.writers == 0 {
+            self.shared.ready.notify_all();
+            self.shared.events.emit(PipeEvent::Finished);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Read, Write};
+    use std::thread::spawn;
+    use super::*;
+
+    #[test]
+    fn spooled_checkpoint() {
+        let (mut r, mut w) = pipe_spooled(4);
+        w.write_all(b"abcdefgh").unwrap();
+        let mut buf = [0; 2];
+        r.read_exact(&mut buf).unwrap();
+        let checkpoint = r.checkpoint();
+
+        // a consumer reads some more, then fails before it is done with it
+        let mut buf = [0; 4];
+        r.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"cdef");
+        r.resume_from(checkpoint).unwrap();
+        drop(w);
+
+        let mut o = Vec::new();
+        r.read_to_end(&mut o).unwrap();
+        assert_eq!(o, b"cdefgh");
+
+        r.checkpoint();
+        assert_eq!(r.resume_from(checkpoint).unwrap_err().kind(), io::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn spooled_overflow() {
+        let (mut r, mut w) = pipe_spooled(64);
+        let i: Vec<u8> = (0..DEFAULT_BUF_SIZE * 2).map(|i| i as u8).collect();
+        for chunk in i.chunks(32) {
+            w.write_all(chunk).unwrap();
+        }
+        assert_eq!(r.memory_len(), 64);
+        assert_eq!(r.spooled_len(), i.len() as u64 - 64);
+        let spooled = i.len() as u64 - 64;
+        assert_eq!(w.spool_stats(), SpoolStats { logical_bytes: spooled, physical_bytes: spooled });
+        drop(w);
+
+        let mut o = Vec::new();
+        r.read_to_end(&mut o).unwrap();
+        assert_eq!(i, o);
+        assert_eq!(r.spooled_len(), 0);
+    }
+
+    #[test]
+    fn spooled_set_mem_limit() {
+        let (mut r, mut w) = pipe_spooled(8);
+        w.write_all(b"abcd").unwrap();
+        w.set_mem_limit(2);
+        assert_eq!(w.mem_limit(), 2);
+        w.write_all(b"ef").unwrap();
+        assert_eq!(r.memory_len(), 4);
+        assert_eq!(r.spooled_len(), 2);
+
+        let mut buf = [0; 6];
+        r.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"abcdef");
+        w.write_all(b"gh").unwrap();
+        w.set_mem_limit(8);
+        w.write_all(b"ijklmn").unwrap();
+        assert_eq!(r.memory_len(), 8);
+        assert_eq!(r.spooled_len(), 0);
+    }
+
+    #[test]
+    fn spooled_read_at() {
+        let (mut r, mut w) = pipe_spooled(4);
+        w.write_all(b"abcd").unwrap();
+        w.write_all(b"efgh").unwrap();
+        w.write_all(b"ijkl").unwrap();
+
+        let mut buf = [0; 8];
+        assert_eq!(r.read_at(0, &mut buf).unwrap(), 4);
+        assert_eq!(&buf[..4], b"abcd");
+        assert_eq!(r.read_at(6, &mut buf[..2]).unwrap(), 2);
+        assert_eq!(&buf[..2], b"gh");
+        assert_eq!(r.read_at(12, &mut buf).unwrap(), 0);
+
+        r.read_exact(&mut buf[..5]).unwrap();
+        assert_eq!(r.read_at(0, &mut buf).unwrap(), 7);
+        assert_eq!(&buf[..7], b"fghijkl");
+    }
+
+    #[test]
+    fn spooled_events() {
+        let (r, mut w) = pipe_spooled(4);
+        let events = r.events();
+        w.write_all(b"abcd").unwrap();
+        w.write_all(b"efgh").unwrap();
+        w.write_all(b"ijkl").unwrap();
+        drop(w);
+        drop(r);
+        assert_eq!(events.collect::<Vec<_>>(), [
+            PipeEvent::Overflowed,
+            PipeEvent::SpilledToDisk,
+            PipeEvent::WriterDropped,
+            PipeEvent::Finished,
+            PipeEvent::ReaderDropped,
+        ]);
+    }
+
+    #[test]
+    fn spooled_threaded() {
+        let (mut r, mut w) = pipe_spooled(16);
+        let guard = spawn(move || {
+            for _ in 0..100 {
+                w.write_all(b"hello there").unwrap();
+            }
+        });
+
+        let mut o = Vec::new();
+        r.read_to_end(&mut o).unwrap();
+        assert_eq!(o.len(), 1100);
+        assert!(o.chunks(11).all(|c| c == b"hello there"));
+
+        guard.join().unwrap();
+    }
+
+    #[test]
+    fn spooled_budget_spill() {
+        let budget = MemoryBudget::new(100, BudgetPolicy::Spill);
+        let (r1, mut w1) = pipe_spooled_with_budget(100, &budget);
+        let (r2, mut w2) = pipe_spooled_with_budget(100, &budget);
+        w1.write_all(&[0; 80]).unwrap();
+        w2.write_all(&[0; 80]).unwrap();
+        assert_eq!(budget.used(), 80);
+        assert_eq!(r1.memory_len(), 80);
+        assert_eq!(r2.spooled_len(), 80);
+
+        drop(r1);
+        assert_eq!(budget.used(), 0);
+    }
+
+    #[test]
+    fn spooled_budget_block() {
+        let budget = MemoryBudget::new(10, BudgetPolicy::Block);
+        let (mut r1, mut w1) = pipe_spooled_with_budget(100, &budget);
+        let (mut r2, mut w2) = pipe_spooled_with_budget(100, &budget);
+        w1.write_all(&[1; 10]).unwrap();
+        drop(w1);
+        let guard = spawn(move || {
+            w2.write_all(&[2; 10]).unwrap();
+        });
+
+        let mut o = Vec::new();
+        r1.read_to_end(&mut o).unwrap();
+        assert_eq!(o, [1; 10]);
+        guard.join().unwrap();
+        o.clear();
+        r2.read_to_end(&mut o).unwrap();
+        assert_eq!(o, [2; 10]);
+        assert_eq!(budget.used(), 0);
+    }
+
+    #[cfg(feature = "zstd")]
+    #[test]
+    fn spooled_compressed() {
+        let (mut r, mut w) = pipe_spooled_compressed(64, 3);
+        let i: Vec<u8> = (0..DEFAULT_BUF_SIZE * 4 + 100).map(|i| (i % 7) as u8).collect();
+        for chunk in i.chunks(32) {
+            w.write_all(chunk).unwrap();
+        }
+        assert_eq!(r.spooled_len(), i.len() as u64 - 64);
+        let file_len = r.shared.lock().spool.as_ref().unwrap().file_write;
+        assert!(file_len < DEFAULT_BUF_SIZE as u64);
+        let stats = r.spool_stats();
+        assert_eq!((stats.logical_bytes, stats.physical_bytes), (i.len() as u64 - 64, file_len));
+        assert!(stats.compression_ratio().unwrap() > 4.0);
+        let mut buf = [0; 16];
+        for &offset in &[10_000, i.len() - 20] {
+            assert_eq!(r.read_at(offset as u64, &mut buf).unwrap(), 16);
+            assert_eq!(buf, i[offset..offset + 16]);
+        }
+        drop(w);
+
+        let mut o = Vec::new();
+        r.read_to_end(&mut o).unwrap();
+        assert_eq!(i, o);
+        assert_eq!(r.spooled_len(), 0);
+    }
+
+    #[test]
+    fn spooled_writer_fail() {
+        let (r, mut w) = pipe_spooled(16);
+        drop(r);
+        assert!(w.write_all(b"hi").is_err());
+    }
+}
@@ -0,0 +1,436 @@
+use crossbeam_channel::{self, Receiver, Select, Sender, SendError, SendTimeoutError};
+use std::borrow::Cow;
+use std::cmp::min;
+use std::fmt;
+use std::io;
+use std::sync::{Arc, Mutex, Weak};
+#[cfg(all(feature = "readiness", unix))]
+use std::sync::OnceLock;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::time::Instant;
+use control::{Gate, PipeControl};
+use error::OS_EPIPE;
+use events::{Events, PipeEvent, PipeEvents};
+#[cfg(all(feature = "readiness", unix))]
+use readiness::Readiness;
+#[cfg(feature = "http-body")]
+use body::Notify;
+
+/// A snapshot of a pipe endpoint's state, for diagnostics (see `PipeReader::dump_state()`)
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PipeState {
+    /// The name the pipe was created with, if any
+    pub name: Option<Cow<'static, str>>,
+    /// Bytes held by this endpoint that haven't been read or sent yet
+    pub buffered: usize,
+    /// Chunks waiting in the underlying channel
+    pub queued: usize,
+    /// Number of bytes a buffered writer holds before sending them
+    pub capacity: Option<usize>,
+    /// Number of readers still attached to the pipe
+    pub readers: usize,
+    /// Number of writers still attached to the pipe
+    pub writers: usize,
+}
+
+/// How hard a pipe is pushing back on its writers (see `PipeWriter::pressure()`)
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Pressure {
+    /// Bytes that writers are currently blocked on handing over to a reader
+    pub queued_bytes: usize,
+    /// Whether a reader is currently waiting for data, so that a write would be received at once
+    pub is_reader_waiting: bool,
+}
+
+/// A freeze-frame of a pipe endpoint, to attach to a bug report when a pipeline wedges (see
+/// `PipeReader::snapshot()`)
+///
+/// `Display` formats the snapshot as a report of a few lines.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PipeSnapshot {
+    /// The state of the endpoint and its pipe
+    pub state: PipeState,
+    /// The pressure on the pipe's writers
+    pub pressure: Pressure,
+    /// Whether delivery to the reader is paused (see `PipeControl`)
+    pub paused: bool,
+    /// Whether a reader cut off the writers (see `PipeReader::disconnect_writers()`)
+    pub disconnected: bool,
+    /// The start of the data buffered by the endpoint, up to the limit passed to `snapshot()`
+    pub data: Vec<u8>,
+}
+
+impl fmt::Display for PipeSnapshot {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let state = &self.state;
+        match &state.name {
+            Some(name) => write!(f, "pipe '{}'", name)?,
+            None => f.write_str("pipe")?,
+        }
+        writeln!(f, ": {} readers, {} writers{}{}", state.readers, state.writers,
+            if self.paused { ", paused" } else { "" },
+            if self.disconnected { ", disconnected" } else { "" },
+        )?;
+        write!(f, "buffered: {} bytes", state.buffered)?;
+        if let Some(capacity) = state.capacity {
+            write!(f, " of {}", capacity)?;
+        }
+        writeln!(f, ", queued: {} chunks, pending: {} bytes, reader waiting: {}",
+            state.queued, self.pressure.queued_bytes, self.pressure.is_reader_waiting,
+        )?;
+        write!(f, "data: \"{}\"", self.data.escape_ascii())?;
+        if self.data.len() < state.buffered {
+            write!(f, " ({} more bytes)", state.buffered - self.data.len())?;
+        }
+        Ok(())
+    }
+}
+
+/// State shared by all handles to a pipe
+pub(crate) struct Shared {
+    name: Option<Cow<'static, str>>,
+    readers: AtomicUsize,
+    writers: AtomicUsize,
+    writer_ids: AtomicUsize,
+    pending: AtomicUsize,
+    waiting: AtomicUsize,
+    delivered: AtomicU64,
+    sequence: AtomicU64,
+    raw_os_errors: AtomicBool,
+    disconnected: AtomicBool,
+    /// Dropped on `disconnect()` so that writers blocked handing over a chunk wake up
+    disconnect_signal: Mutex<Option<Sender<()>>>,
+    disconnect_watch: Receiver<()>,
+    gate: Gate,
+    events: Events,
+    #[cfg(all(feature = "readiness", unix))]
+    readiness: OnceLock<Readiness>,
+    #[cfg(feature = "http-body")]
+    notify: Notify,
+}
+
+impl Shared {
+    pub(crate) fn new() -> Arc<Self> {
+        Self::with_name(None)
+    }
+
+    pub(crate) fn with_name(name: Option<Cow<'static, str>>) -> Arc<Self> {
+        let (disconnect_signal, disconnect_watch) = crossbeam_channel::bounded(0);
+        Arc::new(Shared {
+            name,
+            readers: AtomicUsize::new(0),
+            writers: AtomicUsize::new(0),
+            writer_ids: AtomicUsize::new(0),
+            pending: AtomicUsize::new(0),
+            waiting: AtomicUsize::new(0),
+            delivered: AtomicU64::new(0),
+            sequence: AtomicU64::new(0),
+            raw_os_errors: AtomicBool::new(false),
+            disconnected: AtomicBool::new(false),
+            disconnect_signal: Mutex::new(Some(disconnect_signal)),
+            disconnect_watch,
+            gate: Gate::new(),
+            events: Events::new(),
+            #[cfg(all(feature = "readiness", unix))]
+            readiness: OnceLock::new(),
+            #[cfg(feature = "http-body")]
+            notify: Notify::new(),
+        })
+    }
+
+    pub(crate) fn name(&self) -> Option<&str> {
+        self.name.as_ref().map(|name| &name[..])
+    }
+
+    /// The error returned by writers once the reader is gone, naming the pipe if possible
+    pub(crate) fn epipe(&self) -> io::Error {
+        if let (true, Some(code)) = (self.raw_os_errors(), OS_EPIPE) {
+            return io::Error::from_raw_os_error(code)
+        }
+
+        match self.name() {
+            Some(name) => io::Error::new(io::ErrorKind::BrokenPipe, format!("pipe '{}' reader has been dropped", name)),
+            None => ::epipe(),
+        }
+    }
+
+    pub(crate) fn raw_os_errors(&self) -> bool {
+        self.raw_os_errors.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn set_raw_os_errors(&self, raw: bool) {
+        self.raw_os_errors.store(raw, Ordering::Relaxed);
+    }
+
+    pub(crate) fn readers(&self) -> usize {
+        self.readers.load(Ordering::Acquire)
+    }
+
+    pub(crate) fn writers(&self) -> usize {
+        self.writers.load(Ordering::Acquire)
+    }
+
+    pub(crate) fn pressure(&self) -> Pressure {
+        Pressure {
+            queued_bytes: self.pending.load(Ordering::Acquire),
+            is_reader_waiting: self.waiting.load(Ordering::Acquire) > 0,
+        }
+    }
+
+    /// Counts `len` bytes as being handed over for as long as the guard is alive
+    pub(crate) fn pending(&self, len: usize) -> Counted<'_> {
+        self.pending.fetch_add(len, Ordering::AcqRel);
+        Counted(&self.pending, len)
+    }
+
+    /// Counts a reader as waiting for data for as long as the guard is alive
+    pub(crate) fn waiting(&self) -> Counted<'_> {
+        self.waiting.fetch_add(1, Ordering::AcqRel);
+        Counted(&self.waiting, 1)
+    }
+
+    /// Returns the number of chunks received by readers so far
+    pub(crate) fn delivered(&self) -> u64 {
+        self.delivered.load(Ordering::Relaxed)
+    }
+
+    /// Allocates the next sequence number for a chunk sent through the pipe, starting at `1`
+    pub(crate) fn next_sequence(&self) -> u64 {
+        self.sequence.fetch_add(1, Ordering::Relaxed) + 1
+    }
+
+    /// Whether a reader has cut off the writers (see `PipeReader::disconnect_writers()`)
+    pub(crate) fn is_disconnected(&self) -> bool {
+        self.disconnected.load(Ordering::Acquire)
+    }
+
+    pub(crate) fn disconnect(&self) {
+        self.disconnected.store(true, Ordering::Release);
+        drop(self.disconnect_signal.lock().unwrap_or_else(|e| e.into_inner()).take());
+    }
+
+    /// Captures `state` along with that of the pipe, and up to `max_data` bytes of `buffered`
+    pub(crate) fn snapshot(&self, state: PipeState, buffered: &[u8], max_data: usize) -> PipeSnapshot {
+        PipeSnapshot {
+            state,
+            pressure: self.pressure(),
+            paused: self.gate.is_paused(),
+            disconnected: self.is_disconnected(),
+            data: buffered[..min(buffered.len(), max_data)].to_vec(),
+        }
+    }
+
+    pub(crate) fn gate(&self) -> &Gate {
+        &self.gate
+    }
+
+    pub(crate) fn events(&self) -> PipeEvents {
+        self.events.subscribe()
+    }
+
+    pub(crate) fn emit(&self, event: PipeEvent) {
+        self.events.emit(event);
+    }
+
+    /// Blocks until the reader receives `data`, keeping any readiness handle up to date
+    #[cfg(not(feature = "http-body"))]
+    pub(crate) fn send<T>(&self, sender: &Sender<T>, data: T) -> Result<(), SendError<T>> {
+        self.send_blocking(sender, data)
+    }
+
+    /// Blocks until the reader receives `data`, waking any async reader first
+    #[cfg(feature = "http-body")]
+    pub(crate) fn send<T>(&self, sender: &Sender<T>, data: T) -> Result<(), SendError<T>> {
+        self.notify.send(sender, data, |data| self.send_blocking(sender, data))
+    }
+
+    fn send_blocking<T>(&self, sender: &Sender<T>, data: T) -> Result<(), SendError<T>> {
+        if self.is_disconnected() {
+            return Err(SendError(data))
+        }
+
+        #[cfg(all(feature = "readiness", unix))]
+        {
+            if let Some(readiness) = self.readiness.get() {
+                return readiness.send(sender, data, |data| self.send_until_disconnected(sender, data, None).map_err(|e| SendError(e.into_inner())))
+            }
+        }
+
+        self.send_until_disconnected(sender, data, None).map_err(|e| SendError(e.into_inner()))
+    }
+
+    /// Blocks until the reader receives `data` like `send()`, but gives up at `deadline`
+    ///
+    /// Readers waiting on a readiness handle or asynchronously aren't woken up, so this is only
+    /// meant for readers that block.
+    pub(crate) fn send_deadline<T>(&self, sender: &Sender<T>, data: T, deadline: Instant) -> Result<(), SendTimeoutError<T>> {
+        if self.is_disconnected() {
+            return Err(SendTimeoutError::Disconnected(data))
+        }
+
+        self.send_until_disconnected(sender, data, Some(deadline))
+    }
+
+    /// Blocks until the reader receives `data`, the writers are disconnected, or `deadline` passes
+    ///
+    /// Checking `is_disconnected()` beforehand isn't enough on its own: the reader may disconnect
+    /// and discard what's pending right after, leaving the writer blocked forever.
+    fn send_until_disconnected<T>(&self, sender: &Sender<T>, data: T, deadline: Option<Instant>) -> Result<(), SendTimeoutError<T>> {
+        let mut select = Select::new();
+        let send = select.send(sender);
+        select.recv(&self.disconnect_watch);
+        let oper = match deadline {
+            Some(deadline) => match select.select_deadline(deadline) {
+                Ok(oper) => oper,
+                Err(_) => return Err(SendTimeoutError::Timeout(data)),
+            },
+            None => select.select(),
+        };
+
+        if oper.index() == send {
+            oper.send(sender, data).map_err(|e| SendTimeoutError::Disconnected(e.into_inner()))
+        } else {
+            let _ = oper.recv(&self.disconnect_watch);
+            Err(SendTimeoutError::Disconnected(data))
+        }
+    }
+
+    /// Called by the reader after receiving a chunk
+    pub(crate) fn received(&self) {
+        self.delivered.fetch_add(1, Ordering::Relaxed);
+        #[cfg(all(feature = "readiness", unix))]
+        {
+            if let Some(readiness) = self.readiness.get() {
+                readiness.received();
+                // the last writer may have been dropped (and signalled EOF) before the drain
+                if self.writers() == 0 {
+                    readiness.signal();
+                }
+            }
+        }
+    }
+
+    /// Called once the pipe is resumed after being paused
+    pub(crate) fn resumed(&self) {
+        #[cfg(feature = "http-body")]
+        self.notify.wake();
+    }
+
+    #[cfg(feature = "http-body")]
+    pub(crate) fn notify(&self) -> &Notify {
+        &self.notify
+    }
+
+    #[cfg(all(feature = "readiness", unix))]
+    pub(crate) fn readiness(&self) -> io::Result<&Readiness> {
+        if let Some(readiness) = self.readiness.get() {
+            return Ok(readiness)
+        }
+
+        // if another thread got there first, the new handle is just closed again
+        let _ = self.readiness.set(Readiness::new()?);
+        Ok(self.readiness.get().expect("readiness was just initialized"))
+    }
+
+    /// Allocates an identity for a new writer
+    pub(crate) fn next_writer_id(&self) -> usize {
+        self.writer_ids.fetch_add(1, Ordering::Relaxed)
+    }
+}
+
+/// Undoes an increment of one of the pipe's counters once dropped
+pub(crate) struct Counted<'a>(&'a AtomicUsize, usize);
+
+impl Drop for Counted<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(self.1, Ordering::AcqRel);
+    }
+}
+
+/// Counts a live reader of a pipe for as long as it exists
+pub(crate) struct ReaderHandle(Arc<Shared>);
+
+impl ReaderHandle {
+    pub(crate) fn new(shared: Arc<Shared>) -> Self {
+        shared.readers.fetch_add(1, Ordering::AcqRel);
+        ReaderHandle(shared)
+    }
+
+    pub(crate) fn shared(&self) -> &Shared {
+        &self.0
+    }
+
+    pub(crate) fn control(&self) -> PipeControl {
+        PipeControl::new(self.0.clone())
+    }
+}
+
+impl Clone for ReaderHandle {
+    fn clone(&self) -> Self {
+        ReaderHandle::new(self.0.clone())
+    }
+}
+
+impl Drop for ReaderHandle {
+    fn drop(&mut self) {
+        self.0.readers.fetch_sub(1, Ordering::AcqRel);
+        self.0.events.emit(PipeEvent::ReaderDropped);
+    }
+}
+
+/// Counts a live writer of a pipe for as long as it exists, unless it is `uncounted()`
+pub(crate) struct WriterHandle(Arc<Shared>, bool);
+
+impl WriterHandle {
+    pub(crate) fn new(shared: Arc<Shared>) -> Self {
+        shared.writers.fetch_add(1, Ordering::AcqRel);
+        WriterHandle(shared, true)
+    }
+
+    /// Refers to the same pipe without counting as another writer of it, for a helper that acts
+    /// on behalf of this writer
+    pub(crate) fn uncounted(&self) -> Self {
+        WriterHandle(self.0.clone(), false)
+    }
+
+    pub(crate) fn shared(&self) -> &Shared {
+        &self.0
+    }
+
+    pub(crate) fn control(&self) -> PipeControl {
+        PipeControl::new(self.0.clone())
+    }
+
+    pub(crate) fn downgrade(&self) -> Weak<Shared> {
+        Arc::downgrade(&self.0)
+    }
+}
+
+impl Clone for WriterHandle {
+    fn clone(&self) -> Self {
+        WriterHandle::new(self.0.clone())
+    }
+}
+
+impl Drop for WriterHandle {
+    fn drop(&mut self) {
+        if !self.1 {
+            return
+        }
+
+        let last = self.0.writers.fetch_sub(1, Ordering::AcqRel) == 1;
+        self.0.events.emit(PipeEvent::WriterDropped);
+        if last {
+            self.0.events.emit(PipeEvent::Finished);
+            // EOF is readable too
+            #[cfg(all(feature = "readiness", unix))]
+            {
+                if let Some(readiness) = self.0.readiness.get() {
+                    readiness.signal();
+                }
+            }
+            #[cfg(feature = "http-body")]
+            self.0.notify.wake();
+        }
+    }
+}
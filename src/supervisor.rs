@@ -0,0 +1,303 @@
+use std::any::Any;
+use std::error::Error;
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::thread::{spawn, JoinHandle};
+use {pump, Pipeline, PumpHandle};
+
+/// Whether a supervised task is run again after it fails (see `Supervisor::spawn_restartable()`)
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, Default)]
+pub enum RestartPolicy {
+    /// The first failure is final
+    #[default]
+    Never,
+    /// The task is restarted after returning an error or panicking, up to `max_restarts` times
+    OnFailure {
+        /// How many times the task may be restarted
+        max_restarts: usize,
+    },
+}
+
+/// How a supervised task failed
+#[derive(Debug)]
+pub enum TaskFailure {
+    /// The task returned an error
+    Error(io::Error),
+    /// The task panicked with the given message
+    Panic(String),
+}
+
+impl TaskFailure {
+    fn panic(payload: Box<dyn Any + Send>) -> Self {
+        let message = match payload.downcast::<String>() {
+            Ok(message) => *message,
+            Err(payload) => match payload.downcast::<&'static str>() {
+                Ok(message) => message.to_string(),
+                Err(_) => "Box<dyn Any>".into(),
+            },
+        };
+        TaskFailure::Panic(message)
+    }
+}
+
+impl fmt::Display for TaskFailure {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TaskFailure::Error(e) => fmt::Display::fmt(e, f),
+            TaskFailure::Panic(message) => write!(f, "panicked: {}", message),
+        }
+    }
+}
+
+/// The failure of one task of a `Supervisor`
+#[derive(Debug)]
+pub struct TaskError {
+    /// The name the task was spawned with
+    pub name: String,
+    /// How it failed the last time it ran
+    pub failure: TaskFailure,
+    /// How many times it was restarted before giving up
+    pub restarts: usize,
+}
+
+impl fmt::Display for TaskError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} failed: {}", self.name, self.failure)
+    }
+}
+
+impl Error for TaskError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match &self.failure {
+            TaskFailure::Error(e) => Some(e),
+            TaskFailure::Panic(..) => None,
+        }
+    }
+}
+
+/// The tasks of a `Supervisor` that failed, in the order they were spawned (see
+/// `Supervisor::join()`)
+#[derive(Debug)]
+pub struct SupervisorError {
+    errors: Vec<TaskError>,
+}
+
+impl SupervisorError {
+    /// Returns every failure, in the order the tasks were spawned
+    pub fn errors(&self) -> &[TaskError] {
+        &self.errors
+    }
+
+    /// Converts the report into its list of failures
+    pub fn into_errors(self) -> Vec<TaskError> {
+        self.errors
+    }
+}
+
+impl fmt::Display for SupervisorError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(&self.errors[0], f)?;
+        if self.errors.len() > 1 {
+            write!(f, " (and {} more)", self.errors.len() - 1)?;
+        }
+        Ok(())
+    }
+}
+
+impl Error for SupervisorError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        Some(&self.errors[0])
+    }
+}
+
+type Outcome = Result<(), (TaskFailure, usize)>;
+
+enum Task {
+    Thread(JoinHandle<Outcome>),
+    Pump(PumpHandle),
+}
+
+/// Owns the threads of a group of tasks, such as pipelines and pumps, and reports how they ended
+///
+/// Errors and panics alike are collected as `TaskError`s, rather than a panic being resumed by
+/// whoever joins the thread. Tasks that are safe to run more than once can be restarted after a
+/// failure (see `spawn_restartable()`). Dropping the supervisor without calling `join()` leaves its
+/// threads running on their own.
+///
+/// # Example
+///
+/// ```
+/// use std::io::Read;
+/// use pipe::Supervisor;
+///
+/// let (mut reader, writer) = pipe::pipe();
+/// let mut supervisor = Supervisor::new();
+/// supervisor.pump("feed", &b"hello"[..], writer);
+/// supervisor.spawn("faulty", || panic!("oops"));
+///
+/// let mut data = String::new();
+/// reader.read_to_string(&mut data).unwrap();
+/// assert_eq!(data, "hello");
+/// let err = supervisor.join().unwrap_err();
+/// assert_eq!(err.to_string(), "faulty failed: panicked: oops");
+/// ```
+#[derive(Default)]
+pub struct Supervisor {
+    tasks: Vec<(String, Task)>,
+}
+
+impl Supervisor {
+    /// Creates a supervisor without any tasks
+    pub fn new() -> Self {
+        Supervisor::default()
+    }
+
+    /// Runs `task` on a thread of its own
+    pub fn spawn<N, F>(&mut self, name: N, task: F) where
+        N: Into<String>,
+        F: FnOnce() -> io::Result<()> + Send + 'static,
+    {
+        let thread = spawn(move || task().map_err(|e| (TaskFailure::Error(e), 0)));
+        self.tasks.push((name.into(), Task::Thread(thread)));
+    }
+
+    /// Runs `task` on a thread of its own, running it again on the same thread whenever it fails,
+    /// as far as `policy` allows
+    ///
+    /// This is meant for idempotent tasks, since a task may have been partway through when it
+    /// failed.
+    pub fn spawn_restartable<N, F>(&mut self, name: N, policy: RestartPolicy, mut task: F) where
+        N: Into<String>,
+        F: FnMut() -> io::Result<()> + Send + 'static,
+    {
+        let max_restarts = match policy {
+            RestartPolicy::Never => 0,
+            RestartPolicy::OnFailure { max_restarts } => max_restarts,
+        };
+        let thread = spawn(move || {
+            let mut restarts = 0;
+            loop {
+                let failure = match catch_unwind(AssertUnwindSafe(&mut task)) {
+                    Ok(Ok(())) => return Ok(()),
+                    Ok(Err(e)) => TaskFailure::Error(e),
+                    Err(payload) => TaskFailure::panic(payload),
+                };
+                if restarts >= max_restarts {
+                    return Err((failure, restarts))
+                }
+                restarts += 1;
+            }
+        });
+        self.tasks.push((name.into(), Task::Thread(thread)));
+    }
+
+    /// Runs `pipeline` on a thread of its own, discarding its sink
+    pub fn pipeline<N, R, W>(&mut self, name: N, pipeline: Pipeline<R, W>) where
+        N: Into<String>,
+        R: Read + Send + 'static,
+        W: Write + Send + 'static,
+    {
+        self.spawn(name, move || pipeline.run().map(drop).map_err(From::from))
+    }
+
+    /// Relays everything from `reader` to `writer` like `pump()`, supervising the pump's thread
+    pub fn pump<N, R, W>(&mut self, name: N, reader: R, writer: W) where
+        N: Into<String>,
+        R: Read + Send + 'static,
+        W: Write + Send + 'static,
+    {
+        self.tasks.push((name.into(), Task::Pump(pump(reader, writer))));
+    }
+
+    /// Returns the number of tasks supervised
+    pub fn len(&self) -> usize {
+        self.tasks.len()
+    }
+
+    /// Returns `true` if no tasks are supervised
+    pub fn is_empty(&self) -> bool {
+        self.tasks.is_empty()
+    }
+
+    /// Returns `true` once every task has finished, so that `join()` won't block
+    pub fn is_finished(&self) -> bool {
+        self.tasks.iter().all(|(_, task)| match task {
+            Task::Thread(thread) => thread.is_finished(),
+            Task::Pump(pump) => pump.is_finished(),
+        })
+    }
+
+    /// Waits for every task to finish, failing with all of the tasks that didn't succeed
+    pub fn join(self) -> Result<(), SupervisorError> {
+        let errors: Vec<_> = self.tasks.into_iter().filter_map(|(name, task)| {
+            let outcome = match task {
+                Task::Thread(thread) => thread.join().unwrap_or_else(|payload| Err((TaskFailure::panic(payload), 0))),
+                Task::Pump(pump) => match pump.try_join() {
+                    Ok(report) => report.into_result().map(drop).map_err(|e| (TaskFailure::Error(e), 0)),
+                    Err(payload) => Err((TaskFailure::panic(payload), 0)),
+                },
+            };
+            outcome.err().map(|(failure, restarts)| TaskError { name, failure, restarts })
+        }).collect();
+
+        match errors.is_empty() {
+            true => Ok(()),
+            false => Err(SupervisorError { errors }),
+        }
+    }
+}
+
+impl fmt::Debug for Supervisor {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Supervisor")
+            .field("tasks", &self.tasks.iter().map(|(name, _)| name).collect::<Vec<_>>())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use super::*;
+
+    #[test]
+    fn restart() {
+        let runs = Arc::new(AtomicUsize::new(0));
+        let mut supervisor = Supervisor::new();
+        let runs_ = runs.clone();
+        supervisor.spawn_restartable("flaky", RestartPolicy::OnFailure { max_restarts: 3 }, move || {
+            match runs_.fetch_add(1, Ordering::SeqCst) {
+                0 => panic!("first run"),
+                1 => Err(io::Error::other("second run")),
+                _ => Ok(()),
+            }
+        });
+        supervisor.spawn_restartable("broken", RestartPolicy::OnFailure { max_restarts: 1 }, || {
+            Err(io::Error::new(io::ErrorKind::InvalidData, "always"))
+        });
+        supervisor.spawn("fine", || Ok(()));
+        assert_eq!(supervisor.len(), 3);
+
+        let errors = supervisor.join().unwrap_err().into_errors();
+        assert_eq!(runs.load(Ordering::SeqCst), 3);
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].name, "broken");
+        assert_eq!(errors[0].restarts, 1);
+        assert!(matches!(&errors[0].failure, TaskFailure::Error(e) if e.kind() == io::ErrorKind::InvalidData));
+    }
+
+    #[test]
+    fn supervise_pipeline() {
+        let (r, w) = ::pipe();
+        drop(r);
+        let mut supervisor = Supervisor::new();
+        supervisor.pipeline("copy", Pipeline::new(&b"abc"[..]).then(|r, w| io::copy(r, w).map(drop)).sink(Vec::new()));
+        supervisor.pump("closed", &b"abc"[..], w);
+        let err = supervisor.join().unwrap_err();
+        let names: Vec<_> = err.errors().iter().map(|e| &e.name[..]).collect();
+        assert_eq!(names, ["closed"]);
+        assert_eq!(err.to_string(), "closed failed: pipe reader has been dropped");
+    }
+}
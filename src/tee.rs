@@ -0,0 +1,201 @@
+use crossbeam_channel::{self, Sender};
+use std::io::{self, Read};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::thread::{spawn, JoinHandle};
+use {pipe, Chunk, PipeReader, PipeWriter, DEFAULT_BUF_SIZE};
+
+/// How `tee_n()` treats a reader that falls behind the others
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum TeePolicy {
+    /// Every reader receives every byte, so the source advances at the pace of the slowest one
+    Block,
+    /// Each reader may fall up to this many bytes behind, after which data is dropped for that
+    /// reader alone until it catches up
+    Drop(usize),
+    /// Each reader may fall up to this many bytes behind, after which the source waits for it
+    Buffer(usize),
+}
+
+struct Queue {
+    /// Bytes sent to the relay that it hasn't passed on yet
+    bytes: usize,
+    /// Set once the reader is gone, so that nothing waits for the queue to drain anymore
+    closed: bool,
+}
+
+/// The data queued for a reader that is allowed to fall behind
+struct Backlog {
+    queued: Mutex<Queue>,
+    drained: Condvar,
+}
+
+impl Backlog {
+    fn lock(&self) -> MutexGuard<'_, Queue> {
+        self.queued.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+enum Output {
+    Direct(Box<PipeWriter>),
+    Queued(Sender<Chunk>, Arc<Backlog>),
+}
+
+impl Output {
+    fn new(writer: PipeWriter, policy: TeePolicy) -> Self {
+        if policy == TeePolicy::Block {
+            return Output::Direct(Box::new(writer))
+        }
+
+        let (sender, receiver) = crossbeam_channel::unbounded::<Chunk>();
+        let backlog = Arc::new(Backlog { queued: Mutex::new(Queue { bytes: 0, closed: false }), drained: Condvar::new() });
+        let relay = backlog.clone();
+        spawn(move || {
+            for chunk in receiver {
+                let len = chunk.len();
+                let res = writer.send_chunk(chunk);
+                let mut queued = relay.lock();
+                queued.bytes -= len;
+                if res.is_err() {
+                    // whatever is still queued will never drain
+                    queued.closed = true;
+                    relay.drained.notify_all();
+                    break
+                }
+                relay.drained.notify_one();
+            }
+        });
+        Output::Queued(sender, backlog)
+    }
+
+    /// Passes `data` on to the reader, returning `false` once the reader is gone
+    fn send(&self, data: &[u8], sequence: u64, policy: TeePolicy) -> bool {
+        let chunk = || Chunk::from(data).with_sequence(sequence);
+        match *self {
+            Output::Direct(ref writer) => writer.send_chunk(chunk()).is_ok(),
+            Output::Queued(ref sender, ref backlog) => {
+                let mut queued = backlog.lock();
+                // a write larger than the limit still gets through once the reader has caught up
+                let full = |queued: &Queue| queued.bytes > 0 && queued.bytes + data.len() > policy.limit();
+                match policy {
+                    TeePolicy::Drop(..) if full(&queued) && !queued.closed => return true,
+                    _ => while full(&queued) && !queued.closed {
+                        queued = backlog.drained.wait(queued).unwrap_or_else(|e| e.into_inner());
+                    },
+                }
+                if queued.closed {
+                    return false
+                }
+                queued.bytes += data.len();
+                drop(queued);
+                sender.send(chunk()).is_ok()
+            },
+        }
+    }
+}
+
+impl TeePolicy {
+    fn limit(&self) -> usize {
+        match *self {
+            TeePolicy::Block => 0,
+            TeePolicy::Drop(limit) | TeePolicy::Buffer(limit) => limit,
+        }
+    }
+}
+
+/// Duplicates the contents of `reader` into `n` pipes
+///
+/// The source is read on a thread of its own, and `policy` decides how much a slow reader may hold
+/// back the others. Readers that are dropped stop receiving data, and the source stops being read
+/// once they are all gone. The returned thread finishes once the source reaches EOF, or fails to
+/// read, and returns the number of bytes read from it.
+///
+/// Every read from the source is numbered, so that a reader can find out how much was dropped for
+/// it with `PipeReader::skipped()`, as of the last chunk it received.
+///
+/// # Example
+///
+/// ```
+/// use std::io::Read;
+/// use pipe::TeePolicy;
+///
+/// let (readers, source) = pipe::tee_n(&b"hello"[..], 2, TeePolicy::Buffer(1024));
+/// for mut reader in readers {
+///     let mut data = String::new();
+///     reader.read_to_string(&mut data).unwrap();
+///     assert_eq!(data, "hello");
+/// }
+/// assert_eq!(source.join().unwrap().unwrap(), 5);
+/// ```
+pub fn tee_n<R: Read + Send + 'static>(mut reader: R, n: usize, policy: TeePolicy) -> (Vec<PipeReader>, JoinHandle<io::Result<u64>>) {
+    let (readers, writers): (Vec<_>, Vec<_>) = (0..n).map(|_| pipe()).unzip();
+    let thread = spawn(move || {
+        let mut outputs: Vec<_> = writers.into_iter().map(|writer| Output::new(writer, policy)).collect();
+        let mut buf = vec![0; DEFAULT_BUF_SIZE];
+        let mut total = 0;
+        let mut sequence = 0;
+        while !outputs.is_empty() {
+            let len = match reader.read(&mut buf) {
+                Ok(0) => break,
+                Ok(len) => len,
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            };
+            total += len as u64;
+            sequence += 1;
+            outputs.retain(|output| output.send(&buf[..len], sequence, policy));
+        }
+        Ok(total)
+    });
+    (readers, thread)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{self, Read};
+    use std::thread::sleep;
+    use std::time::Duration;
+    use super::*;
+
+    #[test]
+    fn tee_block() {
+        let (mut readers, source) = tee_n(io::repeat(1).take(100_000), 3, TeePolicy::Block);
+        drop(readers.pop());
+        let threads: Vec<_> = readers.into_iter().map(|mut reader| spawn(move || {
+            let mut data = Vec::new();
+            reader.read_to_end(&mut data).unwrap();
+            assert_eq!(data, vec![1; 100_000]);
+        })).collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+        assert_eq!(source.join().unwrap().unwrap(), 100_000);
+    }
+
+    #[test]
+    fn tee_buffer_dropped_reader() {
+        // the one byte the dropped reader's relay is stuck on frees too little room for what follows
+        let source = (&[1][..]).chain(io::repeat(1).take(100_000));
+        let (mut readers, source) = tee_n(source, 2, TeePolicy::Buffer(DEFAULT_BUF_SIZE + 1));
+        let dropped = readers.pop();
+        // let the source fill up both backlogs
+        sleep(Duration::from_millis(50));
+        drop(dropped);
+
+        let mut data = Vec::new();
+        readers[0].read_to_end(&mut data).unwrap();
+        assert_eq!(data.len(), 100_001);
+        assert_eq!(source.join().unwrap().unwrap(), 100_001);
+    }
+
+    #[test]
+    fn tee_drop() {
+        let (mut readers, source) = tee_n(io::repeat(1).take(100_000), 1, TeePolicy::Drop(0));
+        assert_eq!(source.join().unwrap().unwrap(), 100_000);
+
+        // only the first read was queued, and the rest was dropped while nobody read it
+        let mut data = Vec::new();
+        readers[0].read_to_end(&mut data).unwrap();
+        assert_eq!(data.len(), DEFAULT_BUF_SIZE);
+        assert_eq!(readers[0].sequence(), 1);
+    }
+}
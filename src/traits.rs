@@ -0,0 +1,329 @@
+use std::any::Any;
+use std::io::{self, BufRead, Read, Write};
+use {Chunk, Chunks, PipeBufWriter, PipeReader, PipeWriter};
+use {HistoryPipeReader, HistoryPipeWriter, LocalPipeReader, LocalPipeWriter, PriorityPipeReader, PriorityPipeWriter};
+use {SpooledPipeReader, SpooledPipeWriter, WatchPipeReader, WatchPipeWriter};
+
+/// The sending end of any of the pipes in this crate, as seen by code that is generic over them
+///
+/// Adapters such as codecs or recorders can be written once against this trait rather than for
+/// each kind of pipe. Pipes that don't carry chunk metadata (see `Chunk::with_flags()`) send just
+/// the data of the chunk.
+pub trait ChunkSender {
+    /// Sends `chunk` to the reader, blocking until there is room for it if the pipe has any
+    /// backpressure
+    fn send_chunk(&mut self, chunk: Chunk) -> io::Result<()>;
+}
+
+/// The receiving end of any of the pipes in this crate, as seen by code that is generic over them
+/// (see `ChunkSender`)
+///
+/// Pipes that queue plain data hand out whatever the reader has buffered as a chunk of its own,
+/// which is the rest of a write that was only partly read.
+pub trait ChunkReceiver {
+    /// Blocks until the next chunk is received, or returns `None` at EOF
+    fn recv_chunk(&mut self) -> io::Result<Option<Chunk>>;
+}
+
+impl<S: ChunkSender + ?Sized> ChunkSender for &mut S {
+    fn send_chunk(&mut self, chunk: Chunk) -> io::Result<()> {
+        (**self).send_chunk(chunk)
+    }
+}
+
+impl<R: ChunkReceiver + ?Sized> ChunkReceiver for &mut R {
+    fn recv_chunk(&mut self) -> io::Result<Option<Chunk>> {
+        (**self).recv_chunk()
+    }
+}
+
+impl ChunkSender for PipeWriter {
+    fn send_chunk(&mut self, chunk: Chunk) -> io::Result<()> {
+        PipeWriter::send_chunk(self, chunk).map_err(|e| self.io_error(e))
+    }
+}
+
+/// Any buffered data is flushed before the chunk is sent.
+impl ChunkSender for PipeBufWriter {
+    fn send_chunk(&mut self, chunk: Chunk) -> io::Result<()> {
+        self.send_chunk_after(chunk)
+    }
+}
+
+/// Chunks are sent at the writer's current priority.
+impl ChunkSender for PriorityPipeWriter {
+    fn send_chunk(&mut self, chunk: Chunk) -> io::Result<()> {
+        self.send(chunk.into_vec()).map_err(From::from)
+    }
+}
+
+impl ChunkSender for SpooledPipeWriter {
+    fn send_chunk(&mut self, chunk: Chunk) -> io::Result<()> {
+        self.send(chunk.into_vec())
+    }
+}
+
+/// The chunk's data is written like with `write_all()`, so without a yield hook, a chunk that
+/// doesn't fit fails with `io::ErrorKind::WouldBlock` once part of it has been queued.
+impl ChunkSender for LocalPipeWriter {
+    fn send_chunk(&mut self, chunk: Chunk) -> io::Result<()> {
+        self.write_all(&chunk)
+    }
+}
+
+/// The chunk replaces the one waiting for the reader.
+impl ChunkSender for WatchPipeWriter {
+    fn send_chunk(&mut self, chunk: Chunk) -> io::Result<()> {
+        self.send(chunk.into_vec()).map(drop).map_err(From::from)
+    }
+}
+
+/// The chunk's data is added to the history, never blocking.
+impl ChunkSender for HistoryPipeWriter {
+    fn send_chunk(&mut self, chunk: Chunk) -> io::Result<()> {
+        self.write_all(&chunk)
+    }
+}
+
+impl ChunkReceiver for PipeReader {
+    fn recv_chunk(&mut self) -> io::Result<Option<Chunk>> {
+        PipeReader::recv_chunk(self)
+    }
+}
+
+impl ChunkReceiver for Chunks<'_> {
+    fn recv_chunk(&mut self) -> io::Result<Option<Chunk>> {
+        self.recv()
+    }
+}
+
+/// Hands out the data a `BufRead` pipe end has buffered, filling it first if it is empty
+fn recv_buffered<R: BufRead>(reader: &mut R) -> io::Result<Option<Chunk>> {
+    let chunk = Chunk::from(reader.fill_buf()?);
+    reader.consume(chunk.len());
+    Ok(Some(chunk).filter(|chunk| !chunk.is_empty()))
+}
+
+/// Data consumed this way is retained for `checkpoint()` like any other.
+impl ChunkReceiver for SpooledPipeReader {
+    fn recv_chunk(&mut self) -> io::Result<Option<Chunk>> {
+        recv_buffered(self)
+    }
+}
+
+impl ChunkReceiver for PriorityPipeReader {
+    fn recv_chunk(&mut self) -> io::Result<Option<Chunk>> {
+        recv_buffered(self)
+    }
+}
+
+/// Like reads, this fails with `io::ErrorKind::WouldBlock` rather than blocking when the pipe is
+/// empty and there is no yield hook.
+impl ChunkReceiver for LocalPipeReader {
+    fn recv_chunk(&mut self) -> io::Result<Option<Chunk>> {
+        recv_buffered(self)
+    }
+}
+
+impl ChunkReceiver for HistoryPipeReader {
+    fn recv_chunk(&mut self) -> io::Result<Option<Chunk>> {
+        recv_buffered(self)
+    }
+}
+
+/// Receives the latest chunk like `WatchPipeReader::recv()`, discarding any data still buffered
+/// for `Read`.
+impl ChunkReceiver for WatchPipeReader {
+    fn recv_chunk(&mut self) -> io::Result<Option<Chunk>> {
+        Ok(self.recv().map(|(_, data)| Chunk::from(data)))
+    }
+}
+
+/// A `Read` trait object that can still be downcast to the reader behind it
+///
+/// This is implemented by every reader, so a `Box<dyn AnyRead + Send>` can be used wherever a
+/// `Box<dyn Read + Send>` would be, while generic code can still take advantage of a
+/// `PipeReader`'s own API when it happens to get one (see `as_pipe_reader()`).
+pub trait AnyRead: Read + Any {
+    /// Returns the reader as `Any`, for downcasting
+    fn as_any(&self) -> &dyn Any;
+
+    /// Returns the reader as `Any`, for downcasting
+    fn as_any_mut(&mut self) -> &mut dyn Any;
+
+    /// Converts the boxed reader into a boxed `Any`, for downcasting
+    fn into_any(self: Box<Self>) -> Box<dyn Any>;
+}
+
+impl<R: Read + Any> AnyRead for R {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+
+    fn into_any(self: Box<Self>) -> Box<dyn Any> {
+        self
+    }
+}
+
+/// A `Write` trait object that can still be downcast to the writer behind it (see `AnyRead`)
+pub trait AnyWrite: Write + Any {
+    /// Returns the writer as `Any`, for downcasting
+    fn as_any(&self) -> &dyn Any;
+
+    /// Returns the writer as `Any`, for downcasting
+    fn as_any_mut(&mut self) -> &mut dyn Any;
+
+    /// Converts the boxed writer into a boxed `Any`, for downcasting
+    fn into_any(self: Box<Self>) -> Box<dyn Any>;
+}
+
+impl<W: Write + Any> AnyWrite for W {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+
+    fn into_any(self: Box<Self>) -> Box<dyn Any> {
+        self
+    }
+}
+
+/// Returns the `PipeReader` behind a reader trait object, if that's what it is
+///
+/// # Example
+///
+/// ```
+/// use std::io::Read;
+/// use pipe::AnyRead;
+///
+/// fn read_all(reader: &mut dyn AnyRead) -> Vec<u8> {
+///     let mut data = Vec::new();
+///     match pipe::as_pipe_reader(reader) {
+///         Some(reader) => while let Some(chunk) = reader.recv_chunk().unwrap() {
+///             data.extend_from_slice(&chunk);
+///         },
+///         None => drop(reader.read_to_end(&mut data).unwrap()),
+///     }
+///     data
+/// }
+///
+/// let (mut reader, writer) = pipe::pipe();
+/// let thread = std::thread::spawn(move || writer.send(&b"chunk"[..]));
+/// assert_eq!(read_all(&mut reader), b"chunk");
+/// assert_eq!(read_all(&mut &b"slice"[..]), b"slice");
+/// # thread.join().unwrap().unwrap();
+/// ```
+pub fn as_pipe_reader(reader: &mut dyn AnyRead) -> Option<&mut PipeReader> {
+    reader.as_any_mut().downcast_mut()
+}
+
+/// Returns the `PipeWriter` behind a writer trait object, if that's what it is
+pub fn as_pipe_writer(writer: &mut dyn AnyWrite) -> Option<&mut PipeWriter> {
+    writer.as_any_mut().downcast_mut()
+}
+
+/// Returns the `PipeBufWriter` behind a writer trait object, if that's what it is
+pub fn as_pipe_buf_writer(writer: &mut dyn AnyWrite) -> Option<&mut PipeBufWriter> {
+    writer.as_any_mut().downcast_mut()
+}
+
+impl PipeReader {
+    /// Unboxes a reader trait object that holds a `PipeReader`, or returns it as it was
+    pub fn from_boxed(reader: Box<dyn AnyRead + Send>) -> Result<PipeReader, Box<dyn AnyRead + Send>> {
+        // the box is itself a `AnyRead`, so look past it
+        match (*reader).as_any().is::<PipeReader>() {
+            true => Ok(*reader.into_any().downcast().unwrap_or_else(|_| unreachable!())),
+            false => Err(reader),
+        }
+    }
+}
+
+impl PipeWriter {
+    /// Unboxes a writer trait object that holds a `PipeWriter`, or returns it as it was
+    pub fn from_boxed(writer: Box<dyn AnyWrite + Send>) -> Result<PipeWriter, Box<dyn AnyWrite + Send>> {
+        // the box is itself a `AnyWrite`, so look past it
+        match (*writer).as_any().is::<PipeWriter>() {
+            true => Ok(*writer.into_any().downcast().unwrap_or_else(|_| unreachable!())),
+            false => Err(writer),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+    use std::thread::spawn;
+    use super::*;
+    use {pipe, pipe_local, pipe_priority, pipe_watch, pipe_with_history};
+
+    fn forward<R: ChunkReceiver, S: ChunkSender>(mut receiver: R, mut sender: S) -> io::Result<usize> {
+        let mut count = 0;
+        while let Some(chunk) = receiver.recv_chunk()? {
+            sender.send_chunk(chunk)?;
+            count += 1;
+        }
+        Ok(count)
+    }
+
+    #[test]
+    fn generic_forward() {
+        let (r, w) = pipe();
+        let (mut out, priority) = pipe_priority(1);
+        let guard = spawn(move || forward(r, priority));
+        w.send(&b"hello "[..]).unwrap();
+        w.send(&b"world"[..]).unwrap();
+        drop(w);
+
+        assert_eq!(guard.join().unwrap().unwrap(), 2);
+        let mut data = String::new();
+        out.read_to_string(&mut data).unwrap();
+        assert_eq!(data, "hello world");
+    }
+
+    #[test]
+    fn generic_local() {
+        let (r, mut w) = pipe_local(64);
+        let (mut out, history) = pipe_with_history(64);
+        ChunkSender::send_chunk(&mut w, Chunk::from(&b"hello "[..])).unwrap();
+        ChunkSender::send_chunk(&mut w, Chunk::from(&b"world"[..])).unwrap();
+        drop(w);
+
+        assert_eq!(forward(r, history).unwrap(), 2);
+        let mut data = String::new();
+        out.read_to_string(&mut data).unwrap();
+        assert_eq!(data, "hello world");
+    }
+
+    #[test]
+    fn generic_watch() {
+        let (mut r, mut w) = pipe_watch();
+        ChunkSender::send_chunk(&mut w, Chunk::from(&b"old"[..])).unwrap();
+        ChunkSender::send_chunk(&mut w, Chunk::from(&b"new"[..])).unwrap();
+        drop(w);
+
+        assert_eq!(&ChunkReceiver::recv_chunk(&mut r).unwrap().unwrap()[..], b"new");
+        assert!(ChunkReceiver::recv_chunk(&mut r).unwrap().is_none());
+    }
+
+    #[test]
+    fn downcast() {
+        let (r, w) = pipe();
+        let mut boxed: Box<dyn AnyWrite + Send> = Box::new(w);
+        assert!(as_pipe_writer(&mut *boxed).is_some());
+        assert!(as_pipe_buf_writer(&mut *boxed).is_none());
+        let w = PipeWriter::from_boxed(boxed).ok().unwrap();
+
+        let other: Box<dyn AnyRead + Send> = Box::new(io::empty());
+        assert!(PipeReader::from_boxed(other).is_err());
+        let mut r = PipeReader::from_boxed(Box::new(r)).ok().unwrap();
+        drop(w);
+        assert_eq!(r.read(&mut [0; 4]).unwrap(), 0);
+    }
+}
@@ -0,0 +1,49 @@
+use crossbeam_channel::{Receiver, RecvTimeoutError, TryRecvError};
+use std::hint::spin_loop;
+use std::time::Instant;
+
+/// How a reader waits for data (see `PipeReader::set_wait_strategy()`)
+///
+/// Writers always park while waiting for a reader to pick up their chunk, so only one side of a
+/// pipe ever busy-waits.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum WaitStrategy {
+    /// Park the thread until data arrives
+    #[default]
+    Park,
+    /// Busy-wait for up to the given number of polls, then park
+    SpinThenPark(u32),
+    /// Busy-wait until data arrives, trading CPU time for the lowest handoff latency
+    Spin,
+}
+
+impl WaitStrategy {
+    /// Waits for the next message, giving up at `deadline` if there is one
+    pub(crate) fn recv<T>(self, receiver: &Receiver<T>, deadline: Option<Instant>) -> Result<T, RecvTimeoutError> {
+        let park = || match deadline {
+            Some(deadline) => receiver.recv_deadline(deadline),
+            None => receiver.recv().map_err(|_| RecvTimeoutError::Disconnected),
+        };
+        let spins = match self {
+            WaitStrategy::Park => return park(),
+            WaitStrategy::SpinThenPark(spins) => Some(spins),
+            WaitStrategy::Spin => None,
+        };
+
+        let mut polls = 0;
+        loop {
+            match receiver.try_recv() {
+                Ok(data) => return Ok(data),
+                Err(TryRecvError::Disconnected) => return Err(RecvTimeoutError::Disconnected),
+                Err(TryRecvError::Empty) => (),
+            }
+
+            match spins {
+                Some(spins) if polls >= spins => return park(),
+                _ if deadline.is_some_and(|deadline| Instant::now() >= deadline) => return Err(RecvTimeoutError::Timeout),
+                _ => spin_loop(),
+            }
+            polls += 1;
+        }
+    }
+}
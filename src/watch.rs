@@ -0,0 +1,247 @@
+use std::cmp::min;
+use std::io::{self, BufRead, Read, Write};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use {check_consume, PipeError};
+
+struct State {
+    latest: Option<Vec<u8>>,
+    sequence: u64,
+    writers: usize,
+    reader: bool,
+}
+
+struct Shared {
+    state: Mutex<State>,
+    changed: Condvar,
+}
+
+impl Shared {
+    fn lock(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// The `Read` end of a watch pipe (see `pipe_watch()`)
+pub struct WatchPipeReader {
+    shared: Arc<Shared>,
+    buffer: Vec<u8>,
+    position: usize,
+    sequence: u64,
+    skipped: u64,
+}
+
+/// The `Write` end of a watch pipe (see `pipe_watch()`)
+pub struct WatchPipeWriter {
+    shared: Arc<Shared>,
+}
+
+/// Creates a memory pipe that only keeps the most recently written chunk
+///
+/// Every write replaces any chunk the reader hasn't picked up yet, so writers never block and the
+/// reader always receives the latest data. Chunks are numbered in the order they were written,
+/// starting at `1`, which lets the reader tell how many were skipped (see
+/// `WatchPipeReader::sequence()`).
+pub fn pipe_watch() -> (WatchPipeReader, WatchPipeWriter) {
+    let shared = Arc::new(Shared {
+        state: Mutex::new(State {
+            latest: None,
+            sequence: 0,
+            writers: 1,
+            reader: true,
+        }),
+        changed: Condvar::new(),
+    });
+
+    (
+        WatchPipeReader { shared: shared.clone(), buffer: Vec::new(), position: 0, sequence: 0, skipped: 0 },
+        WatchPipeWriter { shared },
+    )
+}
+
+impl WatchPipeReader {
+    /// Blocks until a chunk newer than the last one received is available, and returns it along
+    /// with its sequence number
+    ///
+    /// Returns `None` once all writers have been dropped and the latest chunk has been received.
+    /// Any data still buffered for `Read` is discarded.
+    pub fn recv(&mut self) -> Option<(u64, Vec<u8>)> {
+        let mut state = self.shared.lock();
+        loop {
+            if let Some(data) = state.latest.take() {
+                let sequence = state.sequence;
+                drop(state);
+                return Some(self.received(sequence, data))
+            }
+            if state.writers == 0 {
+                return None
+            }
+            state = self.shared.changed.wait(state).unwrap_or_else(|e| e.into_inner());
+        }
+    }
+
+    /// Returns the latest chunk if one has been written since the last one received, without
+    /// blocking
+    pub fn try_recv(&mut self) -> Option<(u64, Vec<u8>)> {
+        let mut state = self.shared.lock();
+        let sequence = state.sequence;
+        let data = state.latest.take();
+        drop(state);
+        data.map(|data| self.received(sequence, data))
+    }
+
+    /// Returns the sequence number of the last chunk received, or `0` if there hasn't been one
+    pub fn sequence(&self) -> u64 {
+        self.sequence
+    }
+
+    /// Returns the number of chunks that were replaced before the reader received them
+    pub fn skipped(&self) -> u64 {
+        self.skipped
+    }
+
+    /// Returns a reference to the internally buffered data.
+    pub fn buffer(&self) -> &[u8] {
+        &self.buffer[self.position..]
+    }
+
+    fn received(&mut self, sequence: u64, data: Vec<u8>) -> (u64, Vec<u8>) {
+        self.skipped += sequence - self.sequence - 1;
+        self.sequence = sequence;
+        self.buffer.clear();
+        self.position = 0;
+        (sequence, data)
+    }
+}
+
+impl BufRead for WatchPipeReader {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        while self.position >= self.buffer.len() {
+            match self.recv() {
+                Some((_, data)) => self.buffer = data,
+                None => break,
+            }
+        }
+
+        Ok(&self.buffer[self.position..])
+    }
+
+    fn consume(&mut self, amt: usize) {
+        check_consume(amt, self.buffer.len() - self.position);
+        self.position += amt
+    }
+}
+
+impl Read for WatchPipeReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+
+        let internal = self.fill_buf()?;
+
+        let len = min(buf.len(), internal.len());
+        if len > 0 {
+            buf[..len].copy_from_slice(&internal[..len]);
+            self.consume(len);
+        }
+        Ok(len)
+    }
+}
+
+impl Drop for WatchPipeReader {
+    fn drop(&mut self) {
+        let mut state = self.shared.lock();
+        state.reader = false;
+        state.latest = None;
+    }
+}
+
+impl WatchPipeWriter {
+    /// Replaces the chunk waiting for the associated `WatchPipeReader`, and returns the sequence
+    /// number of the new one
+    pub fn send<B: Into<Vec<u8>>>(&self, bytes: B) -> Result<u64, PipeError> {
+        let mut state = self.shared.lock();
+        if !state.reader {
+            return Err(PipeError::Disconnected)
+        }
+
+        state.latest = Some(bytes.into());
+        state.sequence += 1;
+        self.shared.changed.notify_all();
+        Ok(state.sequence)
+    }
+}
+
+impl Clone for WatchPipeWriter {
+    fn clone(&self) -> Self {
+        self.shared.lock().writers += 1;
+        WatchPipeWriter { shared: self.shared.clone() }
+    }
+}
+
+/// Each write is sent as a chunk of its own, replacing the previous one.
+impl Write for WatchPipeWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.send(buf)
+            .map(|_| buf.len())
+            .map_err(From::from)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Drop for WatchPipeWriter {
+    fn drop(&mut self) {
+        let mut state = self.shared.lock();
+        state.writers -= 1;
+        if state.writers == 0 {
+            self.shared.changed.notify_all();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+    use std::thread::spawn;
+    use super::*;
+
+    #[test]
+    fn watch_latest() {
+        let (mut r, w) = pipe_watch();
+        assert_eq!(r.try_recv(), None);
+        w.send(&b"one"[..]).unwrap();
+        w.send(&b"two"[..]).unwrap();
+        assert_eq!(w.send(&b"three"[..]).unwrap(), 3);
+        assert_eq!(r.recv(), Some((3, b"three".to_vec())));
+        assert_eq!(r.skipped(), 2);
+        assert_eq!(r.try_recv(), None);
+
+        let guard = spawn(move || w.send(&b"four"[..]).unwrap());
+        assert_eq!(r.recv(), Some((4, b"four".to_vec())));
+        guard.join().unwrap();
+        assert_eq!(r.recv(), None);
+        assert_eq!(r.sequence(), 4);
+    }
+
+    #[test]
+    fn watch_read() {
+        let (mut r, mut w) = pipe_watch();
+        w.write_all(b"stale").unwrap();
+        w.write_all(b"fresh").unwrap();
+        drop(w);
+
+        let mut o = String::new();
+        r.read_to_string(&mut o).unwrap();
+        assert_eq!(o, "fresh");
+    }
+
+    #[test]
+    fn watch_writer_fail() {
+        let (r, w) = pipe_watch();
+        drop(r);
+        assert_eq!(w.send(&b"hi"[..]), Err(PipeError::Disconnected));
+    }
+}
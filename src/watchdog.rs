@@ -0,0 +1,211 @@
+use crossbeam_channel::{self, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread::{spawn, JoinHandle};
+use std::time::Duration;
+use clock::ClockHandle;
+use state::Shared;
+use {Clock, PipeControl, PipeEvent};
+
+/// Which side of a pipe is holding it up (see `Watchdog`)
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Stall {
+    /// Writers are blocked on a reader that isn't reading
+    ReaderSlow,
+    /// The reader is waiting, but no writer is sending anything
+    WriterIdle,
+    /// Writers and the reader are both waiting, yet nothing is handed over, such as while the
+    /// pipe is paused
+    BothBlocked,
+}
+
+/// When a `Watchdog` considers a pipe stalled
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct StallThresholds {
+    /// How long the pipe has to go without progress in the same state to count as stalled
+    pub window: Duration,
+    /// How often the pipe is sampled
+    pub interval: Duration,
+}
+
+impl Default for StallThresholds {
+    fn default() -> Self {
+        StallThresholds { window: Duration::from_secs(1), interval: Duration::from_millis(100) }
+    }
+}
+
+/// Watches a pipe from a thread of its own, classifying stalls as they happen
+///
+/// The pipe is sampled every `StallThresholds::interval`. Once no chunk has been received for a
+/// whole `StallThresholds::window`, with the pipe in the same state throughout, the watchdog
+/// emits `PipeEvent::Stalled` to the pipe's event subscribers, telling which side of a wedged
+/// pipeline to look at. The stall lasts until the pipe makes progress again.
+///
+/// # Example
+///
+/// ```
+/// use std::time::Duration;
+/// use pipe::{PipeEvent, Stall, StallThresholds, Watchdog};
+///
+/// let (reader, writer) = pipe::pipe();
+/// let events = writer.events();
+/// let thresholds = StallThresholds { window: Duration::from_millis(20), interval: Duration::from_millis(5) };
+/// let watchdog = Watchdog::monitor(&writer.control(), thresholds);
+/// let thread = std::thread::spawn(move || writer.send(&b"stuck"[..]));
+///
+/// assert_eq!(events.recv(), Some(PipeEvent::Stalled(Stall::ReaderSlow)));
+/// assert_eq!(watchdog.stall(), Some(Stall::ReaderSlow));
+/// drop(reader);
+/// # let _ = thread.join();
+/// ```
+pub struct Watchdog {
+    stall: Arc<Mutex<Option<Stall>>>,
+    stop: Option<Sender<()>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Watchdog {
+    /// Starts watching the pipe controlled by `pipe` (see `PipeReader::control()` or
+    /// `PipeWriter::control()`)
+    pub fn monitor(pipe: &PipeControl, thresholds: StallThresholds) -> Self {
+        Self::monitor_with_clock_handle(pipe, thresholds, ClockHandle::default())
+    }
+
+    /// Starts watching the pipe like `monitor()`, measuring the stall window by `clock`
+    ///
+    /// The pipe is still sampled every `StallThresholds::interval` of real time.
+    pub fn monitor_with_clock<C: Clock + 'static>(pipe: &PipeControl, thresholds: StallThresholds, clock: C) -> Self {
+        Self::monitor_with_clock_handle(pipe, thresholds, ClockHandle::new(clock))
+    }
+
+    fn monitor_with_clock_handle(pipe: &PipeControl, thresholds: StallThresholds, clock: ClockHandle) -> Self {
+        let shared = pipe.shared().clone();
+        let stall = Arc::new(Mutex::new(None));
+        let (stop, stopped) = crossbeam_channel::bounded::<()>(0);
+        let thread = {
+            let stall = stall.clone();
+            spawn(move || {
+                let mut delivered = shared.delivered();
+                let mut candidate = None;
+                let mut since = clock.now();
+                while let Err(RecvTimeoutError::Timeout) = stopped.recv_timeout(thresholds.interval) {
+                    let now = clock.now();
+                    let progress = shared.delivered();
+                    let state = match progress == delivered {
+                        true => classify(&shared),
+                        false => None,
+                    };
+                    delivered = progress;
+                    if state != candidate {
+                        candidate = state;
+                        since = now;
+                    }
+
+                    let mut current = stall.lock().unwrap_or_else(|e| e.into_inner());
+                    let stalled = candidate.filter(|_| now.saturating_duration_since(since) >= thresholds.window);
+                    if stalled != *current {
+                        *current = stalled;
+                        if let Some(stalled) = stalled {
+                            shared.emit(PipeEvent::Stalled(stalled));
+                        }
+                    }
+                }
+            })
+        };
+
+        Watchdog { stall, stop: Some(stop), thread: Some(thread) }
+    }
+
+    /// Returns the stall the pipe is in, if any
+    pub fn stall(&self) -> Option<Stall> {
+        *self.stall.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// Classifies the state of a pipe that hasn't made progress since it was last sampled
+fn classify(shared: &Shared) -> Option<Stall> {
+    let pressure = shared.pressure();
+    match (pressure.queued_bytes > 0, pressure.is_reader_waiting) {
+        (true, _) if shared.gate().is_paused() => Some(Stall::BothBlocked),
+        (true, true) => Some(Stall::BothBlocked),
+        (true, false) => Some(Stall::ReaderSlow),
+        (false, true) => Some(Stall::WriterIdle),
+        (false, false) => None,
+    }
+}
+
+/// Stops the watchdog's thread.
+impl Drop for Watchdog {
+    fn drop(&mut self) {
+        drop(self.stop.take());
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+    use std::thread::spawn;
+    use super::*;
+    use pipe;
+
+    fn thresholds() -> StallThresholds {
+        StallThresholds { window: Duration::from_millis(20), interval: Duration::from_millis(5) }
+    }
+
+    #[test]
+    fn writer_idle() {
+        let (mut r, w) = pipe();
+        let events = r.events();
+        let watchdog = Watchdog::monitor(&r.control(), thresholds());
+        let guard = spawn(move || r.read(&mut [0; 4]).unwrap());
+        assert_eq!(events.recv(), Some(PipeEvent::Stalled(Stall::WriterIdle)));
+
+        w.send(&b"data"[..]).unwrap();
+        assert_eq!(guard.join().unwrap(), 4);
+        drop(watchdog);
+    }
+
+    #[test]
+    fn both_blocked() {
+        let (mut r, w) = pipe();
+        let events = r.events();
+        let control = r.control();
+        let watchdog = Watchdog::monitor(&control, thresholds());
+        control.pause();
+        let guard = spawn(move || w.send(&b"data"[..]).unwrap());
+        assert_eq!(events.recv(), Some(PipeEvent::Stalled(Stall::BothBlocked)));
+        assert_eq!(watchdog.stall(), Some(Stall::BothBlocked));
+
+        control.resume();
+        r.read_exact(&mut [0; 4]).unwrap();
+        guard.join().unwrap();
+    }
+
+    #[test]
+    fn manual_clock() {
+        use std::thread::sleep;
+        use ManualClock;
+
+        let (mut r, w) = pipe();
+        let events = r.events();
+        let clock = ManualClock::new();
+        let thresholds = StallThresholds { window: Duration::from_secs(3600), interval: Duration::from_millis(1) };
+        let watchdog = Watchdog::monitor_with_clock(&r.control(), thresholds, clock.clone());
+        let probe = w.clone();
+        let guard = spawn(move || w.send(&b"data"[..]).unwrap());
+        while probe.pressure().queued_bytes == 0 {
+            sleep(Duration::from_millis(1));
+        }
+        sleep(Duration::from_millis(20));
+        assert_eq!(watchdog.stall(), None);
+
+        clock.advance(thresholds.window);
+        assert_eq!(events.recv(), Some(PipeEvent::Stalled(Stall::ReaderSlow)));
+        assert_eq!(watchdog.stall(), Some(Stall::ReaderSlow));
+
+        r.read_exact(&mut [0; 4]).unwrap();
+        guard.join().unwrap();
+    }
+}